@@ -0,0 +1,196 @@
+use crate::Transaction;
+use crate::mempool::Mempool;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+
+// Weight Bitcoin Core sets aside for the block header and coinbase transaction
+pub const COINBASE_RESERVED_WEIGHT: usize = 4000;
+
+// Transactions chosen for the next block, in the order they must appear after the
+// coinbase, with the fees they pay and the weight they take up
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockTemplate {
+    pub transactions: Vec<Transaction>,
+    pub fees: u64,
+    pub weight: usize,
+}
+
+// Fills a block from the mempool the way Bitcoin Core does: a transaction is judged by
+// the fee rate of its package, itself plus any unconfirmed ancestors it needs, so a
+// high-fee child can pay for a low-fee parent
+#[derive(Debug, Clone, Copy)]
+pub struct BlockAssembler {
+    // Block weight limit, including COINBASE_RESERVED_WEIGHT
+    pub max_weight: usize,
+}
+
+// What including an entry would add: its fee and weight plus those of its ancestors
+// not yet in the block
+#[derive(Debug, Clone, Copy)]
+struct Package {
+    fee: u64,
+    weight: usize,
+}
+
+impl Package {
+    // Highest fee rate last, earliest arrival first among equals
+    fn key(&self, index: usize) -> (u128, Reverse<usize>) {
+        let rate = ((self.fee as u128) << 64) / self.weight.max(1) as u128;
+        (rate, Reverse(index))
+    }
+}
+
+impl BlockAssembler {
+    pub fn new(max_weight: usize) -> Self {
+        Self { max_weight }
+    }
+
+    pub fn assemble(&self, mempool: &Mempool) -> BlockTemplate {
+        // Arrival order puts every entry after its in-mempool parents
+        let entries = mempool.entries();
+        let index: HashMap<[u8; 32], usize> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.txid, i))
+            .collect();
+
+        let mut ancestors: Vec<BTreeSet<usize>> = Vec::with_capacity(entries.len());
+        let mut descendants = vec![Vec::new(); entries.len()];
+        for (i, entry) in entries.iter().enumerate() {
+            let mut own = BTreeSet::new();
+            for input in &entry.transaction.inputs {
+                if let Some(&parent) = index.get(&input.previous_output.txid) {
+                    own.insert(parent);
+                    own.extend(&ancestors[parent]);
+                }
+            }
+            for &ancestor in &own {
+                descendants[ancestor].push(i);
+            }
+            ancestors.push(own);
+        }
+
+        let mut packages: Vec<Package> = (0..entries.len())
+            .map(|i| {
+                let package = std::iter::once(i).chain(ancestors[i].iter().copied());
+                Package {
+                    fee: package.clone().map(|j| entries[j].fee).sum(),
+                    weight: package.map(|j| entries[j].weight()).sum(),
+                }
+            })
+            .collect();
+        let mut candidates: BTreeSet<_> = packages
+            .iter()
+            .enumerate()
+            .map(|(i, package)| package.key(i))
+            .collect();
+
+        let mut template = BlockTemplate {
+            weight: COINBASE_RESERVED_WEIGHT,
+            ..BlockTemplate::default()
+        };
+        let mut included = vec![false; entries.len()];
+        while let Some((_, Reverse(best))) = candidates.pop_last() {
+            // Left out for now, but it may fit once an ancestor is in and its package
+            // shrinks
+            if template.weight + packages[best].weight > self.max_weight {
+                continue;
+            }
+
+            let package: Vec<usize> = ancestors[best]
+                .iter()
+                .copied()
+                .filter(|&j| !included[j])
+                .chain(std::iter::once(best))
+                .collect();
+            for j in package {
+                included[j] = true;
+                candidates.remove(&packages[j].key(j));
+                let entry = entries[j];
+                template.transactions.push(entry.transaction.clone());
+                template.fees += entry.fee;
+                template.weight += entry.weight();
+
+                for &d in descendants[j].iter().filter(|&&d| !included[d]) {
+                    let was_candidate = candidates.remove(&packages[d].key(d));
+                    packages[d].fee -= entry.fee;
+                    packages[d].weight -= entry.weight();
+                    if was_candidate || packages[d].weight + template.weight <= self.max_weight {
+                        candidates.insert(packages[d].key(d));
+                    }
+                }
+            }
+        }
+
+        template
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OutPoint, TxIn, TxOut};
+
+    fn spend(outpoint: OutPoint) -> Transaction {
+        Transaction::new(vec![TxIn::new(outpoint)], vec![TxOut::to_address(1, "bob")])
+    }
+
+    fn child_of(parent: &Transaction) -> Transaction {
+        spend(OutPoint::new(parent.txid(), 0))
+    }
+
+    fn txids(transactions: &[Transaction]) -> Vec<[u8; 32]> {
+        transactions.iter().map(Transaction::txid).collect()
+    }
+
+    #[test]
+    fn test_child_pays_for_parent() {
+        let parent = spend(OutPoint::new([1; 32], 0));
+        let child = child_of(&parent);
+        let other = spend(OutPoint::new([2; 32], 0));
+        let mut mempool = Mempool::default();
+        mempool.add(parent.clone(), 0, 0).unwrap();
+        mempool.add(other.clone(), 1000, 0).unwrap();
+        mempool.add(child.clone(), 5000, 0).unwrap();
+
+        // Room for everything: the parent and child package pays most per weight
+        let template = BlockAssembler::new(4_000_000).assemble(&mempool);
+        assert_eq!(
+            txids(&template.transactions),
+            txids(&[parent.clone(), child.clone(), other.clone()])
+        );
+        assert_eq!(template.fees, 6000);
+        let weight: usize = [&parent, &child, &other].iter().map(|tx| tx.weight()).sum();
+        assert_eq!(template.weight, COINBASE_RESERVED_WEIGHT + weight);
+
+        // Room for two transactions: the package beats the single 1000 satoshi fee
+        let limit = COINBASE_RESERVED_WEIGHT + parent.weight() + child.weight();
+        let template = BlockAssembler::new(limit).assemble(&mempool);
+        assert_eq!(txids(&template.transactions), txids(&[parent, child]));
+        assert_eq!(template.weight, limit);
+    }
+
+    #[test]
+    fn test_fills_space_a_package_cannot() {
+        let parent = spend(OutPoint::new([1; 32], 0));
+        let mut child = child_of(&parent);
+        child.outputs.push(TxOut::to_address(1, "carol"));
+        let small = spend(OutPoint::new([2; 32], 0));
+        assert!(child.weight() > small.weight());
+        let mut mempool = Mempool::default();
+        mempool.add(parent.clone(), 10_000, 0).unwrap();
+        mempool.add(child.clone(), 10_000, 0).unwrap();
+        mempool.add(small.clone(), 1, 0).unwrap();
+
+        // The parent fits but the child doesn't, leaving room for a cheaper transaction
+        let limit = COINBASE_RESERVED_WEIGHT + parent.weight() + small.weight();
+        let template = BlockAssembler::new(limit).assemble(&mempool);
+        assert_eq!(txids(&template.transactions), txids(&[parent, small]));
+        assert_eq!(template.fees, 10_001);
+
+        // No room at all
+        let template = BlockAssembler::new(COINBASE_RESERVED_WEIGHT).assemble(&mempool);
+        assert!(template.transactions.is_empty());
+        assert_eq!(template.weight, COINBASE_RESERVED_WEIGHT);
+    }
+}
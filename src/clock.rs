@@ -0,0 +1,108 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Where the chain and miner get the current time, so it can be pinned or simulated
+pub trait Clock: fmt::Debug + Send + Sync {
+    // Time since the Unix epoch
+    fn since_epoch(&self) -> Duration;
+
+    // Whole seconds since the Unix epoch, as block timestamps count them
+    fn now(&self) -> u32 {
+        self.since_epoch().as_secs() as u32
+    }
+}
+
+// The operating system's wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn since_epoch(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+    }
+}
+
+// A clock that only moves when told to, for reproducible tests
+#[derive(Debug)]
+pub struct ManualClock {
+    time: Mutex<Duration>,
+}
+
+impl ManualClock {
+    pub fn new(timestamp: u32) -> Self {
+        Self {
+            time: Mutex::new(Duration::from_secs(timestamp as u64)),
+        }
+    }
+
+    pub fn set(&self, timestamp: u32) {
+        *self.time.lock().unwrap() = Duration::from_secs(timestamp as u64);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.time.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn since_epoch(&self) -> Duration {
+        *self.time.lock().unwrap()
+    }
+}
+
+// Starts at a chosen time and runs `speed` times faster than real time, for simulating
+// days of mining in minutes
+#[derive(Debug)]
+pub struct SimulatedClock {
+    start: Duration,
+    started: Instant,
+    speed: u32,
+}
+
+impl SimulatedClock {
+    pub fn new(timestamp: u32, speed: u32) -> Self {
+        Self {
+            start: Duration::from_secs(timestamp as u64),
+            started: Instant::now(),
+            speed,
+        }
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn since_epoch(&self) -> Duration {
+        self.start + self.started.elapsed() * self.speed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new(1231006505);
+        assert_eq!(clock.now(), 1231006505);
+
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(
+            clock.since_epoch(),
+            Duration::from_secs(1231006506) + Duration::from_millis(500)
+        );
+        assert_eq!(clock.now(), 1231006506);
+
+        clock.set(1296688602);
+        assert_eq!(clock.now(), 1296688602);
+    }
+
+    #[test]
+    fn test_simulated_clock_runs_faster() {
+        // An hour of simulated time passes every real second
+        let clock = SimulatedClock::new(1231006505, 3600);
+        assert!(clock.now() - 1231006505 < 3600);
+
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(clock.since_epoch() >= Duration::from_secs(1231006505 + 36));
+    }
+}
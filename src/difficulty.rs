@@ -0,0 +1,540 @@
+use crate::params::ChainParams;
+use crate::target::Target;
+use crate::tree::{BlockIndexEntry, BlockTree};
+use std::fmt;
+
+// Ten minutes between blocks, as in Bitcoin
+pub const DEFAULT_TARGET_SPACING: u32 = 600;
+// Two weeks of blocks
+pub const DEFAULT_RETARGET_INTERVAL: u32 = 2016;
+pub const DEFAULT_LWMA_WINDOW: u32 = 45;
+// Two days, as on Bitcoin Cash
+pub const DEFAULT_ASERT_HALF_LIFE: u32 = 172_800;
+pub const DEFAULT_DIGISHIELD_WINDOW: u32 = 17;
+
+// How a chain sets each block's target from the blocks before it
+pub trait DifficultyAlgorithm: fmt::Debug {
+    // nBits a child of `previous_hash`, which `tree` must hold, must commit to
+    fn next_bits(&self, tree: &BlockTree, previous_hash: &[u8; 32]) -> u32;
+
+    // The easiest target allowed, which the genesis block commits to
+    fn pow_limit(&self) -> Target;
+}
+
+// Algorithm by name with the chain's limit and spacing, for choosing one per chain on
+// the command line
+pub fn from_name(name: &str, params: &ChainParams) -> Option<Box<dyn DifficultyAlgorithm>> {
+    let pow_limit = params.pow_limit;
+    let target_spacing = params.target_spacing;
+    match name {
+        "bitcoin" => Some(Box::new(Retarget {
+            interval: params.retarget_interval,
+            target_spacing,
+            pow_limit,
+        })),
+        "fixed" => Some(Box::new(Fixed { pow_limit })),
+        "lwma" => Some(Box::new(Lwma {
+            target_spacing,
+            ..Lwma::new(pow_limit)
+        })),
+        "asert" => Some(Box::new(Asert {
+            target_spacing,
+            ..Asert::new(pow_limit)
+        })),
+        "digishield" => Some(Box::new(DigiShield {
+            target_spacing,
+            ..DigiShield::new(pow_limit)
+        })),
+        _ => None,
+    }
+}
+
+// The last `count` blocks of the branch ending in `hash`, oldest first, or None if the
+// branch is shorter
+fn last_blocks<'a>(
+    tree: &'a BlockTree,
+    hash: &[u8; 32],
+    count: u32,
+) -> Option<Vec<&'a BlockIndexEntry>> {
+    let mut blocks = Vec::with_capacity(count as usize);
+    let mut current = tree.get(hash);
+    while let Some(entry) = current
+        && blocks.len() < count as usize
+    {
+        blocks.push(entry);
+        current = tree.get(&entry.previous_hash);
+    }
+    blocks.reverse();
+    (blocks.len() == count as usize).then_some(blocks)
+}
+
+fn target_of(entry: &BlockIndexEntry, pow_limit: Target) -> Target {
+    Target::from_compact(entry.bits).unwrap_or(pow_limit)
+}
+
+// Every block keeps its parent's nBits, as on regtest
+#[derive(Debug, Clone)]
+pub struct Fixed {
+    pub pow_limit: Target,
+}
+
+impl DifficultyAlgorithm for Fixed {
+    fn next_bits(&self, tree: &BlockTree, previous_hash: &[u8; 32]) -> u32 {
+        tree.get(previous_hash).unwrap().bits
+    }
+
+    fn pow_limit(&self) -> Target {
+        self.pow_limit
+    }
+}
+
+// Bitcoin's difficulty adjustment: every `interval` blocks the target is scaled by how
+// long the last interval took compared to how long it should have, by at most a factor
+// of four either way. Every other block keeps its parent's nBits.
+#[derive(Debug, Clone)]
+pub struct Retarget {
+    pub interval: u32,
+    pub target_spacing: u32,
+    pub pow_limit: Target,
+}
+
+impl Retarget {
+    pub fn new(pow_limit: Target) -> Self {
+        Self {
+            interval: DEFAULT_RETARGET_INTERVAL,
+            target_spacing: DEFAULT_TARGET_SPACING,
+            pow_limit,
+        }
+    }
+
+    // Seconds an interval should take
+    pub fn target_timespan(&self) -> u64 {
+        self.interval as u64 * self.target_spacing as u64
+    }
+
+    // nBits after an interval that took `actual_timespan` seconds
+    pub fn retarget(&self, bits: u32, actual_timespan: i64) -> u32 {
+        let target_timespan = self.target_timespan();
+        let actual_timespan =
+            actual_timespan.clamp(target_timespan as i64 / 4, target_timespan as i64 * 4) as u64;
+
+        let previous = Target::from_compact(bits).unwrap_or(self.pow_limit);
+        previous
+            .scale(actual_timespan, target_timespan)
+            .min(self.pow_limit)
+            .to_compact()
+    }
+}
+
+impl DifficultyAlgorithm for Retarget {
+    // Like Bitcoin Core, the timespan runs from the first to the last block of the
+    // interval, so it covers one block fewer than the interval
+    fn next_bits(&self, tree: &BlockTree, previous_hash: &[u8; 32]) -> u32 {
+        let parent = tree.get(previous_hash).unwrap();
+        if !(parent.height + 1).is_multiple_of(self.interval) {
+            return parent.bits;
+        }
+
+        let first = tree
+            .ancestor(previous_hash, parent.height + 1 - self.interval)
+            .unwrap();
+        let actual_timespan = parent.timestamp as i64 - first.timestamp as i64;
+        self.retarget(parent.bits, actual_timespan)
+    }
+
+    fn pow_limit(&self) -> Target {
+        self.pow_limit
+    }
+}
+
+// Zawy's linearly weighted moving average (LWMA-1): every block, the mean target of the
+// last `window` blocks is scaled by their solve times, the most recent weighted most, so
+// a change in hashrate shows within a few blocks.
+#[derive(Debug, Clone)]
+pub struct Lwma {
+    pub window: u32,
+    pub target_spacing: u32,
+    pub pow_limit: Target,
+}
+
+impl Lwma {
+    pub fn new(pow_limit: Target) -> Self {
+        Self {
+            window: DEFAULT_LWMA_WINDOW,
+            target_spacing: DEFAULT_TARGET_SPACING,
+            pow_limit,
+        }
+    }
+}
+
+impl DifficultyAlgorithm for Lwma {
+    fn next_bits(&self, tree: &BlockTree, previous_hash: &[u8; 32]) -> u32 {
+        // Until there's a full window the target stays where it started
+        let Some(blocks) = last_blocks(tree, previous_hash, self.window + 1) else {
+            return tree.get(previous_hash).unwrap().bits;
+        };
+
+        let spacing = self.target_spacing as i64;
+        let mut weighted_solve_time = 0;
+        let mut mean_target = Target::ZERO;
+        let mut previous_timestamp = blocks[0].timestamp as i64;
+        for (i, block) in blocks[1..].iter().enumerate() {
+            // Out of order timestamps count as one second and long gaps as six spacings,
+            // so a forged timestamp can only move the target so far
+            let timestamp = (block.timestamp as i64).max(previous_timestamp + 1);
+            let solve_time = (timestamp - previous_timestamp).min(6 * spacing);
+            previous_timestamp = timestamp;
+
+            weighted_solve_time += solve_time * (i as i64 + 1);
+            mean_target = mean_target
+                .saturating_add(target_of(block, self.pow_limit).div_u64(self.window as u64));
+        }
+
+        // What the weighted sum would be if every block took exactly one spacing
+        let expected = self.window as i64 * (self.window as i64 + 1) / 2 * spacing;
+        let weighted_solve_time = weighted_solve_time.max(expected / 10);
+        mean_target
+            .scale(weighted_solve_time as u64, expected as u64)
+            .min(self.pow_limit)
+            .to_compact()
+    }
+
+    fn pow_limit(&self) -> Target {
+        self.pow_limit
+    }
+}
+
+// Bitcoin Cash's aserti3-2d: the target doubles for every `half_life` seconds the chain
+// is behind schedule since an anchor block and halves for every `half_life` it is ahead,
+// with a fixed-point approximation of 2^x so every node computes the same bits. The
+// anchor's own timestamp starts the schedule, where Bitcoin Cash uses its parent's.
+#[derive(Debug, Clone)]
+pub struct Asert {
+    pub target_spacing: u32,
+    pub half_life: u32,
+    pub anchor_height: u32,
+    pub pow_limit: Target,
+}
+
+impl Asert {
+    pub fn new(pow_limit: Target) -> Self {
+        Self {
+            target_spacing: DEFAULT_TARGET_SPACING,
+            half_life: DEFAULT_ASERT_HALF_LIFE,
+            anchor_height: 0,
+            pow_limit,
+        }
+    }
+}
+
+impl DifficultyAlgorithm for Asert {
+    fn next_bits(&self, tree: &BlockTree, previous_hash: &[u8; 32]) -> u32 {
+        let parent = tree.get(previous_hash).unwrap();
+        let Some(anchor) = tree.ancestor(previous_hash, self.anchor_height) else {
+            return parent.bits;
+        };
+
+        let time_delta = parent.timestamp as i64 - anchor.timestamp as i64;
+        let height_delta = (parent.height - anchor.height) as i64;
+        let exponent = (time_delta - self.target_spacing as i64 * height_delta) * 65536
+            / self.half_life as i64;
+
+        // 2^exponent as 2^shifts * factor / 65536, with factor in [65536, 131072)
+        let shifts = exponent >> 16;
+        let fraction = (exponent & 0xffff) as u128;
+        let factor = 65536
+            + ((195_766_423_245_049 * fraction
+                + 971_821_376 * fraction.pow(2)
+                + 5127 * fraction.pow(3)
+                + (1 << 47))
+                >> 48) as u64;
+
+        // An anchor target too big to multiply loses its low bits first
+        let anchor_target = target_of(anchor, self.pow_limit);
+        let (product, shifts) = match anchor_target.checked_mul_u64(factor) {
+            Some(product) => (product, shifts - 16),
+            None => (
+                (anchor_target >> 17).checked_mul_u64(factor).unwrap(),
+                shifts + 1,
+            ),
+        };
+        let next = if shifts < 0 {
+            product >> shifts.unsigned_abs().min(256) as u32
+        } else if product.bit_length() as i64 + shifts <= 256 {
+            product << shifts as u32
+        } else {
+            Target::MAX
+        };
+
+        next.max(Target::ONE).min(self.pow_limit).to_compact()
+    }
+
+    fn pow_limit(&self) -> Target {
+        self.pow_limit
+    }
+}
+
+// DigiShield v3 as Zcash runs it: every block, the mean target of the last `window`
+// blocks is scaled by how long they took between median times past, with the deviation
+// from schedule damped to a quarter and clamped to between 84% and 132% of the window's
+// timespan: MaxAdjustUp of 16% and MaxAdjustDown of 32%.
+#[derive(Debug, Clone)]
+pub struct DigiShield {
+    pub window: u32,
+    pub target_spacing: u32,
+    pub pow_limit: Target,
+}
+
+impl DigiShield {
+    pub fn new(pow_limit: Target) -> Self {
+        Self {
+            window: DEFAULT_DIGISHIELD_WINDOW,
+            target_spacing: DEFAULT_TARGET_SPACING,
+            pow_limit,
+        }
+    }
+}
+
+impl DifficultyAlgorithm for DigiShield {
+    fn next_bits(&self, tree: &BlockTree, previous_hash: &[u8; 32]) -> u32 {
+        // Until there's a full window the target stays where it started
+        let Some(blocks) = last_blocks(tree, previous_hash, self.window + 1) else {
+            return tree.get(previous_hash).unwrap().bits;
+        };
+
+        let mean_target = blocks[1..].iter().fold(Target::ZERO, |sum, block| {
+            sum.saturating_add(target_of(block, self.pow_limit).div_u64(self.window as u64))
+        });
+
+        // blocks[0] only marks where the window starts
+        let actual_timespan = tree.median_time_past(previous_hash) as i64
+            - tree.median_time_past(&blocks[1].previous_hash) as i64;
+        let expected = self.window as i64 * self.target_spacing as i64;
+        let damped = expected + (actual_timespan - expected) / 4;
+        let damped = damped.clamp(expected * 84 / 100, expected * 132 / 100);
+
+        mean_target
+            .scale(damped as u64, expected as u64)
+            .min(self.pow_limit)
+            .to_compact()
+    }
+
+    fn pow_limit(&self) -> Target {
+        self.pow_limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target::DIFFICULTY_ONE_BITS;
+    use crate::{Block, COIN, Transaction};
+
+    fn mainnet() -> Retarget {
+        Retarget::new(Target::from_compact(DIFFICULTY_ONE_BITS).unwrap())
+    }
+
+    // Bitcoin Core's pow_tests, from real mainnet retargets
+    #[test]
+    fn test_mainnet_retargets() {
+        let retarget = mainnet();
+        assert_eq!(
+            retarget.retarget(0x1d00ffff, 1262152739 - 1261130161),
+            0x1d00d86a
+        );
+        // Slower than two weeks, but already at the limit
+        assert_eq!(
+            retarget.retarget(0x1d00ffff, 1233061996 - 1231006505),
+            0x1d00ffff
+        );
+        // Clamped to a quarter of the expected timespan
+        assert_eq!(
+            retarget.retarget(0x1c05a3f4, 1279297671 - 1279008237),
+            0x1c0168fd
+        );
+        // Clamped to four times the expected timespan
+        assert_eq!(
+            retarget.retarget(0x1c387f6f, 1269211443 - 1263163443),
+            0x1d00e1fd
+        );
+    }
+
+    #[test]
+    fn test_next_bits_only_changes_at_interval() {
+        let mut retarget = mainnet();
+        retarget.interval = 4;
+        let mut tree = BlockTree::new();
+        let mut previous_hash = [0; 32];
+        let mut hashes = Vec::new();
+
+        for height in 0..6u32 {
+            let coinbase = Transaction::coinbase(height, 0, "miner", COIN);
+            let mut block = Block::new(1, previous_hash, vec![coinbase], 0x1c05a3f4, height);
+            // Each block arrives a minute after the last
+            block.header.timestamp = 1000 + 60 * height;
+            previous_hash = block.hash();
+            tree.insert(&block);
+            hashes.push(previous_hash);
+        }
+
+        assert_eq!(retarget.next_bits(&tree, &hashes[1]), 0x1c05a3f4);
+        // Heights 0..=3 took three minutes against forty expected, so the target
+        // shrinks the most it can
+        assert_eq!(retarget.next_bits(&tree, &hashes[3]), 0x1c0168fd);
+        assert_eq!(retarget.next_bits(&tree, &hashes[4]), 0x1c05a3f4);
+
+        // Targets too big to scale in 256 bits still retarget
+        let easy = Retarget::new(Target::MAX);
+        assert_eq!(easy.retarget(0x2100ffff, 0), 0x203fffbf);
+    }
+
+    // Hashes per second that find a block every ten minutes at the simulated genesis
+    // block's target, which takes 2^30 hashes
+    const BASE_HASHRATE: u128 = (1 << 30) / 600;
+    // Height from which the hashrate is ten times higher
+    const STEP_HEIGHT: u32 = 500;
+
+    // Solve times of `blocks` blocks after genesis, each taking exactly as long as its
+    // target needs on average at a hashrate that jumps tenfold at STEP_HEIGHT
+    fn simulate(algorithm: &dyn DifficultyAlgorithm, blocks: u32) -> Vec<u64> {
+        let mut tree = BlockTree::new();
+        let coinbase = Transaction::coinbase(0, 0, "miner", COIN);
+        let bits = Target::from_leading_zero_bits(30).to_compact();
+        let mut parent = Block::new(1, [0; 32], vec![coinbase], bits, 0);
+        parent.header.timestamp = 1_000_000;
+        tree.insert(&parent);
+
+        let mut solve_times = Vec::new();
+        for height in 1..=blocks {
+            let bits = algorithm.next_bits(&tree, &parent.hash());
+            let hashrate = match height < STEP_HEIGHT {
+                true => BASE_HASHRATE,
+                false => 10 * BASE_HASHRATE,
+            };
+            let work = Target::from_compact(bits).unwrap().work();
+            let solve_time = (work / hashrate).max(1) as u64;
+
+            let coinbase = Transaction::coinbase(height, 0, "miner", COIN);
+            let mut block = Block::new(1, parent.hash(), vec![coinbase], bits, height);
+            block.header.timestamp = parent.header.timestamp + solve_time as u32;
+            tree.insert(&block);
+            solve_times.push(solve_time);
+            parent = block;
+        }
+        solve_times
+    }
+
+    fn mean(solve_times: &[u64]) -> u64 {
+        solve_times.iter().sum::<u64>() / solve_times.len() as u64
+    }
+
+    // Checks the algorithm holds ten minute blocks before the step, that blocks speed
+    // up when the hashrate jumps, and that they're back to ten minutes by the end.
+    // Returns how many blocks after the step the first one takes at least nine minutes.
+    fn assert_converges(algorithm: &dyn DifficultyAlgorithm, blocks: u32) -> usize {
+        let solve_times = simulate(algorithm, blocks);
+        let step = STEP_HEIGHT as usize - 1;
+
+        let before = mean(&solve_times[step - 100..step]);
+        assert!((590..=610).contains(&before), "held {}s", before);
+        assert!(solve_times[step] <= 61);
+        let settled = mean(&solve_times[solve_times.len() - 100..]);
+        assert!((590..=610).contains(&settled), "settled at {}s", settled);
+
+        solve_times[step..]
+            .iter()
+            .position(|solve_time| *solve_time >= 540)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bitcoin_retarget_converges() {
+        let retarget = Retarget {
+            interval: 144,
+            ..Retarget::new(Target::MAX)
+        };
+        // The first retarget after the step still sees mostly slow blocks, and the
+        // next can only quadruple the difficulty, so it takes until the third at 864
+        assert_eq!(assert_converges(&retarget, 1500), 364);
+    }
+
+    #[test]
+    fn test_lwma_converges() {
+        assert_eq!(assert_converges(&Lwma::new(Target::MAX), 1000), 86);
+    }
+
+    #[test]
+    fn test_asert_converges() {
+        let asert = Asert {
+            half_life: 6 * 3600,
+            ..Asert::new(Target::MAX)
+        };
+        assert_eq!(assert_converges(&asert, 1000), 227);
+    }
+
+    #[test]
+    fn test_digishield_converges() {
+        assert_eq!(assert_converges(&DigiShield::new(Target::MAX), 1000), 154);
+    }
+
+    #[test]
+    fn test_digishield_adjustment_bounds() {
+        let digishield = DigiShield {
+            window: 10,
+            ..DigiShield::new(Target::MAX)
+        };
+        // bits after a full window of 0x1d00ffff blocks `spacing` seconds apart
+        let next_bits = |spacing: u32| {
+            let mut tree = BlockTree::new();
+            let mut previous_hash = [0; 32];
+            for height in 0..=10 {
+                let coinbase = Transaction::coinbase(height, 0, "miner", COIN);
+                let mut block = Block::new(1, previous_hash, vec![coinbase], 0x1d00ffff, height);
+                block.header.timestamp = 1_000_000 + spacing * height;
+                previous_hash = block.hash();
+                tree.insert(&block);
+            }
+            digishield.next_bits(&tree, &previous_hash)
+        };
+
+        // As in Zcash, the timespan bottoms out at 84% of schedule however fast the
+        // blocks were, and tops out at 132% however slow
+        assert_eq!(next_bits(1), 0x1d00d709);
+        assert_eq!(next_bits(6000), 0x1d0151ea);
+    }
+
+    #[test]
+    fn test_asert_schedule() {
+        let asert = Asert::new(Target::MAX);
+        // bits for a block 100 blocks above the anchor, `offset` seconds behind schedule
+        let next_bits = |offset: i64| {
+            let mut tree = BlockTree::new();
+            let coinbase = Transaction::coinbase(0, 0, "miner", COIN);
+            let mut anchor = Block::new(1, [0; 32], vec![coinbase], 0x1c05a3f4, 0);
+            anchor.header.timestamp = 1_000_000;
+            tree.insert(&anchor);
+
+            let coinbase = Transaction::coinbase(100, 0, "miner", COIN);
+            let mut parent = Block::new(1, anchor.hash(), vec![coinbase], 0x1c05a3f4, 100);
+            parent.header.timestamp = (1_000_000 + 100 * 600 + offset) as u32;
+            tree.insert(&parent);
+            asert.next_bits(&tree, &parent.hash())
+        };
+
+        assert_eq!(next_bits(0), 0x1c05a3f4);
+        let half_life = DEFAULT_ASERT_HALF_LIFE as i64;
+        assert_eq!(next_bits(half_life), 0x1c0b47e8);
+        assert_eq!(next_bits(-half_life), 0x1c02d1fa);
+        assert_eq!(next_bits(-2 * half_life), 0x1c0168fd);
+    }
+
+    #[test]
+    fn test_from_name() {
+        let params = ChainParams::regtest();
+        for name in ["bitcoin", "fixed", "lwma", "asert", "digishield"] {
+            let algorithm = from_name(name, &params).unwrap();
+            assert_eq!(algorithm.pow_limit(), params.pow_limit);
+        }
+        assert!(from_name("scrypt", &params).is_none());
+    }
+}
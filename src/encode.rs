@@ -0,0 +1,147 @@
+use crate::DecodeError;
+
+// Bitcoin's variable-length integer: one byte below 0xfd, otherwise a marker byte
+// followed by a 2, 4 or 8 byte little-endian value
+pub fn write_compact_size(bytes: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0xfc => bytes.push(value as u8),
+        0xfd..=0xffff => {
+            bytes.push(0xfd);
+            bytes.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            bytes.push(0xfe);
+            bytes.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        _ => {
+            bytes.push(0xff);
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+// Cursor over an encoded message
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    // Rejects values that would fit a shorter encoding, as Bitcoin Core does
+    pub fn read_compact_size(&mut self) -> Result<u64, DecodeError> {
+        let (value, minimum) = match self.read_u8()? {
+            0xfd => (self.read_u16()? as u64, 0xfd),
+            0xfe => (self.read_u32()? as u64, 0x10000),
+            0xff => (self.read_u64()?, 0x1_0000_0000),
+            byte => (byte as u64, 0),
+        };
+        if value < minimum {
+            return Err(DecodeError::NonCanonicalCompactSize);
+        }
+        Ok(value)
+    }
+
+    // A count of items that each take at least one byte, so it can't exceed what's left.
+    // Keeps a forged count from reserving huge allocations.
+    pub fn read_count(&mut self) -> Result<usize, DecodeError> {
+        let count = self.read_compact_size()?;
+        if count > self.remaining() as u64 {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        Ok(count as usize)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    // Succeeds only if every byte was consumed
+    pub fn finish(self) -> Result<(), DecodeError> {
+        match self.remaining() {
+            0 => Ok(()),
+            count => Err(DecodeError::TrailingBytes { count }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_size_round_trip() {
+        let cases: [(u64, &str); 6] = [
+            (0, "00"),
+            (0xfc, "fc"),
+            (0xfd, "fdfd00"),
+            (0xffff, "fdffff"),
+            (0x10000, "fe00000100"),
+            (0x1_0000_0000, "ff0000000001000000"),
+        ];
+
+        for (value, encoded) in cases {
+            let mut bytes = Vec::new();
+            write_compact_size(&mut bytes, value);
+            assert_eq!(hex::encode(&bytes), encoded);
+
+            let mut reader = Reader::new(&bytes);
+            assert_eq!(reader.read_compact_size(), Ok(value));
+            assert_eq!(reader.finish(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_malformed_input_is_rejected() {
+        assert_eq!(
+            Reader::new(&[0xfd, 0x10, 0x00]).read_compact_size(),
+            Err(DecodeError::NonCanonicalCompactSize)
+        );
+        assert_eq!(
+            Reader::new(&[0xfe, 0x01]).read_compact_size(),
+            Err(DecodeError::UnexpectedEnd)
+        );
+        assert_eq!(
+            Reader::new(&[0x05, 0x00]).read_count(),
+            Err(DecodeError::UnexpectedEnd)
+        );
+        assert_eq!(
+            Reader::new(&[0x00]).finish(),
+            Err(DecodeError::TrailingBytes { count: 1 })
+        );
+    }
+}
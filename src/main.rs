@@ -1,32 +1,306 @@
-use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub mod assembler;
+pub mod clock;
+pub mod difficulty;
+pub mod encode;
+pub mod mempool;
+pub mod merkle;
+pub mod midstate;
+pub mod params;
+pub mod script;
+pub mod store;
+pub mod target;
+pub mod tree;
+pub mod utxo;
+pub mod wallet;
+
+use assembler::{BlockAssembler, BlockTemplate};
+use clock::{Clock, SystemClock};
+use difficulty::DifficultyAlgorithm;
+use encode::{Reader, write_compact_size};
+use mempool::{Mempool, MempoolError};
+use merkle::{MerkleBlock, MerkleProof, PartialMerkleTree, merkle_proof, merkle_root};
+use midstate::HeaderHasher;
+use params::ChainParams;
+use script::{ScriptError, TransactionChecker, verify_script};
+use std::path::Path;
+use store::{ChainStore, FileStore, MemoryStore, StoreError};
+use target::Target;
+use tree::BlockTree;
+use utxo::{BlockUndo, UtxoError, UtxoSet};
+
+// Reference to an output of an earlier transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OutPoint {
+    pub txid: [u8; 32],
+    pub vout: u32,
+}
+
+impl OutPoint {
+    // Spent by coinbase inputs, which create coins instead of moving them
+    pub const NULL: OutPoint = OutPoint {
+        txid: [0; 32],
+        vout: u32::MAX,
+    };
+
+    pub fn new(txid: [u8; 32], vout: u32) -> Self {
+        Self { txid, vout }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Self::NULL
+    }
+}
+
+impl fmt::Display for OutPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", hash_to_hex(&self.txid), self.vout)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxIn {
+    pub previous_output: OutPoint,
+    pub script_sig: Vec<u8>,
+    pub sequence: u32,
+}
+
+impl TxIn {
+    pub fn new(previous_output: OutPoint) -> Self {
+        Self {
+            previous_output,
+            script_sig: vec![],
+            sequence: u32::MAX,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxOut {
+    pub value: u64,
+    pub script_pubkey: Vec<u8>,
+}
+
+impl TxOut {
+    // Pay to the public key hash of `address`'s wallet key
+    pub fn to_address(value: u64, address: &str) -> Self {
+        Self {
+            value,
+            script_pubkey: wallet::script_pubkey(address),
+        }
+    }
+
+    pub fn is_locked_to(&self, address: &str) -> bool {
+        self.script_pubkey == wallet::script_pubkey(address)
+    }
+}
 
 // Transaction structure (simplified)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Transaction {
-    pub id: String,
-    pub inputs: Vec<String>,
-    pub outputs: Vec<String>,
-    pub amount: u64,
+    pub version: u32,
+    pub inputs: Vec<TxIn>,
+    pub outputs: Vec<TxOut>,
+    pub lock_time: u32,
 }
 
 impl Transaction {
-    pub fn new(id: String, inputs: Vec<String>, outputs: Vec<String>, amount: u64) -> Self {
+    pub fn new(inputs: Vec<TxIn>, outputs: Vec<TxOut>) -> Self {
         Self {
-            id,
+            version: 1,
             inputs,
             outputs,
-            amount,
+            lock_time: 0,
         }
     }
 
-    pub fn hash(&self) -> [u8; 32] {
-        let serialized = serde_json::to_string(self).unwrap();
-        let mut hasher = Sha256::new();
-        hasher.update(serialized.as_bytes());
-        hasher.finalize().into()
+    // Newly minted coins for the block at `height`, paid to `recipient`. The input
+    // script pushes the height, which keeps coinbase txids unique, and the extranonce.
+    pub fn coinbase(height: u32, extranonce: u32, recipient: &str, amount: u64) -> Self {
+        let mut script_sig = vec![4];
+        script_sig.extend_from_slice(&height.to_le_bytes());
+        script_sig.push(4);
+        script_sig.extend_from_slice(&extranonce.to_le_bytes());
+
+        let input = TxIn {
+            previous_output: OutPoint::NULL,
+            script_sig,
+            sequence: u32::MAX,
+        };
+        Self::new(vec![input], vec![TxOut::to_address(amount, recipient)])
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1 && self.inputs[0].previous_output.is_null()
+    }
+
+    pub fn extranonce(&self) -> Option<u32> {
+        if !self.is_coinbase() {
+            return None;
+        }
+        let script_sig = &self.inputs[0].script_sig;
+        let bytes = script_sig.get(COINBASE_EXTRANONCE_OFFSET..COINBASE_EXTRANONCE_OFFSET + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    // Returns false if this is not a coinbase created by `Transaction::coinbase`
+    pub fn set_extranonce(&mut self, extranonce: u32) -> bool {
+        if self.extranonce().is_none() {
+            return false;
+        }
+        let script_sig = &mut self.inputs[0].script_sig;
+        script_sig[COINBASE_EXTRANONCE_OFFSET..COINBASE_EXTRANONCE_OFFSET + 4]
+            .copy_from_slice(&extranonce.to_le_bytes());
+        true
+    }
+
+    // Whether a block at `height` whose parent has median time past `time` may include
+    // the transaction. Lock times below LOCKTIME_THRESHOLD are heights, and final
+    // sequences on every input waive the lock time.
+    pub fn is_final(&self, height: u32, time: u32) -> bool {
+        let limit = if self.lock_time < script::LOCKTIME_THRESHOLD {
+            height
+        } else {
+            time
+        };
+        self.lock_time == 0
+            || self.lock_time < limit
+            || self
+                .inputs
+                .iter()
+                .all(|input| input.sequence == script::SEQUENCE_FINAL)
+    }
+
+    // BIP68: whether a block at `height` whose parent has median time past `time` may
+    // include the transaction, given the heights of the blocks that created the coins
+    // it spends. `median_time_past` gives that of the block at a height on the same
+    // branch. Version 1 transactions and inputs with the disable flag aren't locked.
+    pub fn sequence_locks_met(
+        &self,
+        coin_heights: &[u32],
+        height: u32,
+        time: u32,
+        median_time_past: impl Fn(u32) -> u32,
+    ) -> bool {
+        if self.version < 2 {
+            return true;
+        }
+        self.inputs
+            .iter()
+            .zip(coin_heights)
+            .all(|(input, &coin_height)| {
+                let sequence = input.sequence;
+                if sequence & script::SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+                    return true;
+                }
+                let lock = (sequence & script::SEQUENCE_LOCKTIME_MASK) as i64;
+                if sequence & script::SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+                    // In units of 512 seconds from the median time past before the
+                    // coin's block
+                    let coin_time = median_time_past(coin_height.saturating_sub(1)) as i64;
+                    coin_time + (lock << 9) - 1 < time as i64
+                } else {
+                    coin_height as i64 + lock - 1 < height as i64
+                }
+            })
+    }
+
+    // Sum of the output values, or None if an output or the sum is above MAX_MONEY
+    pub fn total_output(&self) -> Option<u64> {
+        self.outputs.iter().try_fold(0u64, |total, output| {
+            let total = total.checked_add(output.value)?;
+            (output.value <= MAX_MONEY && total <= MAX_MONEY).then_some(total)
+        })
+    }
+
+    pub fn txid(&self) -> [u8; 32] {
+        double_sha256(&self.serialize())
+    }
+
+    // Bytes of the wire encoding
+    pub fn size(&self) -> usize {
+        self.serialize().len()
+    }
+
+    // Without witness data every byte counts WITNESS_SCALE_FACTOR times
+    pub fn weight(&self) -> usize {
+        self.size() * WITNESS_SCALE_FACTOR
+    }
+
+    // Wire format without witness data: version, CompactSize-counted inputs and outputs,
+    // then the lock time. Scripts are CompactSize-prefixed.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+
+        write_compact_size(&mut bytes, self.inputs.len() as u64);
+        for input in &self.inputs {
+            bytes.extend_from_slice(&input.previous_output.txid);
+            bytes.extend_from_slice(&input.previous_output.vout.to_le_bytes());
+            write_compact_size(&mut bytes, input.script_sig.len() as u64);
+            bytes.extend_from_slice(&input.script_sig);
+            bytes.extend_from_slice(&input.sequence.to_le_bytes());
+        }
+
+        write_compact_size(&mut bytes, self.outputs.len() as u64);
+        for output in &self.outputs {
+            bytes.extend_from_slice(&output.value.to_le_bytes());
+            write_compact_size(&mut bytes, output.script_pubkey.len() as u64);
+            bytes.extend_from_slice(&output.script_pubkey);
+        }
+
+        bytes.extend_from_slice(&self.lock_time.to_le_bytes());
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let transaction = Self::read(&mut reader)?;
+        reader.finish()?;
+        Ok(transaction)
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let version = reader.read_u32()?;
+
+        let input_count = reader.read_count()?;
+        let inputs = (0..input_count)
+            .map(|_| {
+                let previous_output = OutPoint::new(reader.read_array()?, reader.read_u32()?);
+                let script_length = reader.read_count()?;
+                Ok(TxIn {
+                    previous_output,
+                    script_sig: reader.read_bytes(script_length)?.to_vec(),
+                    sequence: reader.read_u32()?,
+                })
+            })
+            .collect::<Result<_, DecodeError>>()?;
+
+        let output_count = reader.read_count()?;
+        let outputs = (0..output_count)
+            .map(|_| {
+                let value = reader.read_u64()?;
+                let script_length = reader.read_count()?;
+                Ok(TxOut {
+                    value,
+                    script_pubkey: reader.read_bytes(script_length)?.to_vec(),
+                })
+            })
+            .collect::<Result<_, DecodeError>>()?;
+
+        Ok(Self {
+            version,
+            inputs,
+            outputs,
+            lock_time: reader.read_u32()?,
+        })
     }
 }
 
@@ -35,55 +309,114 @@ impl fmt::Display for Transaction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Transaction {{ id: {}, amount: {} satoshis }}",
-            self.id, self.amount
+            "Transaction {{ txid: {}, inputs: {}, outputs: {}, value: {} }}",
+            hash_to_hex(&self.txid()),
+            self.inputs.len(),
+            self.outputs.len(),
+            self.total_output()
+                .map_or("out of range".to_string(), |value| format!(
+                    "{} satoshis",
+                    value
+                ))
         )
     }
 }
 
+// Position of the extranonce push data in a coinbase input script
+const COINBASE_EXTRANONCE_OFFSET: usize = 6;
+
+// Satoshis per bitcoin
+pub const COIN: u64 = 100_000_000;
+// No amount may exceed the 21 million coins there will ever be. Bounding values this
+// way keeps sums of them from overflowing.
+pub const MAX_MONEY: u64 = 21_000_000 * COIN;
+pub const INITIAL_SUBSIDY: u64 = 50 * COIN;
+pub const HALVING_INTERVAL: u32 = 210_000;
+
+// Address paid by the genesis block
+pub const GENESIS_ADDRESS: &str = "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa";
+// Bitcoin's genesis time, so a new chain's genesis block is the same on every run
+pub const GENESIS_TIMESTAMP: u32 = 1231006505;
+
+// Bitcoin's well-known burn address, which receives block rewards unless the miner
+// sets its own
+pub const DEFAULT_REWARD_ADDRESS: &str = "1BitcoinEaterAddressDontSendf59kuE";
+
+// Size of a serialized block header in bytes
+pub const BLOCK_HEADER_SIZE: usize = 80;
+// Weight units per non-witness byte, which makes the weight limit four times the size
+// limit
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+
 // Block header structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BlockHeader {
     pub version: u32,
     pub previous_hash: [u8; 32],
     pub merkle_root: [u8; 32],
-    pub timestamp: u64,
-    pub difficulty_target: u32,
+    pub timestamp: u32,
+    pub bits: u32,
     pub nonce: u32,
 }
 
 impl BlockHeader {
-    pub fn new(
-        version: u32,
-        previous_hash: [u8; 32],
-        merkle_root: [u8; 32],
-        difficulty_target: u32,
-    ) -> Self {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
+    // Unstamped header: whoever mines it sets the timestamp from their clock
+    pub fn new(version: u32, previous_hash: [u8; 32], merkle_root: [u8; 32], bits: u32) -> Self {
         Self {
             version,
             previous_hash,
             merkle_root,
-            timestamp,
-            difficulty_target,
+            timestamp: 0,
+            bits,
             nonce: 0,
         }
     }
 
+    // Consensus encoding: every field little-endian, hashes in internal byte order
+    pub fn serialize(&self) -> [u8; BLOCK_HEADER_SIZE] {
+        let mut bytes = [0u8; BLOCK_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&self.version.to_le_bytes());
+        bytes[4..36].copy_from_slice(&self.previous_hash);
+        bytes[36..68].copy_from_slice(&self.merkle_root);
+        bytes[68..72].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[72..76].copy_from_slice(&self.bits.to_le_bytes());
+        bytes[76..80].copy_from_slice(&self.nonce.to_le_bytes());
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, DecodeError> {
+        if bytes.len() != BLOCK_HEADER_SIZE {
+            return Err(DecodeError::InvalidLength {
+                expected: BLOCK_HEADER_SIZE,
+                actual: bytes.len(),
+            });
+        }
+
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+        Ok(Self {
+            version: u32_at(0),
+            previous_hash: bytes[4..36].try_into().unwrap(),
+            merkle_root: bytes[36..68].try_into().unwrap(),
+            timestamp: u32_at(68),
+            bits: u32_at(72),
+            nonce: u32_at(76),
+        })
+    }
+
     pub fn hash(&self) -> [u8; 32] {
-        let serialized = serde_json::to_string(self).unwrap();
-        let mut hasher = Sha256::new();
-        hasher.update(serialized.as_bytes());
-        let first_hash = hasher.finalize();
+        double_sha256(&self.serialize())
+    }
+
+    // Target encoded in nBits, or None if the encoding is invalid
+    pub fn target(&self) -> Option<Target> {
+        Target::from_compact(self.bits)
+    }
 
-        // Double SHA-256 (Bitcoin standard)
-        let mut hasher = Sha256::new();
-        hasher.update(first_hash);
-        hasher.finalize().into()
+    pub fn meets_target(&self) -> bool {
+        self.target()
+            .is_some_and(|target| target.is_met_by(&self.hash()))
     }
 }
 
@@ -92,17 +425,17 @@ impl fmt::Display for BlockHeader {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "BlockHeader {{")?;
         writeln!(f, "  version: {}", self.version)?;
-        writeln!(f, "  previous_hash: {}", hex::encode(self.previous_hash))?;
-        writeln!(f, "  merkle_root: {}", hex::encode(self.merkle_root))?;
+        writeln!(f, "  previous_hash: {}", hash_to_hex(&self.previous_hash))?;
+        writeln!(f, "  merkle_root: {}", hash_to_hex(&self.merkle_root))?;
         writeln!(f, "  timestamp: {}", self.timestamp)?;
-        writeln!(f, "  difficulty_target: {}", self.difficulty_target)?;
+        writeln!(f, "  bits: 0x{:08x}", self.bits)?;
         writeln!(f, "  nonce: {}", self.nonce)?;
         write!(f, "}}")
     }
 }
 
 // Block structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Block {
     pub header: BlockHeader,
     pub transactions: Vec<Transaction>,
@@ -114,11 +447,11 @@ impl Block {
         version: u32,
         previous_hash: [u8; 32],
         transactions: Vec<Transaction>,
-        difficulty_target: u32,
+        bits: u32,
         height: u32,
     ) -> Self {
         let merkle_root = Self::calculate_merkle_root(&transactions);
-        let header = BlockHeader::new(version, previous_hash, merkle_root, difficulty_target);
+        let header = BlockHeader::new(version, previous_hash, merkle_root, bits);
 
         Self {
             header,
@@ -131,35 +464,86 @@ impl Block {
         self.header.hash()
     }
 
-    // Simplified merkle root calculation
-    fn calculate_merkle_root(transactions: &[Transaction]) -> [u8; 32] {
-        if transactions.is_empty() {
-            return [0; 32];
+    // Wire format: header, CompactSize transaction count, transactions. The height isn't
+    // part of it.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = self.header.serialize().to_vec();
+        write_compact_size(&mut bytes, self.transactions.len() as u64);
+        for tx in &self.transactions {
+            bytes.extend_from_slice(&tx.serialize());
         }
+        bytes
+    }
+
+    // The wire format leaves out the height, so the caller supplies it
+    pub fn deserialize(bytes: &[u8], height: u32) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let header = BlockHeader::deserialize(reader.read_bytes(BLOCK_HEADER_SIZE)?)?;
+        let count = reader.read_count()?;
+        let transactions = (0..count)
+            .map(|_| Transaction::read(&mut reader))
+            .collect::<Result<_, DecodeError>>()?;
+        reader.finish()?;
+
+        Ok(Self {
+            header,
+            transactions,
+            height,
+        })
+    }
 
-        let mut hashes: Vec<[u8; 32]> = transactions.iter().map(|tx| tx.hash()).collect();
+    // Bytes of the wire encoding: header, CompactSize transaction count, transactions
+    pub fn size(&self) -> usize {
+        let mut count = Vec::new();
+        write_compact_size(&mut count, self.transactions.len() as u64);
+        BLOCK_HEADER_SIZE
+            + count.len()
+            + self
+                .transactions
+                .iter()
+                .map(Transaction::size)
+                .sum::<usize>()
+    }
 
-        while hashes.len() > 1 {
-            let mut next_level = Vec::new();
+    // Move to the next extranonce and recommit to the changed coinbase. Returns false
+    // if the block has no coinbase or its extranonce space is used up.
+    pub fn increment_extranonce(&mut self) -> bool {
+        let Some(coinbase) = self.transactions.first_mut() else {
+            return false;
+        };
+        let Some(extranonce) = coinbase.extranonce().and_then(|n| n.checked_add(1)) else {
+            return false;
+        };
 
-            for chunk in hashes.chunks(2) {
-                let mut hasher = Sha256::new();
-                hasher.update(&chunk[0]);
+        coinbase.set_extranonce(extranonce);
+        self.header.merkle_root = Self::calculate_merkle_root(&self.transactions);
+        true
+    }
 
-                if chunk.len() == 2 {
-                    hasher.update(&chunk[1]);
-                } else {
-                    // If odd number, duplicate the last hash
-                    hasher.update(&chunk[0]);
-                }
+    pub fn txids(&self) -> Vec<[u8; 32]> {
+        self.transactions.iter().map(|tx| tx.txid()).collect()
+    }
 
-                next_level.push(hasher.finalize().into());
-            }
+    // Branch linking `txid` to the header's merkle root, if the block contains it
+    pub fn merkle_proof(&self, txid: &[u8; 32]) -> Option<MerkleProof> {
+        let txids = self.txids();
+        let index = txids.iter().position(|id| id == txid)?;
+        merkle_proof(&txids, index)
+    }
 
-            hashes = next_level;
+    // Header plus a partial merkle tree proving which of `txids` the block contains
+    pub fn merkle_block(&self, txids: &[[u8; 32]]) -> MerkleBlock {
+        let block_txids = self.txids();
+        let matches: Vec<bool> = block_txids.iter().map(|id| txids.contains(id)).collect();
+        MerkleBlock {
+            header: self.header.clone(),
+            tree: PartialMerkleTree::new(&block_txids, &matches),
         }
+    }
 
-        hashes[0]
+    pub fn calculate_merkle_root(transactions: &[Transaction]) -> [u8; 32] {
+        let txids: Vec<[u8; 32]> = transactions.iter().map(|tx| tx.txid()).collect();
+        merkle_root(&txids).0
     }
 }
 
@@ -168,7 +552,7 @@ impl fmt::Display for Block {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Block #{} {{", self.height)?;
         writeln!(f, "  header: {}", self.header)?;
-        writeln!(f, "  block_hash: {}", hex::encode(self.hash()))?;
+        writeln!(f, "  block_hash: {}", hash_to_hex(&self.hash()))?;
         writeln!(f, "  transactions: [")?;
         for (i, tx) in self.transactions.iter().enumerate() {
             writeln!(f, "    {}: {}", i, tx)?;
@@ -181,475 +565,3411 @@ impl fmt::Display for Block {
 // Blockchain structure
 #[derive(Debug)]
 pub struct Blockchain {
-    pub blocks: Vec<Block>,
-    pub pending_transactions: Vec<Transaction>,
+    // The active chain
+    store: Box<dyn ChainStore>,
+    // Every known block, including competing branches
+    tree: BlockTree,
+    // Undo data for exactly the blocks on the active chain
+    undo: HashMap<[u8; 32], BlockUndo>,
+    pub mempool: Mempool,
+    pub utxos: UtxoSet,
     pub miner: Miner,
+    params: ChainParams,
+    // Bounds how far ahead of now a new block's timestamp may be. The miner shares it
+    // once set through `set_clock`.
+    clock: Arc<dyn Clock>,
+    // Fixed for the chain's lifetime, since stored blocks were validated under it
+    difficulty: Box<dyn DifficultyAlgorithm>,
+}
+
+// How the chain changed when a block was accepted
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChainUpdate {
+    // The block extends the active chain
+    Extended,
+    // The block is on a branch with no more work than the active chain
+    SideBranch,
+    // The block's branch overtook the active chain, whose last `depth` blocks above
+    // `fork_height` were disconnected
+    Reorganized { depth: u32, fork_height: u32 },
+}
+
+// Everything derived from replaying the active chain
+struct ChainState {
+    utxos: UtxoSet,
+    tree: BlockTree,
+    undo: HashMap<[u8; 32], BlockUndo>,
 }
 
 impl Blockchain {
-    pub fn new(difficulty: u32) -> Self {
+    pub fn new(params: ChainParams) -> Self {
+        // The genesis block alone in memory can't fail to store or validate
+        Self::with_store(Box::new(MemoryStore::new()), params).unwrap()
+    }
+
+    // Load the chain stored in `dir`, or start one there from the genesis block
+    pub fn open(dir: impl AsRef<Path>, params: ChainParams) -> Result<Self, ChainError> {
+        Self::with_store(Box::new(FileStore::open(dir)?), params)
+    }
+
+    // Use the chain already in `store`, or start one from the genesis block, with the
+    // difficulty adjustment `params` describes
+    pub fn with_store(store: Box<dyn ChainStore>, params: ChainParams) -> Result<Self, ChainError> {
+        let difficulty = params.difficulty();
+        Self::with_difficulty(store, params, difficulty)
+    }
+
+    // Stored blocks are revalidated under `params` and `difficulty` before anything is
+    // trusted
+    pub fn with_difficulty(
+        mut store: Box<dyn ChainStore>,
+        params: ChainParams,
+        difficulty: Box<dyn DifficultyAlgorithm>,
+    ) -> Result<Self, ChainError> {
+        if store.is_empty() {
+            store.insert(params.genesis.clone())?;
+        }
+
         let mut blockchain = Self {
-            blocks: Vec::new(),
-            pending_transactions: Vec::new(),
-            miner: Miner::new(difficulty),
+            store,
+            tree: BlockTree::new(),
+            undo: HashMap::new(),
+            mempool: Mempool::default(),
+            utxos: UtxoSet::new(),
+            miner: Miner::new(difficulty.pow_limit()),
+            params,
+            clock: Arc::new(SystemClock),
+            difficulty,
         };
+        let state = blockchain.replay()?;
+        blockchain.utxos = state.utxos;
+        blockchain.tree = state.tree;
+        blockchain.undo = state.undo;
 
-        // Create and add genesis block
-        let genesis = genesis_block();
-        blockchain.blocks.push(genesis);
-
-        blockchain
+        Ok(blockchain)
     }
 
-    pub fn add_transaction(&mut self, transaction: Transaction) {
-        self.pending_transactions.push(transaction);
+    pub fn add_transaction(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        let now = self.clock.now();
+        self.mempool.expire(now);
+        let fee = self.check_transaction(&transaction)?;
+        self.mempool.check_fee(fee, transaction.size())?;
+
+        // The mempool may turn it away as the cheapest entry when full
+        let summary = transaction.to_string();
+        let evicted = self.mempool.add(transaction, fee, now)?;
         println!(
-            "➕ Added transaction: {}",
-            self.pending_transactions.last().unwrap()
+            "➕ Added transaction: {} paying {} satoshis in fees",
+            summary, fee
         );
+        for evicted in evicted {
+            println!("🗑️  Evicted transaction: {}", evicted.transaction);
+        }
+        Ok(())
+    }
+
+    // Validate a transaction against confirmed outputs plus those in the mempool.
+    // Returns the fee it pays.
+    pub fn check_transaction(&self, transaction: &Transaction) -> Result<u64, TransactionError> {
+        if transaction.is_coinbase() {
+            return Err(TransactionError::Coinbase);
+        }
+        if transaction.inputs.is_empty() {
+            return Err(TransactionError::NoInputs);
+        }
+        if transaction.outputs.is_empty() {
+            return Err(TransactionError::NoOutputs);
+        }
+        if transaction.outputs.iter().any(|output| output.value == 0) {
+            return Err(TransactionError::ZeroAmount);
+        }
+
+        let txid = transaction.txid();
+        let confirmed = (0..transaction.outputs.len())
+            .any(|vout| self.utxos.contains(&OutPoint::new(txid, vout as u32)));
+        if confirmed || self.mempool.contains(&txid) {
+            return Err(TransactionError::DuplicateId { txid });
+        }
+
+        // It can be mined no sooner than the next block
+        let next_height = self.store.len() as u32;
+        if !transaction.is_final(next_height, self.median_time_past()) {
+            return Err(TransactionError::NonFinal);
+        }
+        let mut spent = HashSet::new();
+        let mut available = 0u64;
+        // Unconfirmed coins count as created in the next block
+        let mut coin_heights = Vec::new();
+        for (index, input) in transaction.inputs.iter().enumerate() {
+            let outpoint = input.previous_output;
+            if !spent.insert(outpoint) {
+                return Err(TransactionError::DuplicateInput { outpoint });
+            }
+            if let Some(conflicting_txid) = self.mempool.spender(&outpoint) {
+                return Err(TransactionError::ConflictingSpend {
+                    outpoint,
+                    conflicting_txid,
+                });
+            }
+            let output = match self.utxos.get(&outpoint) {
+                Some(entry) if !entry.is_mature(next_height, self.params.coinbase_maturity) => {
+                    return Err(TransactionError::ImmatureCoinbase { outpoint });
+                }
+                Some(entry) => {
+                    coin_heights.push(entry.height);
+                    &entry.output
+                }
+                None => {
+                    coin_heights.push(next_height);
+                    self.mempool
+                        .get(&outpoint.txid)
+                        .and_then(|entry| entry.transaction.outputs.get(outpoint.vout as usize))
+                        .ok_or(TransactionError::MissingInput { outpoint })?
+                }
+            };
+            let checker = TransactionChecker::new(transaction, index);
+            verify_script(&input.script_sig, &output.script_pubkey, &checker).map_err(|error| {
+                TransactionError::ScriptFailed {
+                    input: index,
+                    error,
+                }
+            })?;
+            available = available
+                .checked_add(output.value)
+                .filter(|value| *value <= MAX_MONEY)
+                .ok_or(TransactionError::ValueOutOfRange)?;
+        }
+
+        let tip = self.store.tip().unwrap();
+        if !transaction.sequence_locks_met(
+            &coin_heights,
+            next_height,
+            self.median_time_past(),
+            |height| self.tree.ancestor_median_time_past(&tip, height),
+        ) {
+            return Err(TransactionError::SequenceLocked);
+        }
+
+        let required = transaction
+            .total_output()
+            .ok_or(TransactionError::ValueOutOfRange)?;
+        if required > available {
+            return Err(TransactionError::InsufficientFunds {
+                available,
+                required,
+            });
+        }
+
+        Ok(available - required)
+    }
+
+    // Unspent outputs of `address` that the next block could spend, including unconfirmed
+    // change in the mempool
+    fn spendable_outputs(&self, address: &str) -> Vec<(OutPoint, u64)> {
+        let next_height = self.store.len() as u32;
+        let confirmed = self
+            .utxos
+            .outputs_for(address)
+            .filter(|(_, entry)| entry.is_mature(next_height, self.params.coinbase_maturity))
+            .map(|(outpoint, entry)| (*outpoint, entry.output.value));
+        let script_pubkey = wallet::script_pubkey(address);
+        let pending = self.mempool.entries().into_iter().flat_map(|entry| {
+            let txid = entry.txid;
+            entry
+                .transaction
+                .outputs
+                .iter()
+                .enumerate()
+                .filter(|(_, output)| output.script_pubkey == script_pubkey)
+                .map(move |(vout, output)| (OutPoint::new(txid, vout as u32), output.value))
+        });
+
+        confirmed
+            .chain(pending)
+            .filter(|(outpoint, _)| self.mempool.spender(outpoint).is_none())
+            .collect()
+    }
+
+    // Build a payment from `from` to `to` paying the mempool's minimum relay fee rate,
+    // returning any excess to `from` as change, and sign it with `from`'s wallet key.
+    // Returns None if `from` can't cover the amount and fee.
+    pub fn create_transaction(&self, from: &str, to: &str, amount: u64) -> Option<Transaction> {
+        self.create_transaction_with_fee_rate(from, to, amount, self.mempool.min_fee_rate)
+    }
+
+    // Like `create_transaction`, paying `fee_rate` satoshis per 1000 bytes
+    pub fn create_transaction_with_fee_rate(
+        &self,
+        from: &str,
+        to: &str,
+        amount: u64,
+        fee_rate: u64,
+    ) -> Option<Transaction> {
+        let mut outputs = self.spendable_outputs(from);
+        // Spend the largest outputs first to keep transactions small
+        outputs.sort_by_key(|(_, value)| std::cmp::Reverse(*value));
+
+        // The fee depends on the size, which depends on how many inputs the fee takes
+        let mut fee = 0;
+        loop {
+            let total = amount
+                .checked_add(fee)
+                .filter(|total| *total <= MAX_MONEY)?;
+            let mut inputs = Vec::new();
+            let mut input_value = 0u64;
+            for (outpoint, value) in &outputs {
+                if input_value >= total {
+                    break;
+                }
+                inputs.push(TxIn::new(*outpoint));
+                input_value = input_value.checked_add(*value)?;
+            }
+            if input_value < total {
+                return None;
+            }
+
+            let mut tx_outputs = vec![TxOut::to_address(amount, to)];
+            if input_value > total {
+                tx_outputs.push(TxOut::to_address(input_value - total, from));
+            }
+
+            let mut tx = Transaction::new(inputs, tx_outputs);
+            wallet::sign_inputs(&mut tx, from);
+            let required = mempool::fee_for_size(fee_rate, tx.size());
+            if fee >= required {
+                return Some(tx);
+            }
+            fee = required;
+        }
     }
 
     pub fn mine_pending_transactions(&mut self) -> Result<(), MiningError> {
-        if self.pending_transactions.is_empty() {
+        if self.mempool.is_empty() {
             println!("⚠️  No pending transactions to mine");
             return Ok(());
         }
 
-        let previous_block = self.blocks.last().unwrap();
-        let previous_hash = previous_block.hash();
-        let height = (self.blocks.len()) as u32;
+        let previous_hash = self.store.tip().unwrap();
+        let height = self.store.len() as u32;
 
+        // Transactions stay in the mempool until the block connects
+        let template = self.block_template();
         println!(
-            "\n⛏️  Mining block #{} with {} transactions...",
+            "\n⛏️  Mining block #{} with {} of {} transactions ({} satoshis in fees, weight {})...",
             height,
-            self.pending_transactions.len()
+            template.transactions.len(),
+            self.mempool.len(),
+            template.fees,
+            template.weight
         );
 
-        let transactions = std::mem::take(&mut self.pending_transactions);
-        let new_block = self
-            .miner
-            .mine_block(1, previous_hash, transactions, height)?;
-
-        self.blocks.push(new_block);
+        let transactions = template.transactions;
+        let maturity = self.params.coinbase_maturity;
+        let fees = self
+            .utxos
+            .fees(&transactions, height, maturity)
+            .map_err(|err| {
+                println!("❌ Pending transactions are invalid: {}", err);
+                MiningError::InvalidBlock
+            })?;
+
+        let reward = self.params.block_subsidy(height) + fees;
+        let mut block = self.miner.create_block(
+            self.params.block_version,
+            previous_hash,
+            transactions,
+            height,
+            reward,
+        );
+        block.header.bits = self.next_bits();
+        block.header.timestamp = self.clock.now().max(self.median_time_past() + 1);
+        let mined = self.miner.mine(block)?;
+
+        match self.submit_block(mined.block) {
+            Ok(_) => {}
+            Err(ChainError::Store(err)) => return Err(err.into()),
+            Err(ChainError::Invalid(err)) => {
+                println!("❌ Mined block is invalid: {}", err);
+                return Err(MiningError::InvalidBlock);
+            }
+        }
         println!("✅ Block #{} added to blockchain!", height);
 
         Ok(())
     }
 
-    pub fn get_latest_block(&self) -> &Block {
-        self.blocks.last().unwrap()
+    // The highest-fee set of mempool transactions that fits in the next block
+    pub fn block_template(&self) -> BlockTemplate {
+        let max_weight = self.params.max_block_size * WITNESS_SCALE_FACTOR;
+        BlockAssembler::new(max_weight).assemble(&self.mempool)
     }
 
-    pub fn validate_chain(&self) -> bool {
-        for i in 1..self.blocks.len() {
-            let current_block = &self.blocks[i];
-            let previous_block = &self.blocks[i - 1];
+    // Number of blocks, including genesis
+    pub fn block_count(&self) -> usize {
+        self.store.len()
+    }
 
-            // Validate block hash
-            if !self.miner.validate_block(current_block) {
-                println!("❌ Block #{} has invalid hash", current_block.height);
-                return false;
-            }
+    pub fn get_block(&self, hash: &[u8; 32]) -> Result<Option<Block>, StoreError> {
+        self.store.get(hash)
+    }
 
-            // Validate chain linkage
-            if current_block.header.previous_hash != previous_block.hash() {
-                println!(
-                    "❌ Block #{} has invalid previous hash",
-                    current_block.height
-                );
-                return false;
-            }
-        }
+    pub fn get_block_by_height(&self, height: u32) -> Result<Option<Block>, StoreError> {
+        self.store.get_by_height(height)
+    }
 
-        println!("✅ Blockchain is valid!");
-        true
+    // The store always holds at least the genesis block
+    pub fn get_latest_block(&self) -> Result<Block, StoreError> {
+        self.get_block_by_height(self.store.len() as u32 - 1)
+            .map(Option::unwrap)
     }
 
-    pub fn print_chain(&self) {
-        println!("\n📊 BLOCKCHAIN STATUS");
-        println!("=====================================");
-        println!("Total blocks: {}", self.blocks.len());
-        println!(
-            "Total pending transactions: {}",
-            self.pending_transactions.len()
-        );
-        println!("Current difficulty: {}", self.miner.difficulty_target);
+    // Median timestamp of the last MEDIAN_TIME_SPAN blocks, which the next block must exceed
+    pub fn median_time_past(&self) -> u32 {
+        self.tree.median_time_past(&self.store.tip().unwrap())
+    }
 
-        for block in &self.blocks {
-            println!("\n{}", block);
-        }
+    pub fn params(&self) -> &ChainParams {
+        &self.params
     }
 
-    pub fn get_balance(&self, address: &str) -> u64 {
-        let mut balance = 0u64;
+    // Use `clock` for validation and mining from now on
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.miner.clock = clock.clone();
+        self.clock = clock;
+    }
 
-        for block in &self.blocks {
-            for transaction in &block.transactions {
-                // Add to balance if address is in outputs
-                if transaction.outputs.contains(&address.to_string()) {
-                    balance += transaction.amount;
-                }
-                // Subtract from balance if address is in inputs (simplified)
-                if transaction.inputs.contains(&address.to_string()) {
-                    balance = balance.saturating_sub(transaction.amount);
-                }
-            }
-        }
+    // nBits the next block on the active chain must commit to
+    pub fn next_bits(&self) -> u32 {
+        self.difficulty
+            .next_bits(&self.tree, &self.store.tip().unwrap())
+    }
 
-        balance
+    // Total work of the active chain
+    pub fn chainwork(&self) -> u128 {
+        self.tree.get(&self.store.tip().unwrap()).unwrap().chainwork
     }
-}
 
-// Mining module
-#[derive(Debug)]
-pub struct Miner {
-    pub difficulty_target: u32,
-}
+    // Accept a block building on any known block. The active chain switches to the
+    // block's branch once that has strictly more work, as in Bitcoin.
+    pub fn submit_block(&mut self, block: Block) -> Result<ChainUpdate, ChainError> {
+        let hash = block.hash();
+        let previous_hash = block.header.previous_hash;
+        let fail = |kind| ValidationError {
+            height: block.height,
+            hash,
+            kind,
+        };
 
-impl Miner {
-    pub fn new(difficulty_target: u32) -> Self {
-        Self { difficulty_target }
-    }
+        if self.tree.contains(&hash) {
+            return Err(fail(ValidationErrorKind::DuplicateBlock).into());
+        }
+        let Some(parent) = self.tree.get(&previous_hash) else {
+            return Err(fail(ValidationErrorKind::UnknownParent).into());
+        };
+        if self.tree.is_invalid(&previous_hash) {
+            self.tree.mark_invalid(&hash);
+            return Err(fail(ValidationErrorKind::InvalidParent).into());
+        }
 
-    pub fn mine_block(
-        &self,
-        version: u32,
-        previous_hash: [u8; 32],
-        transactions: Vec<Transaction>,
-        height: u32,
-    ) -> Result<Block, MiningError> {
-        let mut block = Block::new(
-            version,
-            previous_hash,
-            transactions,
-            self.difficulty_target,
-            height,
-        );
+        self.check_block(&self.tree, &block, parent.height + 1, previous_hash)
+            .map_err(fail)?;
+        // Checked on arrival only, as in Bitcoin: stored blocks only fall further behind
+        // the clock, and a block too far ahead now may be acceptable later
+        let latest = self.clock.now().saturating_add(MAX_FUTURE_BLOCK_TIME);
+        if block.header.timestamp > latest {
+            return Err(fail(ValidationErrorKind::TimestampTooFarAhead { latest }).into());
+        }
 
-        println!(
-            "🎯 Target difficulty: {} leading zero bits",
-            self.difficulty_target
-        );
-        println!("🔗 Previous hash: {}", hex::encode(previous_hash));
-        println!("🌳 Merkle root: {}", hex::encode(block.header.merkle_root));
+        let chainwork = self.tree.insert(&block).chainwork;
+        self.tree.add_side_block(block);
+        if chainwork <= self.chainwork() {
+            return Ok(ChainUpdate::SideBranch);
+        }
 
-        let start_time = SystemTime::now();
+        let (depth, fork_height) = self.activate_branch(hash)?;
 
-        loop {
-            let hash = block.hash();
+        Ok(if depth == 0 {
+            ChainUpdate::Extended
+        } else {
+            ChainUpdate::Reorganized { depth, fork_height }
+        })
+    }
 
-            if self.meets_difficulty_target(&hash) {
-                let mining_time = start_time.elapsed().unwrap();
-                println!(
-                    "⚡ Block mined! Nonce: {}, Time: {:.2}s",
-                    block.header.nonce,
-                    mining_time.as_secs_f64()
-                );
-                println!("🏆 Block hash: {}", hex::encode(hash));
-                return Ok(block);
-            }
-
-            // Increment nonce and try again
-            if block.header.nonce == u32::MAX {
-                // If nonce overflows, update timestamp and reset nonce
-                block.header.timestamp = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-                block.header.nonce = 0;
-            } else {
-                block.header.nonce += 1;
-            }
+    // Make `tip` the active tip: disconnect back to its branch's fork point, then
+    // connect the branch. If a branch block turns out to be invalid or the store
+    // fails, the previous chain is restored. Returns the number of blocks disconnected
+    // and the fork height.
+    fn activate_branch(&mut self, tip: [u8; 32]) -> Result<(u32, u32), ChainError> {
+        let mut branch = Vec::new();
+        let mut fork = tip;
+        while !self.undo.contains_key(&fork) {
+            branch.push(fork);
+            fork = self.tree.get(&fork).unwrap().previous_hash;
+        }
+        branch.reverse();
+
+        let mut disconnected = Vec::new();
+        if let Err(err) = self.disconnect_to(&fork, &mut disconnected) {
+            self.reconnect(disconnected)?;
+            return Err(err.into());
+        }
+        let depth = disconnected.len() as u32;
+        let fork_height = self.tree.get(&fork).unwrap().height;
+        if depth > 0 {
+            println!(
+                "🔀 Reorganizing: disconnecting {} blocks above height {}",
+                depth, fork_height
+            );
+        }
 
-            // Print progress every 100k attempts for better visibility
-            if block.header.nonce % 100_000 == 0 && block.header.nonce > 0 {
-                println!("⛏️  Mining... Tried {} nonces", block.header.nonce);
+        let mut connected = Vec::new();
+        for (i, hash) in branch.iter().enumerate() {
+            let block = self.tree.take_side_block(hash).unwrap();
+            let mut utxos = std::mem::take(&mut self.utxos);
+            let result = self.connect_transactions(&self.tree, &mut utxos, &block);
+            self.utxos = utxos;
+
+            let err = match result {
+                Ok(undo) => match self.store.insert(block.clone()) {
+                    Ok(()) => {
+                        self.undo.insert(*hash, undo);
+                        connected.push(block);
+                        continue;
+                    }
+                    // The block is still valid, so it stays a candidate
+                    Err(err) => {
+                        self.utxos.disconnect_block(&block, &undo);
+                        self.tree.add_side_block(block);
+                        ChainError::Store(err)
+                    }
+                },
+                Err(kind) => {
+                    for invalid in &branch[i..] {
+                        self.tree.mark_invalid(invalid);
+                    }
+                    ValidationError {
+                        height: block.height,
+                        hash: *hash,
+                        kind,
+                    }
+                    .into()
+                }
+            };
+
+            // Put the old chain back; it was valid before, so it still is
+            let mut reverted = Vec::new();
+            if let Err(store_err) = self.disconnect_to(&fork, &mut reverted) {
+                self.update_mempool(disconnected, &[]);
+                return Err(store_err.into());
             }
+            self.reconnect(disconnected)?;
+            return Err(err);
         }
+
+        self.update_mempool(disconnected, &connected);
+
+        Ok((depth, fork_height))
     }
 
-    fn meets_difficulty_target(&self, hash: &[u8; 32]) -> bool {
-        // Check if hash has the required number of leading zeros
-        let leading_zeros = self.count_leading_zero_bits(hash);
-        leading_zeros >= self.difficulty_target
+    // Disconnect active blocks down to `fork`, pushing them onto `disconnected` tip
+    // first. They stay in the block tree as a side branch. On failure, `disconnected`
+    // holds the blocks taken off so far and the chain is consistent at its new tip.
+    fn disconnect_to(
+        &mut self,
+        fork: &[u8; 32],
+        disconnected: &mut Vec<Block>,
+    ) -> Result<(), StoreError> {
+        while self.store.tip().as_ref() != Some(fork) {
+            let block = self.store.pop()?.unwrap();
+            let hash = block.hash();
+            let undo = self.undo.remove(&hash).unwrap();
+            self.utxos.disconnect_block(&block, &undo);
+            self.tree.add_side_block(block.clone());
+            disconnected.push(block);
+        }
+        Ok(())
     }
 
-    fn count_leading_zero_bits(&self, hash: &[u8; 32]) -> u32 {
-        let mut count = 0;
-        for byte in hash.iter() {
-            if *byte == 0 {
-                count += 8;
-            } else {
-                count += byte.leading_zeros();
-                break;
+    // Connect again blocks `disconnect_to` took off, given tip first. If the store
+    // fails, those left over stay a side branch and their transactions go back to the
+    // mempool.
+    fn reconnect(&mut self, mut disconnected: Vec<Block>) -> Result<(), ChainError> {
+        while let Some(block) = disconnected.pop() {
+            if let Err(err) = self.connect_valid_block(&block) {
+                disconnected.push(block);
+                self.update_mempool(disconnected, &[]);
+                return Err(err.into());
             }
         }
-        count
+        Ok(())
     }
 
-    pub fn validate_block(&self, block: &Block) -> bool {
+    fn connect_valid_block(&mut self, block: &Block) -> Result<(), StoreError> {
+        self.store.insert(block.clone())?;
         let hash = block.hash();
-        self.meets_difficulty_target(&hash)
+        let undo = self
+            .utxos
+            .connect_block(block, self.params.coinbase_maturity)
+            .unwrap();
+        self.tree.take_side_block(&hash);
+        self.undo.insert(hash, undo);
+        Ok(())
     }
 
-    // Adjust difficulty based on mining time
-    pub fn adjust_difficulty(&mut self, actual_time: u64, target_time: u64) {
-        let ratio = actual_time as f64 / target_time as f64;
+    // Drop mempool transactions the connected blocks confirmed or conflict with. After
+    // a reorg, transactions from the disconnected blocks get another chance to confirm
+    // and everything waiting is rechecked against the new chain.
+    fn update_mempool(&mut self, disconnected: Vec<Block>, connected: &[Block]) {
+        for block in connected {
+            self.mempool.remove_for_block(block);
+        }
+        let now = self.clock.now();
+        self.mempool.expire(now);
+        if disconnected.is_empty() {
+            return;
+        }
 
-        if ratio > 2.0 {
-            // Too slow, decrease difficulty
-            if self.difficulty_target > 1 {
-                self.difficulty_target -= 1;
-                println!("📉 Difficulty decreased to: {}", self.difficulty_target);
+        let resurrected = disconnected
+            .into_iter()
+            .rev()
+            .flat_map(|block| block.transactions.into_iter().skip(1))
+            .map(|tx| (tx, now));
+        let waiting: Vec<_> = self
+            .mempool
+            .clear()
+            .into_iter()
+            .map(|entry| (entry.transaction, entry.time))
+            .collect();
+        for (tx, time) in resurrected.chain(waiting) {
+            if let Ok(fee) = self.check_transaction(&tx) {
+                // Anything evicted paid the least, so it's no loss
+                let _ = self.mempool.add(tx, fee, time);
             }
-        } else if ratio < 0.5 {
-            // Too fast, increase difficulty
-            self.difficulty_target += 1;
-            println!("📈 Difficulty increased to: {}", self.difficulty_target);
         }
     }
-}
-
-#[derive(Debug)]
-pub enum MiningError {
-    InvalidBlock,
-    MiningFailed,
-}
 
-impl std::fmt::Display for MiningError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            MiningError::InvalidBlock => write!(f, "Invalid block"),
-            MiningError::MiningFailed => write!(f, "Mining failed"),
-        }
+    // A block the store can't read back fails validation as surely as a broken rule
+    pub fn validate_chain(&self) -> Result<(), ChainError> {
+        self.replay().map(|_| ())
     }
-}
 
-impl std::error::Error for MiningError {}
-
-// Helper functions
-pub fn genesis_block() -> Block {
-    let genesis_transaction = Transaction::new(
-        "genesis".to_string(),
-        vec![],
-        vec!["1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa".to_string()],
-        50_00000000, // 50 BTC in satoshis
-    );
+    // Replay every block against a fresh UTXO set, stopping at the first broken rule
+    fn replay(&self) -> Result<ChainState, ChainError> {
+        let mut state = ChainState {
+            utxos: UtxoSet::new(),
+            tree: BlockTree::new(),
+            undo: HashMap::new(),
+        };
+        let mut previous_hash = None;
 
-    Block::new(1, [0; 32], vec![genesis_transaction], 8, 0) // Genesis block is height 0
+        for (i, block) in self.store.iter().enumerate() {
+            let block = block?;
+            let hash = block.hash();
+            let fail = |kind| ValidationError {
+                height: block.height,
+                hash,
+                kind,
+            };
+
+            match previous_hash {
+                Some(previous_hash) => {
+                    self.check_block(&state.tree, &block, i as u32, previous_hash)
+                }
+                None => self.check_genesis(&block),
+            }
+            .map_err(fail)?;
+            let undo = self
+                .connect_transactions(&state.tree, &mut state.utxos, &block)
+                .map_err(fail)?;
+
+            state.tree.insert(&block);
+            state.undo.insert(hash, undo);
+            previous_hash = Some(hash);
+        }
+
+        Ok(state)
+    }
+
+    // Like Bitcoin Core, the genesis block isn't validated, only required to be the
+    // one the chain's parameters name
+    fn check_genesis(&self, block: &Block) -> Result<(), ValidationErrorKind> {
+        if block.height != 0 {
+            return Err(ValidationErrorKind::BadHeight { expected: 0 });
+        }
+        if *block != self.params.genesis {
+            return Err(ValidationErrorKind::WrongGenesis);
+        }
+        Ok(())
+    }
+
+    // Rules a block must meet given its parent's hash, which `tree` must hold:
+    // everything but whether its transactions spend valid outputs
+    fn check_block(
+        &self,
+        tree: &BlockTree,
+        block: &Block,
+        height: u32,
+        previous_hash: [u8; 32],
+    ) -> Result<(), ValidationErrorKind> {
+        if block.height != height {
+            return Err(ValidationErrorKind::BadHeight { expected: height });
+        }
+        let size = block.size();
+        if size > self.params.max_block_size {
+            return Err(ValidationErrorKind::BlockTooLarge {
+                size,
+                max: self.params.max_block_size,
+            });
+        }
+        let (merkle_root, mutated) = merkle_root(&block.txids());
+        if mutated {
+            return Err(ValidationErrorKind::MutatedMerkleTree);
+        }
+        if block.header.merkle_root != merkle_root {
+            return Err(ValidationErrorKind::BadMerkleRoot);
+        }
+
+        let expected_bits = self.difficulty.next_bits(tree, &previous_hash);
+        if block.header.bits != expected_bits {
+            return Err(ValidationErrorKind::BadBits {
+                expected: expected_bits,
+            });
+        }
+        if !self.miner.check_proof_of_work(block) {
+            return Err(ValidationErrorKind::BadProofOfWork);
+        }
+        if block.header.previous_hash != previous_hash {
+            return Err(ValidationErrorKind::BadPreviousHash);
+        }
+        let median = tree.median_time_past(&previous_hash);
+        if block.header.timestamp <= median {
+            return Err(ValidationErrorKind::TimestampTooEarly {
+                median_time_past: median,
+            });
+        }
+
+        match block.transactions.first() {
+            Some(tx) if tx.is_coinbase() => {}
+            _ => return Err(ValidationErrorKind::MissingCoinbase),
+        }
+        if block.transactions[1..].iter().any(|tx| tx.is_coinbase()) {
+            return Err(ValidationErrorKind::MultipleCoinbases);
+        }
+        if let Some(tx) = block
+            .transactions
+            .iter()
+            .find(|tx| !tx.is_final(height, median))
+        {
+            return Err(ValidationErrorKind::NonFinalTransaction { txid: tx.txid() });
+        }
+
+        Ok(())
+    }
+
+    // Apply the block's transactions to `utxos` and check what its coinbase claims.
+    // `utxos` is unchanged on failure.
+    fn connect_transactions(
+        &self,
+        tree: &BlockTree,
+        utxos: &mut UtxoSet,
+        block: &Block,
+    ) -> Result<BlockUndo, ValidationErrorKind> {
+        // Relative lock times count from the blocks that created the coins, which are
+        // in `utxos` unless they're earlier in this block
+        let previous_hash = block.header.previous_hash;
+        let time = tree.median_time_past(&previous_hash);
+        for tx in block.transactions.iter().skip(1) {
+            let coin_heights: Vec<u32> = tx
+                .inputs
+                .iter()
+                .map(|input| {
+                    utxos
+                        .get(&input.previous_output)
+                        .map_or(block.height, |entry| entry.height)
+                })
+                .collect();
+            let ancestor_time = |height| tree.ancestor_median_time_past(&previous_hash, height);
+            if !tx.sequence_locks_met(&coin_heights, block.height, time, ancestor_time) {
+                return Err(ValidationErrorKind::SequenceLocked { txid: tx.txid() });
+            }
+        }
+
+        let undo = utxos
+            .connect_block(block, self.params.coinbase_maturity)
+            .map_err(ValidationErrorKind::InvalidTransaction)?;
+
+        let Some(paid) = block.transactions[0].total_output() else {
+            utxos.disconnect_block(block, &undo);
+            return Err(ValidationErrorKind::BadCoinbase);
+        };
+        let allowed = self.params.block_subsidy(block.height) + undo.fees;
+        // Genesis mints coins out of nothing, so only later coinbases are bounded
+        if block.height > 0 && paid > allowed {
+            utxos.disconnect_block(block, &undo);
+            return Err(ValidationErrorKind::CoinbaseOverpays { paid, allowed });
+        }
+
+        Ok(undo)
+    }
+
+    pub fn print_chain(&self) {
+        println!("\n📊 BLOCKCHAIN STATUS");
+        println!("=====================================");
+        println!("Total blocks: {}", self.store.len());
+        println!("Transactions in mempool: {}", self.mempool.len());
+        println!("Unspent outputs: {}", self.utxos.len());
+        let bits = self.next_bits();
+        println!(
+            "Current difficulty: {:.8} (bits 0x{:08x})",
+            Target::from_compact(bits).map_or(0.0, |target| target.difficulty()),
+            bits
+        );
+
+        for block in self.store.iter() {
+            match block {
+                Ok(block) => println!("\n{}", block),
+                Err(err) => println!("\n❌ {}", err),
+            }
+        }
+    }
+
+    // Confirmed balance of `address`
+    pub fn get_balance(&self, address: &str) -> u64 {
+        self.utxos.balance(address)
+    }
+}
+
+// Mining module
+#[derive(Debug)]
+pub struct Miner {
+    pub target: Target,
+    pub reward_address: String,
+    pub threads: usize,
+    pub max_duration: Option<Duration>,
+    pub max_hashes: Option<u64>,
+    // Stamps new blocks and times searches
+    pub clock: Arc<dyn Clock>,
+}
+
+// Result of a successful search, with the worker that found it
+#[derive(Debug)]
+pub struct MinedBlock {
+    pub block: Block,
+    pub worker: usize,
+    pub hashes: u64,
+}
+
+// Handle for abandoning a search from another thread, e.g. when a new block arrives
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
 }
 
-// Sample transaction generator
-pub fn create_sample_transactions() -> Vec<Transaction> {
-    vec![
-        Transaction::new(
-            "tx_001".to_string(),
-            vec!["1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa".to_string()],
-            vec!["1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2".to_string()],
-            10_00000000, // 10 BTC
-        ),
-        Transaction::new(
-            "tx_002".to_string(),
-            vec!["1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa".to_string()],
-            vec!["3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy".to_string()],
-            5_00000000, // 5 BTC
-        ),
-        Transaction::new(
-            "tx_003".to_string(),
-            vec!["1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2".to_string()],
-            vec!["1JfbZRwdDHKZmuiZgYArJZhcuuzuw2HuMu".to_string()],
-            3_50000000, // 3.5 BTC
-        ),
-        Transaction::new(
-            "tx_004".to_string(),
-            vec!["3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy".to_string()],
-            vec!["1dice8EMZmqKvrGE4Qc9bUFf9PX3xaYDp".to_string()],
-            2_25000000, // 2.25 BTC
-        ),
-        Transaction::new(
-            "tx_005".to_string(),
-            vec!["1JfbZRwdDHKZmuiZgYArJZhcuuzuw2HuMu".to_string()],
-            vec!["1BoatSLRHtKNngkdXEeobR76b53LETtpyT".to_string()],
-            1_00000000, // 1 BTC
-        ),
-    ]
-}
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// Workers publish their hash counts in batches to keep the shared counter uncontended
+const HASH_COUNT_BATCH: u64 = 10_000;
+const PROGRESS_INTERVAL: u64 = 100_000;
+
+impl Miner {
+    pub fn new(target: Target) -> Self {
+        let threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+
+        Self {
+            target,
+            reward_address: DEFAULT_REWARD_ADDRESS.to_string(),
+            threads,
+            max_duration: None,
+            max_hashes: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    // Compact form of the target, as committed to in block headers
+    pub fn bits(&self) -> u32 {
+        self.target.to_compact()
+    }
+
+    // Unmined block paying `reward` to the reward address through a coinbase
+    pub fn create_block(
+        &self,
+        version: u32,
+        previous_hash: [u8; 32],
+        transactions: Vec<Transaction>,
+        height: u32,
+        reward: u64,
+    ) -> Block {
+        let coinbase = Transaction::coinbase(height, 0, &self.reward_address, reward);
+        let mut block_transactions = vec![coinbase];
+        block_transactions.extend(transactions);
+        let mut block = Block::new(
+            version,
+            previous_hash,
+            block_transactions,
+            self.bits(),
+            height,
+        );
+        block.header.timestamp = self.clock.now();
+        block
+    }
+
+    pub fn mine_block(
+        &self,
+        version: u32,
+        previous_hash: [u8; 32],
+        transactions: Vec<Transaction>,
+        height: u32,
+        reward: u64,
+    ) -> Result<MinedBlock, MiningError> {
+        self.mine(self.create_block(version, previous_hash, transactions, height, reward))
+    }
+
+    pub fn mine(&self, block: Block) -> Result<MinedBlock, MiningError> {
+        self.mine_with_cancel(block, &CancellationToken::new())
+    }
+
+    // Search for a nonce satisfying the block's own nBits. Worker `w` of `n` tries
+    // nonces w, w + n, w + 2n, ... for each extranonce in turn, so no two workers ever
+    // hash the same header, and a hash budget of k covers exactly nonces 0..k.
+    pub fn mine_with_cancel(
+        &self,
+        block: Block,
+        cancel: &CancellationToken,
+    ) -> Result<MinedBlock, MiningError> {
+        println!(
+            "🎯 Target: {} (bits 0x{:08x})",
+            block.header.target().unwrap_or(Target::ZERO),
+            block.header.bits
+        );
+        println!(
+            "🔗 Previous hash: {}",
+            hash_to_hex(&block.header.previous_hash)
+        );
+        println!("🌳 Merkle root: {}", hash_to_hex(&block.header.merkle_root));
+
+        let threads = self.threads.clamp(1, u32::MAX as usize);
+        let start_time = self.clock.since_epoch();
+        let search = NonceSearch {
+            target: block.header.target().ok_or(MiningError::InvalidBlock)?,
+            stride: threads as u32,
+            stop: AtomicBool::new(false),
+            total_hashes: AtomicU64::new(0),
+            cancel,
+            clock: self.clock.as_ref(),
+            deadline: self.max_duration.map(|duration| start_time + duration),
+        };
+
+        let results: Vec<(u64, WorkerOutcome)> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|worker| {
+                    let block = block.clone();
+                    let budget = self.max_hashes.map(|max| {
+                        max / threads as u64 + u64::from((worker as u64) < max % threads as u64)
+                    });
+                    let search = &search;
+                    scope.spawn(move || search.run(block, worker as u32, budget))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        let attempts = results.iter().map(|(count, _)| count).sum();
+        let exhausted = results
+            .iter()
+            .all(|(_, outcome)| matches!(outcome, WorkerOutcome::Exhausted));
+        let winner =
+            results
+                .into_iter()
+                .enumerate()
+                .find_map(|(worker, (_, outcome))| match outcome {
+                    WorkerOutcome::Found(block) => Some((worker, block)),
+                    _ => None,
+                });
+
+        let Some((worker, block)) = winner else {
+            return Err(if cancel.is_cancelled() {
+                MiningError::Cancelled { attempts }
+            } else if search.past_deadline() {
+                MiningError::Timeout { attempts }
+            } else if exhausted {
+                MiningError::NonceSpaceExhausted { attempts }
+            } else {
+                MiningError::HashLimitReached { attempts }
+            });
+        };
+
+        let mining_time = self.clock.since_epoch().saturating_sub(start_time);
+        println!(
+            "⚡ Block mined! Nonce: {}, Extranonce: {}, Worker: {}/{}, Hashes: {}, Time: {:.2}s",
+            block.header.nonce,
+            block
+                .transactions
+                .first()
+                .and_then(|tx| tx.extranonce())
+                .unwrap_or(0),
+            worker,
+            threads,
+            attempts,
+            mining_time.as_secs_f64()
+        );
+        println!("🏆 Block hash: {}", hash_to_hex(&block.hash()));
+
+        Ok(MinedBlock {
+            block,
+            worker,
+            hashes: attempts,
+        })
+    }
+
+    // Checks against the target the header commits to, after compact rounding
+    fn meets_target(&self, header: &BlockHeader, hash: &[u8; 32]) -> bool {
+        header.target().is_some_and(|target| target.is_met_by(hash))
+    }
+
+    // Proof of work against the header's own nBits, whatever the current target
+    pub fn check_proof_of_work(&self, block: &Block) -> bool {
+        self.meets_target(&block.header, &block.hash())
+    }
+}
+
+// State shared by the workers of one nonce search
+struct NonceSearch<'a> {
+    target: Target,
+    stride: u32,
+    stop: AtomicBool,
+    total_hashes: AtomicU64,
+    cancel: &'a CancellationToken,
+    clock: &'a dyn Clock,
+    // Time since the epoch at which to give up
+    deadline: Option<Duration>,
+}
+
+enum WorkerOutcome {
+    Found(Block),
+    Stopped,
+    Exhausted,
+}
+
+impl NonceSearch<'_> {
+    fn past_deadline(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| self.clock.since_epoch() >= deadline)
+    }
+
+    // Walk one worker's slice of the nonce space until it, or another worker, finds a
+    // solution, or the search is cancelled or runs out of time or budget
+    fn run(&self, mut block: Block, worker: u32, budget: Option<u64>) -> (u64, WorkerOutcome) {
+        let mut hashes = 0u64;
+        let mut hasher = HeaderHasher::new(&block.header);
+        let mut nonce = worker;
+
+        loop {
+            if self.stop.load(Ordering::Relaxed)
+                || self.cancel.is_cancelled()
+                || budget.is_some_and(|budget| hashes >= budget)
+            {
+                return (hashes, WorkerOutcome::Stopped);
+            }
+
+            let hash = hasher.hash_with_nonce(nonce);
+            hashes += 1;
+
+            if self.target.is_met_by(&hash) {
+                // Only the first worker to claim the solution reports it
+                if self
+                    .stop
+                    .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    block.header.nonce = nonce;
+                    return (hashes, WorkerOutcome::Found(block));
+                }
+                return (hashes, WorkerOutcome::Stopped);
+            }
+
+            if hashes.is_multiple_of(HASH_COUNT_BATCH) {
+                let total = self
+                    .total_hashes
+                    .fetch_add(HASH_COUNT_BATCH, Ordering::Relaxed)
+                    + HASH_COUNT_BATCH;
+                if total.is_multiple_of(PROGRESS_INTERVAL) {
+                    println!("⛏️  Mining... Tried {} nonces", total);
+                }
+                if self.past_deadline() {
+                    self.stop.store(true, Ordering::Relaxed);
+                    return (hashes, WorkerOutcome::Stopped);
+                }
+            }
+
+            match nonce.checked_add(self.stride) {
+                Some(next) => nonce = next,
+                None => {
+                    // This worker's nonces are used up; change the coinbase instead
+                    if !block.increment_extranonce() {
+                        return (hashes, WorkerOutcome::Exhausted);
+                    }
+                    hasher = HeaderHasher::new(&block.header);
+                    nonce = worker;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MiningError {
+    InvalidBlock,
+    Cancelled { attempts: u64 },
+    Timeout { attempts: u64 },
+    HashLimitReached { attempts: u64 },
+    NonceSpaceExhausted { attempts: u64 },
+    Storage(StoreError),
+}
+
+impl std::fmt::Display for MiningError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MiningError::InvalidBlock => write!(f, "Invalid block"),
+            MiningError::Cancelled { attempts } => {
+                write!(f, "Mining cancelled after {} attempts", attempts)
+            }
+            MiningError::Timeout { attempts } => {
+                write!(f, "Mining timed out after {} attempts", attempts)
+            }
+            MiningError::HashLimitReached { attempts } => {
+                write!(f, "Hash limit reached after {} attempts", attempts)
+            }
+            MiningError::NonceSpaceExhausted { attempts } => {
+                write!(f, "Nonce space exhausted after {} attempts", attempts)
+            }
+            MiningError::Storage(err) => write!(f, "Failed to store mined block: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for MiningError {}
+
+impl From<StoreError> for MiningError {
+    fn from(err: StoreError) -> Self {
+        MiningError::Storage(err)
+    }
+}
+
+// A block that breaks a consensus rule, identified by height and hash
+#[derive(Debug, PartialEq, Eq)]
+pub struct ValidationError {
+    pub height: u32,
+    pub hash: [u8; 32],
+    pub kind: ValidationErrorKind,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ValidationErrorKind {
+    BadHeight { expected: u32 },
+    BlockTooLarge { size: usize, max: usize },
+    BadMerkleRoot,
+    MutatedMerkleTree,
+    BadBits { expected: u32 },
+    BadProofOfWork,
+    BadPreviousHash,
+    TimestampTooEarly { median_time_past: u32 },
+    TimestampTooFarAhead { latest: u32 },
+    WrongGenesis,
+    MissingCoinbase,
+    MultipleCoinbases,
+    // The coinbase pays an output or total above MAX_MONEY
+    BadCoinbase,
+    NonFinalTransaction { txid: [u8; 32] },
+    SequenceLocked { txid: [u8; 32] },
+    CoinbaseOverpays { paid: u64, allowed: u64 },
+    InvalidTransaction(UtxoError),
+    DuplicateBlock,
+    UnknownParent,
+    InvalidParent,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Block #{} ({}) ", self.height, hash_to_hex(&self.hash))?;
+        match &self.kind {
+            ValidationErrorKind::BadHeight { expected } => {
+                write!(f, "should be at height {}", expected)
+            }
+            ValidationErrorKind::BlockTooLarge { size, max } => {
+                write!(f, "is {} bytes, over the {} byte limit", size, max)
+            }
+            ValidationErrorKind::BadMerkleRoot => {
+                write!(f, "has a merkle root that doesn't match its transactions")
+            }
+            ValidationErrorKind::MutatedMerkleTree => {
+                write!(f, "repeats transactions to forge its merkle root")
+            }
+            ValidationErrorKind::BadBits { expected } => {
+                write!(f, "has nBits other than the expected 0x{:08x}", expected)
+            }
+            ValidationErrorKind::BadProofOfWork => write!(f, "has insufficient proof of work"),
+            ValidationErrorKind::BadPreviousHash => {
+                write!(f, "doesn't link to the previous block")
+            }
+            ValidationErrorKind::TimestampTooEarly { median_time_past } => write!(
+                f,
+                "has a timestamp not after the median time past {}",
+                median_time_past
+            ),
+            ValidationErrorKind::TimestampTooFarAhead { latest } => write!(
+                f,
+                "has a timestamp more than two hours ahead, after {}",
+                latest
+            ),
+            ValidationErrorKind::WrongGenesis => {
+                write!(f, "isn't this chain's genesis block")
+            }
+            ValidationErrorKind::MissingCoinbase => write!(f, "has no coinbase"),
+            ValidationErrorKind::MultipleCoinbases => write!(f, "has more than one coinbase"),
+            ValidationErrorKind::BadCoinbase => {
+                write!(f, "has a coinbase paying beyond the 21 million coin limit")
+            }
+            ValidationErrorKind::NonFinalTransaction { txid } => write!(
+                f,
+                "includes transaction {} before its lock time",
+                hash_to_hex(txid)
+            ),
+            ValidationErrorKind::SequenceLocked { txid } => write!(
+                f,
+                "includes transaction {} before its relative lock times",
+                hash_to_hex(txid)
+            ),
+            ValidationErrorKind::CoinbaseOverpays { paid, allowed } => write!(
+                f,
+                "coinbase pays {} satoshis, more than the {} allowed",
+                paid, allowed
+            ),
+            ValidationErrorKind::InvalidTransaction(err) => write!(f, "is invalid: {}", err),
+            ValidationErrorKind::DuplicateBlock => write!(f, "is already known"),
+            ValidationErrorKind::UnknownParent => write!(f, "builds on an unknown block"),
+            ValidationErrorKind::InvalidParent => write!(f, "builds on an invalid block"),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+// Why a block or stored chain couldn't be accepted
+#[derive(Debug)]
+pub enum ChainError {
+    Store(StoreError),
+    Invalid(ValidationError),
+}
+
+impl std::fmt::Display for ChainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ChainError::Store(err) => write!(f, "{}", err),
+            ChainError::Invalid(err) => write!(f, "Invalid block: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ChainError {}
+
+impl From<StoreError> for ChainError {
+    fn from(err: StoreError) -> Self {
+        ChainError::Store(err)
+    }
+}
+
+impl From<ValidationError> for ChainError {
+    fn from(err: ValidationError) -> Self {
+        ChainError::Invalid(err)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TransactionError {
+    Coinbase,
+    NoInputs,
+    NoOutputs,
+    ZeroAmount,
+    DuplicateId {
+        txid: [u8; 32],
+    },
+    DuplicateInput {
+        outpoint: OutPoint,
+    },
+    ConflictingSpend {
+        outpoint: OutPoint,
+        conflicting_txid: [u8; 32],
+    },
+    MissingInput {
+        outpoint: OutPoint,
+    },
+    InsufficientFunds {
+        available: u64,
+        required: u64,
+    },
+    ImmatureCoinbase {
+        outpoint: OutPoint,
+    },
+    // An output, the outputs' total or the inputs' total is above MAX_MONEY
+    ValueOutOfRange,
+    NonFinal,
+    SequenceLocked,
+    ScriptFailed {
+        input: usize,
+        error: ScriptError,
+    },
+    Mempool(MempoolError),
+}
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TransactionError::Coinbase => write!(f, "Coinbase transactions can't be relayed"),
+            TransactionError::NoInputs => write!(f, "Transaction has no inputs"),
+            TransactionError::NoOutputs => write!(f, "Transaction has no outputs"),
+            TransactionError::ZeroAmount => write!(f, "Transaction has a zero-value output"),
+            TransactionError::DuplicateId { txid } => {
+                write!(f, "Transaction {} already exists", hash_to_hex(txid))
+            }
+            TransactionError::DuplicateInput { outpoint } => {
+                write!(f, "Output {} is spent twice", outpoint)
+            }
+            TransactionError::ConflictingSpend {
+                outpoint,
+                conflicting_txid,
+            } => write!(
+                f,
+                "Output {} is already spent by pending transaction {}",
+                outpoint,
+                hash_to_hex(conflicting_txid)
+            ),
+            TransactionError::MissingInput { outpoint } => {
+                write!(f, "Output {} does not exist or is already spent", outpoint)
+            }
+            TransactionError::InsufficientFunds {
+                available,
+                required,
+            } => write!(
+                f,
+                "Insufficient funds: {} satoshis available, {} required",
+                available, required
+            ),
+            TransactionError::ImmatureCoinbase { outpoint } => {
+                write!(f, "Coinbase output {} has not matured yet", outpoint)
+            }
+            TransactionError::ValueOutOfRange => {
+                write!(f, "Transaction has values beyond the 21 million coin limit")
+            }
+            TransactionError::NonFinal => {
+                write!(f, "Transaction can't be mined before its lock time")
+            }
+            TransactionError::SequenceLocked => write!(
+                f,
+                "Transaction can't be mined before its relative lock times"
+            ),
+            TransactionError::ScriptFailed { input, error } => {
+                write!(f, "Input {} fails its script: {}", input, error)
+            }
+            TransactionError::Mempool(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+impl From<MempoolError> for TransactionError {
+    fn from(err: MempoolError) -> Self {
+        TransactionError::Mempool(err)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    InvalidLength { expected: usize, actual: usize },
+    InvalidHex,
+    UnexpectedEnd,
+    NonCanonicalCompactSize,
+    TrailingBytes { count: usize },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::InvalidLength { expected, actual } => {
+                write!(
+                    f,
+                    "Invalid length: expected {} bytes, got {}",
+                    expected, actual
+                )
+            }
+            DecodeError::InvalidHex => write!(f, "Invalid hex string"),
+            DecodeError::UnexpectedEnd => write!(f, "Unexpected end of data"),
+            DecodeError::NonCanonicalCompactSize => {
+                write!(f, "CompactSize is not minimally encoded")
+            }
+            DecodeError::TrailingBytes { count } => {
+                write!(f, "{} unexpected bytes after the end of the data", count)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// Helper functions
+pub fn double_sha256(data: &[u8]) -> [u8; 32] {
+    let first_hash = Sha256::digest(data);
+    Sha256::digest(first_hash).into()
+}
+
+// Hashes are displayed byte-reversed, matching block explorers and bitcoind
+pub fn hash_to_hex(hash: &[u8; 32]) -> String {
+    let mut reversed = *hash;
+    reversed.reverse();
+    hex::encode(reversed)
+}
+
+pub fn hash_from_hex(s: &str) -> Result<[u8; 32], DecodeError> {
+    let bytes = hex::decode(s).map_err(|_| DecodeError::InvalidHex)?;
+    let mut hash: [u8; 32] =
+        bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| DecodeError::InvalidLength {
+                expected: 32,
+                actual: bytes.len(),
+            })?;
+    hash.reverse();
+    Ok(hash)
+}
+
+// How far ahead of the clock a new block's timestamp may be, in seconds
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+// Number of past blocks whose median timestamp bounds the next block's
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+// Median of the last MEDIAN_TIME_SPAN of `timestamps`, or 0 if there are none
+pub fn median_time_past(timestamps: &[u32]) -> u32 {
+    let mut recent = timestamps[timestamps.len().saturating_sub(MEDIAN_TIME_SPAN)..].to_vec();
+    recent.sort_unstable();
+    recent.get(recent.len() / 2).copied().unwrap_or(0)
+}
+
+// Sample payments as (from, to, amount), for `Blockchain::create_transaction`
+pub fn create_sample_payments() -> Vec<(&'static str, &'static str, u64)> {
+    vec![
+        (
+            GENESIS_ADDRESS,
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+            10_00000000,
+        ), // 10 BTC
+        (
+            GENESIS_ADDRESS,
+            "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+            5_00000000,
+        ), // 5 BTC
+        (
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+            "1JfbZRwdDHKZmuiZgYArJZhcuuzuw2HuMu",
+            3_50000000,
+        ), // 3.5 BTC
+        (
+            "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+            "1dice8EMZmqKvrGE4Qc9bUFf9PX3xaYDp",
+            2_25000000,
+        ), // 2.25 BTC
+        (
+            "1JfbZRwdDHKZmuiZgYArJZhcuuzuw2HuMu",
+            "1BoatSLRHtKNngkdXEeobR76b53LETtpyT",
+            1_00000000,
+        ), // 1 BTC
+    ]
+}
+
+// Compare header hashing with and without the cached midstate on the same header.
+// Run with `cargo run --release -- bench`.
+fn run_hash_benchmark() {
+    const ITERATIONS: u32 = 2_000_000;
+
+    println!("⏱️  HEADER HASHING BENCHMARK");
+    println!("=====================================");
+
+    let mut header = ChainParams::mainnet().genesis.header;
+    let start = Instant::now();
+    for nonce in 0..ITERATIONS {
+        header.nonce = nonce;
+        std::hint::black_box(header.hash());
+    }
+    let full_rate = ITERATIONS as f64 / start.elapsed().as_secs_f64();
+
+    let hasher = HeaderHasher::new(&header);
+    let start = Instant::now();
+    for nonce in 0..ITERATIONS {
+        std::hint::black_box(hasher.hash_with_nonce(nonce));
+    }
+    let midstate_rate = ITERATIONS as f64 / start.elapsed().as_secs_f64();
+
+    println!("Full header hash: {:>12.0} H/s", full_rate);
+    println!("Cached midstate:  {:>12.0} H/s", midstate_rate);
+    println!("Speedup:          {:>12.2}x", midstate_rate / full_rate);
+}
+
+// Example usage
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("bench") {
+        run_hash_benchmark();
+        return Ok(());
+    }
+    let option = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
+    };
+    let data_dir = option("--datadir");
+
+    println!("🚀 BITCOIN BLOCKCHAIN MINING SIMULATOR");
+    println!("=====================================\n");
+
+    // Create a new blockchain with moderate difficulty, on disk if asked to
+    let params = ChainParams::custom(Target::from_leading_zero_bits(8));
+    let algorithm = option("--difficulty").map_or("bitcoin", String::as_str);
+    let difficulty =
+        difficulty::from_name(algorithm, &params).ok_or("unknown difficulty algorithm")?;
+    let store: Box<dyn ChainStore> = match data_dir {
+        Some(dir) => Box::new(FileStore::open(dir)?),
+        None => Box::new(MemoryStore::new()),
+    };
+    let mut blockchain =
+        Blockchain::with_difficulty(store, params, difficulty).map_err(|err| match err {
+            ChainError::Invalid(err) => format!("Stored chain is invalid: {}", err),
+            ChainError::Store(err) => err.to_string(),
+        })?;
+    blockchain.miner.reward_address = "1CounterpartyXXXXXXXXXXXXXXXUWLpVr".to_string();
+
+    if blockchain.block_count() > 1 {
+        // The sample payments below have already been made from this genesis block
+        println!(
+            "📂 Loaded and validated {} blocks from {}",
+            blockchain.block_count(),
+            data_dir.unwrap()
+        );
+        blockchain.print_chain();
+        return Ok(());
+    }
+    println!("📋 Genesis block created successfully!");
+
+    // Create and add multiple transactions for the first block
+    println!("\n💳 Adding transactions to Block #1...");
+    let payments_block1 = vec![
+        (
+            GENESIS_ADDRESS,
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+            25_00000000,
+        ), // 25 BTC
+        (
+            GENESIS_ADDRESS,
+            "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+            15_00000000,
+        ), // 15 BTC
+        (
+            GENESIS_ADDRESS,
+            "1JfbZRwdDHKZmuiZgYArJZhcuuzuw2HuMu",
+            8_50000000,
+        ), // 8.5 BTC
+    ];
+
+    for (from, to, amount) in payments_block1 {
+        let tx = blockchain
+            .create_transaction(from, to, amount)
+            .ok_or("insufficient funds")?;
+        blockchain.add_transaction(tx)?;
+    }
+
+    // Mine the first block
+    blockchain.mine_pending_transactions()?;
+
+    // Add transactions for the second block
+    println!("\n💳 Adding transactions to Block #2...");
+    let payments_block2 = vec![
+        (
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+            "1dice8EMZmqKvrGE4Qc9bUFf9PX3xaYDp",
+            12_00000000,
+        ), // 12 BTC
+        (
+            "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+            "1BoatSLRHtKNngkdXEeobR76b53LETtpyT",
+            7_25000000,
+        ), // 7.25 BTC
+    ];
+
+    for (from, to, amount) in payments_block2 {
+        let tx = blockchain
+            .create_transaction(from, to, amount)
+            .ok_or("insufficient funds")?;
+        blockchain.add_transaction(tx)?;
+    }
+
+    // Mine the second block
+    blockchain.mine_pending_transactions()?;
+
+    // Add transactions for the third block
+    println!("\n💳 Adding transactions to Block #3...");
+    let payments_block3 = vec![
+        (
+            "1JfbZRwdDHKZmuiZgYArJZhcuuzuw2HuMu",
+            "1F1tAaz5x1HUXrCNLbtMDqcw6o5GNn4xqX",
+            4_00000000,
+        ), // 4 BTC
+        (
+            "1dice8EMZmqKvrGE4Qc9bUFf9PX3xaYDp",
+            "1Eqk7vVT3w5GF8mJVWK5vJfYahb4D5nVZ7",
+            6_50000000,
+        ), // 6.5 BTC
+        (
+            "1BoatSLRHtKNngkdXEeobR76b53LETtpyT",
+            "1HLoD9E4SDFFPDiYfNYnkBLQ85Y51J3Zb1",
+            3_75000000,
+        ), // 3.75 BTC
+    ];
+
+    for (from, to, amount) in payments_block3 {
+        let tx = blockchain
+            .create_transaction(from, to, amount)
+            .ok_or("insufficient funds")?;
+        blockchain.add_transaction(tx)?;
+    }
+
+    // Mine the third block
+    blockchain.mine_pending_transactions()?;
+
+    // Validate the entire blockchain
+    println!("\n🔍 Validating blockchain...");
+    match blockchain.validate_chain() {
+        Ok(()) => println!("✅ Blockchain is valid!"),
+        Err(ChainError::Invalid(err)) => println!("❌ Stored chain is invalid: {}", err),
+        Err(ChainError::Store(err)) => println!("❌ {}", err),
+    }
+
+    // Print the complete blockchain
+    blockchain.print_chain();
+
+    // Show some balance information
+    println!("\n💰 WALLET BALANCES");
+    println!("=====================================");
+    let addresses = vec![
+        GENESIS_ADDRESS,
+        "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+        "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+        "1JfbZRwdDHKZmuiZgYArJZhcuuzuw2HuMu",
+        "1dice8EMZmqKvrGE4Qc9bUFf9PX3xaYDp",
+        "1CounterpartyXXXXXXXXXXXXXXXUWLpVr", // Miner
+    ];
+
+    for addr in addresses {
+        let balance = blockchain.get_balance(addr);
+        println!(
+            "{}: {} satoshis ({:.8} BTC)",
+            addr,
+            balance,
+            balance as f64 / 100_000_000.0
+        );
+    }
+
+    println!("\n🎉 Mining simulation completed successfully!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::ManualClock;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_blockchain_creation() {
+        let blockchain = Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        assert_eq!(blockchain.block_count(), 1); // Genesis block
+        assert_eq!(
+            blockchain.get_block_by_height(0).unwrap().unwrap().height,
+            0
+        );
+    }
+
+    #[test]
+    fn test_chain_starts_from_preset_genesis() {
+        for params in [
+            ChainParams::mainnet(),
+            ChainParams::testnet(),
+            ChainParams::regtest(),
+        ] {
+            let blockchain = Blockchain::new(params.clone());
+            assert_eq!(blockchain.get_latest_block().unwrap(), params.genesis);
+            assert_eq!(blockchain.next_bits(), params.genesis.header.bits);
+            blockchain.validate_chain().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_transaction_addition() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        let tx = blockchain
+            .create_transaction(GENESIS_ADDRESS, "output1", 100)
+            .unwrap();
+
+        blockchain.add_transaction(tx).unwrap();
+        assert_eq!(blockchain.mempool.len(), 1);
+    }
+
+    #[test]
+    fn test_mining_multiple_blocks() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+
+        let tx1 = blockchain
+            .create_transaction(GENESIS_ADDRESS, "addr1", 100)
+            .unwrap();
+        blockchain.add_transaction(tx1).unwrap();
+        let tx2 = blockchain
+            .create_transaction(GENESIS_ADDRESS, "addr2", 200)
+            .unwrap();
+        blockchain.add_transaction(tx2).unwrap();
+
+        let result = blockchain.mine_pending_transactions();
+        assert!(result.is_ok());
+        assert_eq!(blockchain.block_count(), 2); // Genesis + 1 new block
+    }
+
+    #[test]
+    fn test_blockchain_validation() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        let tx = blockchain
+            .create_transaction(GENESIS_ADDRESS, "addr1", 100)
+            .unwrap();
+
+        blockchain.add_transaction(tx).unwrap();
+        blockchain.mine_pending_transactions().unwrap();
+
+        blockchain.validate_chain().unwrap();
+    }
+
+    // Spends a made-up output; fine for mining, which doesn't look at inputs
+    fn sample_transaction() -> Transaction {
+        Transaction::new(
+            vec![TxIn::new(OutPoint::new([1; 32], 0))],
+            vec![TxOut::to_address(100, "addr1")],
+        )
+    }
+
+    fn mainnet_genesis_header() -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            previous_hash: [0; 32],
+            merkle_root: hash_from_hex(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            )
+            .unwrap(),
+            timestamp: 1231006505,
+            bits: 0x1d00ffff,
+            nonce: 2083236893,
+        }
+    }
+
+    #[test]
+    fn test_mainnet_genesis_header_hash() {
+        let header = mainnet_genesis_header();
+        assert_eq!(
+            hash_to_hex(&header.hash()),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+    }
+
+    #[test]
+    fn test_block_header_round_trip() {
+        let header = mainnet_genesis_header();
+        let bytes = header.serialize();
+        assert_eq!(
+            hex::encode(bytes),
+            "0100000000000000000000000000000000000000000000000000000000000000\
+             000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa\
+             4b1e5e4a29ab5f49ffff001d1dac2b7c"
+        );
+        assert_eq!(BlockHeader::deserialize(&bytes).unwrap(), header);
+        assert_eq!(
+            BlockHeader::deserialize(&bytes[..79]),
+            Err(DecodeError::InvalidLength {
+                expected: BLOCK_HEADER_SIZE,
+                actual: 79
+            })
+        );
+    }
+
+    // The first bitcoin payment, from Satoshi to Hal Finney in block 170
+    const BLOCK_170_PAYMENT: &str = "0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25857fcd3704000000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901ffffffff0200ca9a3b00000000434104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac00286bee0000000043410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000";
+
+    #[test]
+    fn test_transaction_wire_format() {
+        let bytes = hex::decode(BLOCK_170_PAYMENT).unwrap();
+        let tx = Transaction::deserialize(&bytes).unwrap();
+        assert_eq!(
+            hash_to_hex(&tx.txid()),
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16"
+        );
+        assert_eq!(
+            hash_to_hex(&tx.inputs[0].previous_output.txid),
+            "0437cd7f8525ceed2324359c2d0ba26006d92d856a9c20fa0241106ee5a597c9"
+        );
+        assert_eq!(tx.inputs[0].previous_output.vout, 0);
+        assert_eq!(tx.outputs[0].value, 10 * COIN);
+        assert_eq!(tx.outputs[1].value, 40 * COIN);
+        assert_eq!(tx.size(), 275);
+        assert_eq!(tx.serialize(), bytes);
+
+        // The genesis coinbase, whose txid is the genesis merkle root
+        let genesis = &ChainParams::mainnet().genesis.transactions[0];
+        let decoded = Transaction::deserialize(&genesis.serialize()).unwrap();
+        assert_eq!(&decoded, genesis);
+        assert_eq!(
+            hash_to_hex(&decoded.txid()),
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+        );
+        assert!(decoded.is_coinbase());
+    }
+
+    #[test]
+    fn test_transaction_decoding_rejects_malformed_bytes() {
+        let bytes = hex::decode(BLOCK_170_PAYMENT).unwrap();
+        assert_eq!(
+            Transaction::deserialize(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            Transaction::deserialize(&trailing),
+            Err(DecodeError::TrailingBytes { count: 1 })
+        );
+
+        // An input count far beyond the bytes that follow
+        let mut forged = bytes[..4].to_vec();
+        forged.extend_from_slice(&[0xfe, 0xff, 0xff, 0xff, 0x7f]);
+        assert_eq!(
+            Transaction::deserialize(&forged),
+            Err(DecodeError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn test_block_wire_format() {
+        // The mainnet genesis block is 285 bytes on the wire
+        let genesis = ChainParams::mainnet().genesis;
+        let bytes = genesis.serialize();
+        assert_eq!(bytes.len(), 285);
+        assert_eq!(bytes.len(), genesis.size());
+        assert_eq!(bytes[..BLOCK_HEADER_SIZE], genesis.header.serialize());
+        assert_eq!(Block::deserialize(&bytes, 0).unwrap(), genesis);
+
+        assert_eq!(
+            Block::deserialize(&bytes[..bytes.len() - 1], 0),
+            Err(DecodeError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn test_block_170_signature_verifies() {
+        let tx = Transaction::deserialize(&hex::decode(BLOCK_170_PAYMENT).unwrap()).unwrap();
+        // The spent block 9 coinbase paid the same key as the change output
+        let spent_script = tx.outputs[1].script_pubkey.clone();
+        let checker = script::TransactionChecker::new(&tx, 0);
+        assert_eq!(
+            script::verify_script(&tx.inputs[0].script_sig, &spent_script, &checker),
+            Ok(())
+        );
+
+        let mut altered = tx.clone();
+        altered.outputs[0].value -= 1;
+        let checker = script::TransactionChecker::new(&altered, 0);
+        assert_eq!(
+            script::verify_script(&tx.inputs[0].script_sig, &spent_script, &checker),
+            Err(ScriptError::EvalFalse)
+        );
+    }
+
+    #[test]
+    fn test_mainnet_genesis_header_meets_target() {
+        let mut header = mainnet_genesis_header();
+        assert!(header.meets_target());
+
+        header.nonce += 1;
+        assert!(!header.meets_target());
+    }
+
+    #[test]
+    fn test_mining_with_fractional_difficulty() {
+        let easy = Target::from_leading_zero_bits(4);
+        let target = Target::from_difficulty(easy.difficulty() * 1.5);
+        let miner = Miner::new(target);
+
+        let tx = sample_transaction();
+        let block = miner
+            .mine_block(1, [0; 32], vec![tx], 1, INITIAL_SUBSIDY)
+            .unwrap()
+            .block;
+
+        assert!(miner.check_proof_of_work(&block));
+        assert!(target.is_met_by(&block.hash()));
+    }
+
+    fn sample_block(bits: u32) -> Block {
+        let tx = sample_transaction();
+        let mut block = Block::new(1, [0; 32], vec![tx], bits, 1);
+        block.header.timestamp = 1_700_000_000;
+        block
+    }
+
+    #[test]
+    fn test_single_worker_finds_lowest_nonce() {
+        let target = Target::from_leading_zero_bits(8);
+        let mut miner = Miner::new(target);
+        miner.threads = 1;
+
+        let mined = miner.mine(sample_block(target.to_compact())).unwrap();
+        assert_eq!(mined.worker, 0);
+        assert_eq!(mined.hashes, mined.block.header.nonce as u64 + 1);
+
+        let mut header = mined.block.header.clone();
+        for nonce in 0..mined.block.header.nonce {
+            header.nonce = nonce;
+            assert!(!header.meets_target());
+        }
+    }
+
+    #[test]
+    fn test_parallel_workers_partition_nonces() {
+        let target = Target::from_leading_zero_bits(12);
+        let mut miner = Miner::new(target);
+        miner.threads = 4;
+
+        let mined = miner.mine(sample_block(target.to_compact())).unwrap();
+        assert!(miner.check_proof_of_work(&mined.block));
+        assert_eq!(mined.block.header.nonce % 4, mined.worker as u32);
+        assert!(mined.hashes >= mined.block.header.nonce as u64 / 4);
+    }
+
+    #[test]
+    fn test_unique_solution_in_budget_is_deterministic() {
+        let target = Target::from_leading_zero_bits(10);
+        let mut miner = Miner::new(target);
+        miner.threads = 1;
+        let block = sample_block(target.to_compact());
+        let nonce = miner.mine(block.clone()).unwrap().block.header.nonce;
+
+        // Nonces 0..=nonce hold exactly one solution, so every split must find it
+        miner.max_hashes = Some(nonce as u64 + 1);
+        for threads in [2, 3, 8] {
+            miner.threads = threads;
+            let mined = miner.mine(block.clone()).unwrap();
+            assert_eq!(mined.block.header.nonce, nonce);
+            assert_eq!(mined.worker, nonce as usize % threads);
+        }
+    }
+
+    #[test]
+    fn test_hash_limit() {
+        let mut miner = Miner::new(Target::ZERO);
+        miner.threads = 3;
+        miner.max_hashes = Some(1000);
+
+        let result = miner.mine(sample_block(Target::ZERO.to_compact()));
+        assert!(matches!(
+            result,
+            Err(MiningError::HashLimitReached { attempts: 1000 })
+        ));
+    }
+
+    #[test]
+    fn test_mining_timeout() {
+        let mut miner = Miner::new(Target::ZERO);
+        miner.threads = 2;
+        miner.max_duration = Some(Duration::from_millis(50));
+
+        let result = miner.mine(sample_block(Target::ZERO.to_compact()));
+        assert!(matches!(result, Err(MiningError::Timeout { attempts }) if attempts > 0));
+
+        // A stopped clock never reaches a deadline, and one already past stops the
+        // search at the first check
+        miner.threads = 1;
+        miner.clock = Arc::new(ManualClock::new(GENESIS_TIMESTAMP));
+        miner.max_hashes = Some(3 * HASH_COUNT_BATCH);
+        let result = miner.mine(sample_block(Target::ZERO.to_compact()));
+        assert!(matches!(
+            result,
+            Err(MiningError::HashLimitReached { attempts }) if attempts == 3 * HASH_COUNT_BATCH
+        ));
+        miner.max_duration = Some(Duration::ZERO);
+        let result = miner.mine(sample_block(Target::ZERO.to_compact()));
+        assert!(matches!(
+            result,
+            Err(MiningError::Timeout { attempts }) if attempts == HASH_COUNT_BATCH
+        ));
+    }
+
+    #[test]
+    fn test_mining_cancellation() {
+        let mut miner = Miner::new(Target::ZERO);
+        miner.threads = 2;
+        let cancel = CancellationToken::new();
+
+        let handle = {
+            let cancel = cancel.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                cancel.cancel();
+            })
+        };
+
+        let result = miner.mine_with_cancel(sample_block(Target::ZERO.to_compact()), &cancel);
+        handle.join().unwrap();
+        assert!(matches!(result, Err(MiningError::Cancelled { attempts }) if attempts > 0));
+    }
+
+    #[test]
+    fn test_extranonce_changes_merkle_root() {
+        let tx = sample_transaction();
+        let mut block = Block::new(
+            1,
+            [0; 32],
+            vec![Transaction::coinbase(1, u32::MAX - 1, "miner", 0), tx],
+            0x207fffff,
+            1,
+        );
+        let first_root = block.header.merkle_root;
+        let first_hash = block.hash();
+
+        assert!(block.increment_extranonce());
+        assert_eq!(block.transactions[0].extranonce(), Some(u32::MAX));
+        assert_ne!(block.header.merkle_root, first_root);
+        assert_ne!(block.hash(), first_hash);
+
+        assert!(!block.increment_extranonce());
+        assert!(!sample_block(0x207fffff).increment_extranonce());
+    }
+
+    #[test]
+    fn test_coinbase_pays_miner() {
+        let params = ChainParams {
+            halving_interval: 2,
+            ..ChainParams::custom(Target::from_leading_zero_bits(4))
+        };
+        let mut blockchain = Blockchain::new(params);
+        blockchain.miner.reward_address = "miner".to_string();
+
+        // The subsidy halves every two blocks, and each block also collects its fees
+        let mut fees = 0;
+        for (i, subsidy) in [50 * COIN, 25 * COIN, 25 * COIN, 1_250_000_000]
+            .into_iter()
+            .enumerate()
+        {
+            let tx = blockchain
+                .create_transaction(GENESIS_ADDRESS, "addr1", i as u64 + 1)
+                .unwrap();
+            let fee = blockchain.check_transaction(&tx).unwrap();
+            assert!(fee > 0);
+            blockchain.add_transaction(tx).unwrap();
+            blockchain.mine_pending_transactions().unwrap();
+            fees += fee;
+
+            let coinbase = &blockchain.get_latest_block().unwrap().transactions[0];
+            assert!(coinbase.is_coinbase());
+            assert_eq!(coinbase.total_output(), Some(subsidy + fee));
+        }
+
+        assert_eq!(blockchain.get_balance("miner"), 11_250_000_000 + fees);
+        blockchain.validate_chain().unwrap();
+    }
+
+    #[test]
+    fn test_coinbase_spendable_after_maturity() {
+        let params = ChainParams {
+            coinbase_maturity: 2,
+            ..ChainParams::custom(Target::from_leading_zero_bits(4))
+        };
+        let mut blockchain = Blockchain::new(params);
+        let genesis = blockchain.get_block_by_height(0).unwrap().unwrap();
+        let funds = OutPoint::new(genesis.transactions[0].txid(), 0);
+
+        // Created at height 0, so the earliest block that may spend it is height 2
+        let spend = signed(
+            Transaction::new(
+                vec![TxIn::new(funds)],
+                vec![TxOut::to_address(COIN, "alice")],
+            ),
+            GENESIS_ADDRESS,
+        );
+        assert!(
+            blockchain
+                .create_transaction(GENESIS_ADDRESS, "alice", COIN)
+                .is_none()
+        );
+        assert_eq!(
+            blockchain.add_transaction(spend.clone()),
+            Err(TransactionError::ImmatureCoinbase { outpoint: funds })
+        );
+        let early = mine_on_tip(&blockchain, vec![spend.clone()], INITIAL_SUBSIDY);
+        assert!(matches!(
+            blockchain.submit_block(early),
+            Err(ChainError::Invalid(ValidationError {
+                kind: ValidationErrorKind::InvalidTransaction(UtxoError::ImmatureCoinbase { .. }),
+                ..
+            }))
+        ));
+
+        let empty = mine_on_tip(&blockchain, vec![], INITIAL_SUBSIDY);
+        blockchain.submit_block(empty).unwrap();
+        blockchain.add_transaction(spend).unwrap();
+        blockchain.mine_pending_transactions().unwrap();
+        assert_eq!(blockchain.get_balance("alice"), COIN);
+        blockchain.validate_chain().unwrap();
+    }
+
+    // `tx` with every input signed by `owner`'s wallet key
+    fn signed(mut tx: Transaction, owner: &str) -> Transaction {
+        wallet::sign_inputs(&mut tx, owner);
+        tx
+    }
+
+    // Valid block on the current tip, apart from whatever the transactions break
+    fn mine_on_tip(blockchain: &Blockchain, transactions: Vec<Transaction>, reward: u64) -> Block {
+        let tip = blockchain.get_latest_block().unwrap();
+        let mut block =
+            blockchain
+                .miner
+                .create_block(1, tip.hash(), transactions, tip.height + 1, reward);
+        block.header.timestamp = blockchain.median_time_past() + 1;
+        blockchain.miner.mine(block).unwrap().block
+    }
+
+    #[test]
+    fn test_validation_identifies_broken_rule() {
+        let params = ChainParams::custom(Target::from_leading_zero_bits(4));
+        let mut blockchain = Blockchain::new(params.clone());
+        let genesis = blockchain.get_block_by_height(0).unwrap().unwrap();
+        let valid = mine_on_tip(&blockchain, vec![], INITIAL_SUBSIDY);
+
+        // Load genesis and `block` from a store, as a restarted node would
+        let check = |block: Block| {
+            let mut store = MemoryStore::new();
+            store.insert(genesis.clone()).unwrap();
+            store.insert(block).unwrap();
+            match Blockchain::with_store(Box::new(store), params.clone()) {
+                Ok(_) => Ok(()),
+                Err(ChainError::Invalid(err)) => Err(err.kind),
+                Err(err) => panic!("{}", err),
+            }
+        };
+
+        assert_eq!(check(valid.clone()), Ok(()));
+
+        // Stores only take the next height, so a wrong one is caught on submission
+        let mut block = valid.clone();
+        block.height = 2;
+        let Err(ChainError::Invalid(err)) = blockchain.submit_block(block) else {
+            panic!("block at the wrong height was accepted");
+        };
+        assert_eq!(err.kind, ValidationErrorKind::BadHeight { expected: 1 });
+        assert!(err.to_string().ends_with("should be at height 1"));
+
+        let mut store = MemoryStore::new();
+        store.insert(genesis.clone()).unwrap();
+        store.insert(valid.clone()).unwrap();
+        let max = valid.size() - 1;
+        let small_blocks = ChainParams {
+            max_block_size: max,
+            ..params.clone()
+        };
+        assert!(matches!(
+            Blockchain::with_store(Box::new(store), small_blocks),
+            Err(ChainError::Invalid(ValidationError {
+                kind: ValidationErrorKind::BlockTooLarge { size, max: limit },
+                ..
+            })) if size == max + 1 && limit == max
+        ));
+
+        let mut block = valid.clone();
+        block.transactions[0].outputs[0].value -= 1;
+        assert_eq!(check(block), Err(ValidationErrorKind::BadMerkleRoot));
+
+        let mut block = valid.clone();
+        block.header.bits = Target::from_leading_zero_bits(5).to_compact();
+        assert_eq!(
+            check(block),
+            Err(ValidationErrorKind::BadBits {
+                expected: blockchain.miner.bits()
+            })
+        );
+
+        // Rewrite the header and search again so only the intended rule is broken
+        let miner = Miner::new(Target::from_leading_zero_bits(4));
+        let remine = |mut block: Block| {
+            block.header.nonce = 0;
+            miner.mine(block).unwrap().block
+        };
+
+        let mut block = valid.clone();
+        block.header.previous_hash = [0xaa; 32];
+        let block = remine(block);
+        assert_eq!(check(block), Err(ValidationErrorKind::BadPreviousHash));
+
+        let median = blockchain.median_time_past();
+        let mut block = valid.clone();
+        block.header.timestamp = median;
+        let block = remine(block);
+        assert_eq!(
+            check(block),
+            Err(ValidationErrorKind::TimestampTooEarly {
+                median_time_past: median
+            })
+        );
+
+        let mut block = valid.clone();
+        while block.header.meets_target() {
+            block.header.nonce += 1;
+        }
+        assert_eq!(check(block), Err(ValidationErrorKind::BadProofOfWork));
+
+        // Duplicating the last transaction pairs a hash with itself
+        let mut block = valid.clone();
+        block.transactions.push(block.transactions[0].clone());
+        block.header.merkle_root = Block::calculate_merkle_root(&block.transactions);
+        let block = remine(block);
+        assert_eq!(check(block), Err(ValidationErrorKind::MutatedMerkleTree));
+
+        let mut block = valid;
+        block
+            .transactions
+            .push(Transaction::coinbase(1, 1, "miner", 0));
+        block.header.merkle_root = Block::calculate_merkle_root(&block.transactions);
+        let block = remine(block);
+        assert_eq!(check(block), Err(ValidationErrorKind::MultipleCoinbases));
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        assert_eq!(median_time_past(&[]), 0);
+        assert_eq!(median_time_past(&[100]), 100);
+
+        let mut timestamps = vec![100, 300, 200, 500, 400];
+        assert_eq!(median_time_past(&timestamps), 300);
+
+        // Only the last eleven blocks count
+        timestamps.extend([50; MEDIAN_TIME_SPAN]);
+        assert_eq!(median_time_past(&timestamps), 50);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_overpaying_coinbase_is_rejected() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        let block = mine_on_tip(&blockchain, vec![], INITIAL_SUBSIDY + 1);
+
+        blockchain.store.insert(block).unwrap();
+        let Err(ChainError::Invalid(err)) = blockchain.validate_chain() else {
+            panic!("overpaying coinbase was accepted");
+        };
+        assert_eq!(
+            err.kind,
+            ValidationErrorKind::CoinbaseOverpays {
+                paid: INITIAL_SUBSIDY + 1,
+                allowed: INITIAL_SUBSIDY
+            }
+        );
+        blockchain.store.pop().unwrap();
+
+        // Outputs whose total would wrap around to 1 satoshi
+        let mut block = mine_on_tip(&blockchain, vec![], u64::MAX);
+        block.transactions[0]
+            .outputs
+            .push(TxOut::to_address(2, "miner"));
+        block.header.merkle_root = merkle_root(&block.txids()).0;
+        block.header.nonce = 0;
+        let block = blockchain.miner.mine(block).unwrap().block;
+        assert!(matches!(
+            blockchain.submit_block(block),
+            Err(ChainError::Invalid(ValidationError {
+                kind: ValidationErrorKind::BadCoinbase,
+                ..
+            }))
+        ));
+    }
 
     #[test]
-    fn test_blockchain_creation() {
-        let blockchain = Blockchain::new(4);
-        assert_eq!(blockchain.blocks.len(), 1); // Genesis block
-        assert_eq!(blockchain.blocks[0].height, 0);
+    fn test_balances_follow_utxos() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+
+        // Without fees the change is easy to follow
+        blockchain.mempool.min_fee_rate = 0;
+
+        // Both payments spend the genesis output; the second uses the first's change
+        let tx1 = blockchain
+            .create_transaction(GENESIS_ADDRESS, "alice", 30 * COIN)
+            .unwrap();
+        assert_eq!(
+            tx1.outputs[1],
+            TxOut::to_address(20 * COIN, GENESIS_ADDRESS)
+        );
+        blockchain.add_transaction(tx1).unwrap();
+        let tx2 = blockchain
+            .create_transaction(GENESIS_ADDRESS, "bob", 15 * COIN)
+            .unwrap();
+        blockchain.add_transaction(tx2).unwrap();
+        assert!(
+            blockchain
+                .create_transaction(GENESIS_ADDRESS, "bob", 6 * COIN)
+                .is_none()
+        );
+
+        blockchain.mine_pending_transactions().unwrap();
+
+        assert_eq!(blockchain.get_balance(GENESIS_ADDRESS), 5 * COIN);
+        assert_eq!(blockchain.get_balance("alice"), 30 * COIN);
+        assert_eq!(blockchain.get_balance("bob"), 15 * COIN);
+        blockchain.validate_chain().unwrap();
     }
 
     #[test]
-    fn test_transaction_addition() {
-        let mut blockchain = Blockchain::new(4);
+    fn test_fees_go_to_coinbase() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        blockchain.miner.reward_address = "miner".to_string();
+
+        let genesis_output = OutPoint::new(
+            blockchain
+                .get_block_by_height(0)
+                .unwrap()
+                .unwrap()
+                .transactions[0]
+                .txid(),
+            0,
+        );
         let tx = Transaction::new(
-            "test_tx".to_string(),
-            vec!["input1".to_string()],
-            vec!["output1".to_string()],
-            100,
+            vec![TxIn::new(genesis_output)],
+            vec![TxOut::to_address(49 * COIN, "alice")],
         );
+        blockchain
+            .add_transaction(signed(tx, GENESIS_ADDRESS))
+            .unwrap();
+        blockchain.mine_pending_transactions().unwrap();
 
-        blockchain.add_transaction(tx);
-        assert_eq!(blockchain.pending_transactions.len(), 1);
+        assert_eq!(blockchain.get_balance("miner"), 51 * COIN);
+        blockchain.validate_chain().unwrap();
     }
 
     #[test]
-    fn test_mining_multiple_blocks() {
-        let mut blockchain = Blockchain::new(4);
+    fn test_wallet_pays_fee_rate() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        blockchain.miner.reward_address = "miner".to_string();
+
+        let cheap = blockchain
+            .create_transaction(GENESIS_ADDRESS, "alice", COIN)
+            .unwrap();
+        let fee = blockchain.check_transaction(&cheap).unwrap();
+        assert_eq!(fee, mempool::fee_for_size(1000, cheap.size()));
+        assert_eq!(cheap.outputs[1].value, INITIAL_SUBSIDY - COIN - fee);
+
+        let generous = blockchain
+            .create_transaction_with_fee_rate(GENESIS_ADDRESS, "alice", COIN, 5000)
+            .unwrap();
+        let fee = blockchain.check_transaction(&generous).unwrap();
+        assert!(fee >= mempool::fee_for_size(5000, generous.size()));
+        blockchain.add_transaction(generous).unwrap();
+        blockchain.mine_pending_transactions().unwrap();
+        assert_eq!(blockchain.get_balance("miner"), INITIAL_SUBSIDY + fee);
+        blockchain.validate_chain().unwrap();
+    }
+
+    #[test]
+    fn test_connected_block_clears_mempool() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        let payment = blockchain
+            .create_transaction(GENESIS_ADDRESS, "alice", 2 * COIN)
+            .unwrap();
+        blockchain.add_transaction(payment.clone()).unwrap();
+        let child = blockchain.create_transaction("alice", "bob", COIN).unwrap();
+        blockchain.add_transaction(child.clone()).unwrap();
+        let change = blockchain
+            .create_transaction(GENESIS_ADDRESS, "carol", COIN)
+            .unwrap();
+        blockchain.add_transaction(change.clone()).unwrap();
+        assert_eq!(blockchain.mempool.len(), 3);
+
+        // A block confirming `payment` leaves its child waiting
+        let block = mine_on_tip(&blockchain, vec![payment], INITIAL_SUBSIDY);
+        blockchain.submit_block(block).unwrap();
+        assert_eq!(
+            blockchain.mempool.transactions(),
+            vec![child.clone(), change.clone()]
+        );
 
-        let tx1 = Transaction::new("tx1".to_string(), vec![], vec!["addr1".to_string()], 100);
-        let tx2 = Transaction::new("tx2".to_string(), vec![], vec!["addr2".to_string()], 200);
+        // One double-spending the child evicts it, and `change` stays valid
+        let mut respend = child;
+        respend.outputs[0] = TxOut::to_address(COIN, "dave");
+        let respend = signed(respend, "alice");
+        let block = mine_on_tip(&blockchain, vec![respend], INITIAL_SUBSIDY);
+        blockchain.submit_block(block).unwrap();
+        assert_eq!(blockchain.mempool.transactions(), vec![change]);
+    }
 
-        blockchain.add_transaction(tx1);
-        blockchain.add_transaction(tx2);
+    #[test]
+    fn test_blocks_hold_what_fits() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        let payment = blockchain
+            .create_transaction(GENESIS_ADDRESS, "alice", 2 * COIN)
+            .unwrap();
+        blockchain.add_transaction(payment.clone()).unwrap();
+        let child = blockchain.create_transaction("alice", "bob", COIN).unwrap();
+        blockchain.add_transaction(child.clone()).unwrap();
+
+        // Room for the payment but not its child as well
+        blockchain.params.max_block_size =
+            (assembler::COINBASE_RESERVED_WEIGHT + payment.weight()) / WITNESS_SCALE_FACTOR;
+        let template = blockchain.block_template();
+        assert_eq!(template.transactions, vec![payment.clone()]);
+        assert_eq!(
+            template.fees,
+            blockchain.mempool.get(&payment.txid()).unwrap().fee
+        );
 
-        let result = blockchain.mine_pending_transactions();
-        assert!(result.is_ok());
-        assert_eq!(blockchain.blocks.len(), 2); // Genesis + 1 new block
+        blockchain.mine_pending_transactions().unwrap();
+        assert_eq!(
+            blockchain.get_latest_block().unwrap().transactions[1..],
+            [payment]
+        );
+        assert_eq!(blockchain.mempool.transactions(), vec![child.clone()]);
+        blockchain.mine_pending_transactions().unwrap();
+        assert_eq!(
+            blockchain.get_latest_block().unwrap().transactions[1..],
+            [child]
+        );
+        assert!(blockchain.mempool.is_empty());
     }
 
     #[test]
-    fn test_blockchain_validation() {
-        let mut blockchain = Blockchain::new(4);
-        let tx = Transaction::new("tx1".to_string(), vec![], vec!["addr1".to_string()], 100);
+    fn test_empty_transactions_are_rejected_in_blocks() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        let reject = |blockchain: &mut Blockchain, tx: Transaction| {
+            let block = mine_on_tip(blockchain, vec![tx], INITIAL_SUBSIDY);
+            let Err(ChainError::Invalid(err)) = blockchain.submit_block(block) else {
+                panic!("block with an empty transaction was accepted");
+            };
+            err.kind
+        };
+
+        // The mempool refuses both, so blocks must too
+        let no_inputs = Transaction::new(vec![], vec![TxOut::to_address(COIN, "alice")]);
+        assert_eq!(
+            blockchain.add_transaction(no_inputs.clone()),
+            Err(TransactionError::NoInputs)
+        );
+        let txid = no_inputs.txid();
+        assert_eq!(
+            reject(&mut blockchain, no_inputs),
+            ValidationErrorKind::InvalidTransaction(UtxoError::NoInputs { txid })
+        );
+
+        let mut no_outputs = blockchain
+            .create_transaction(GENESIS_ADDRESS, "alice", COIN)
+            .unwrap();
+        no_outputs.outputs.clear();
+        assert_eq!(
+            blockchain.add_transaction(no_outputs.clone()),
+            Err(TransactionError::NoOutputs)
+        );
+        let txid = no_outputs.txid();
+        assert_eq!(
+            reject(&mut blockchain, no_outputs),
+            ValidationErrorKind::InvalidTransaction(UtxoError::NoOutputs { txid })
+        );
+        assert_eq!(blockchain.block_count(), 1);
+    }
 
-        blockchain.add_transaction(tx);
+    #[test]
+    fn test_double_spend_across_blocks_is_rejected() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        let tx = blockchain
+            .create_transaction(GENESIS_ADDRESS, "alice", COIN)
+            .unwrap();
+        blockchain.add_transaction(tx.clone()).unwrap();
         blockchain.mine_pending_transactions().unwrap();
 
-        assert!(blockchain.validate_chain());
+        // Re-spending the same genesis output can't be mined or slipped into the chain
+        let mut respend = tx;
+        respend.outputs[0] = TxOut::to_address(COIN, "bob");
+        assert_eq!(
+            blockchain.add_transaction(respend.clone()),
+            Err(TransactionError::MissingInput {
+                outpoint: respend.inputs[0].previous_output
+            })
+        );
+
+        let block = mine_on_tip(&blockchain, vec![respend], INITIAL_SUBSIDY);
+        let hash = block.hash();
+        blockchain.store.insert(block).unwrap();
+        let Err(ChainError::Invalid(err)) = blockchain.validate_chain() else {
+            panic!("spend of a missing output was accepted");
+        };
+        assert_eq!((err.height, err.hash), (2, hash));
+        assert!(matches!(
+            err.kind,
+            ValidationErrorKind::InvalidTransaction(UtxoError::MissingInput { .. })
+        ));
     }
-}
 
-// Example usage
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("🚀 BITCOIN BLOCKCHAIN MINING SIMULATOR");
-    println!("=====================================\n");
+    #[test]
+    fn test_add_transaction_rejects_invalid() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        let genesis_output = OutPoint::new(
+            blockchain
+                .get_block_by_height(0)
+                .unwrap()
+                .unwrap()
+                .transactions[0]
+                .txid(),
+            0,
+        );
+        let spend = |value: u64, to: &str| {
+            let tx = Transaction::new(
+                vec![TxIn::new(genesis_output)],
+                vec![TxOut::to_address(value, to)],
+            );
+            signed(tx, GENESIS_ADDRESS)
+        };
 
-    // Create a new blockchain with moderate difficulty
-    let mut blockchain = Blockchain::new(8);
+        assert_eq!(
+            blockchain.add_transaction(spend(0, "alice")),
+            Err(TransactionError::ZeroAmount)
+        );
+        // Outputs whose total would wrap around to 1 satoshi
+        let mut overflowing = spend(u64::MAX, "alice");
+        overflowing.outputs.push(TxOut::to_address(2, "alice"));
+        assert_eq!(
+            blockchain.add_transaction(signed(overflowing, GENESIS_ADDRESS)),
+            Err(TransactionError::ValueOutOfRange)
+        );
+        assert_eq!(
+            blockchain.add_transaction(spend(INITIAL_SUBSIDY + 1, "alice")),
+            Err(TransactionError::InsufficientFunds {
+                available: INITIAL_SUBSIDY,
+                required: INITIAL_SUBSIDY + 1
+            })
+        );
 
-    println!("📋 Genesis block created successfully!");
+        let first = spend(COIN, "alice");
+        blockchain.add_transaction(first.clone()).unwrap();
+        assert_eq!(
+            blockchain.add_transaction(first.clone()),
+            Err(TransactionError::DuplicateId { txid: first.txid() })
+        );
+        assert_eq!(
+            blockchain.add_transaction(spend(COIN, "bob")),
+            Err(TransactionError::ConflictingSpend {
+                outpoint: genesis_output,
+                conflicting_txid: first.txid()
+            })
+        );
 
-    // Create and add multiple transactions for the first block
-    println!("\n💳 Adding transactions to Block #1...");
-    let transactions_block1 = vec![
-        Transaction::new(
-            "tx_001".to_string(),
-            vec!["1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa".to_string()],
-            vec!["1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2".to_string()],
-            25_00000000, // 25 BTC
-        ),
-        Transaction::new(
-            "tx_002".to_string(),
-            vec!["1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa".to_string()],
-            vec!["3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy".to_string()],
-            15_00000000, // 15 BTC
-        ),
-        Transaction::new(
-            "tx_003".to_string(),
-            vec!["1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa".to_string()],
-            vec!["1JfbZRwdDHKZmuiZgYArJZhcuuzuw2HuMu".to_string()],
-            8_50000000, // 8.5 BTC
-        ),
-    ];
+        let unknown = OutPoint::new([7; 32], 0);
+        let from_nowhere = Transaction::new(
+            vec![TxIn::new(unknown)],
+            vec![TxOut::to_address(COIN, "bob")],
+        );
+        assert_eq!(
+            blockchain.add_transaction(from_nowhere),
+            Err(TransactionError::MissingInput { outpoint: unknown })
+        );
+        assert_eq!(
+            blockchain.add_transaction(Transaction::coinbase(1, 0, "bob", COIN)),
+            Err(TransactionError::Coinbase)
+        );
+
+        // Relaying needs a fee of at least 1000 satoshis per 1000 bytes
+        let chained = |value: u64| {
+            let tx = Transaction::new(
+                vec![TxIn::new(OutPoint::new(first.txid(), 0))],
+                vec![TxOut::to_address(value, "carol")],
+            );
+            signed(tx, "alice")
+        };
+        let free = chained(COIN);
+        assert_eq!(
+            blockchain.add_transaction(free.clone()),
+            Err(TransactionError::Mempool(MempoolError::FeeTooLow {
+                fee: 0,
+                required: free.size() as u64
+            }))
+        );
 
-    for tx in transactions_block1 {
-        blockchain.add_transaction(tx);
+        // Outputs of pending transactions can be spent, but only once
+        blockchain.add_transaction(chained(COIN - 1000)).unwrap();
+        assert_eq!(blockchain.mempool.len(), 2);
     }
 
-    // Mine the first block
-    blockchain.mine_pending_transactions()?;
+    #[test]
+    fn test_wallet_outputs_need_the_owners_signature() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        let payment = blockchain
+            .create_transaction(GENESIS_ADDRESS, "alice", COIN)
+            .unwrap();
+        assert_eq!(
+            payment.outputs[0].script_pubkey,
+            script::p2pkh(&script::hash160(&wallet::public_key("alice")))
+        );
+        blockchain.add_transaction(payment.clone()).unwrap();
+        blockchain.mine_pending_transactions().unwrap();
 
-    // Add transactions for the second block
-    println!("\n💳 Adding transactions to Block #2...");
-    let transactions_block2 = vec![
-        Transaction::new(
-            "tx_004".to_string(),
-            vec!["1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2".to_string()],
-            vec!["1dice8EMZmqKvrGE4Qc9bUFf9PX3xaYDp".to_string()],
-            12_00000000, // 12 BTC
-        ),
-        Transaction::new(
-            "tx_005".to_string(),
-            vec!["3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy".to_string()],
-            vec!["1BoatSLRHtKNngkdXEeobR76b53LETtpyT".to_string()],
-            7_25000000, // 7.25 BTC
-        ),
-    ];
+        let theft = Transaction::new(
+            vec![TxIn::new(OutPoint::new(payment.txid(), 0))],
+            vec![TxOut::to_address(COIN / 2, "mallory")],
+        );
+        assert_eq!(
+            blockchain.add_transaction(theft.clone()),
+            Err(TransactionError::ScriptFailed {
+                input: 0,
+                error: ScriptError::InvalidStackOperation
+            })
+        );
+        // Mallory's key doesn't hash to the one alice's output is locked to
+        let theft = signed(theft, "mallory");
+        assert_eq!(
+            blockchain.add_transaction(theft.clone()),
+            Err(TransactionError::ScriptFailed {
+                input: 0,
+                error: ScriptError::Verify
+            })
+        );
+        let block = mine_on_tip(&blockchain, vec![theft], INITIAL_SUBSIDY);
+        assert!(matches!(
+            blockchain.submit_block(block),
+            Err(ChainError::Invalid(ValidationError {
+                kind: ValidationErrorKind::InvalidTransaction(UtxoError::ScriptFailed { .. }),
+                ..
+            }))
+        ));
+
+        let spend = blockchain
+            .create_transaction("alice", "bob", COIN / 2)
+            .unwrap();
+        blockchain.add_transaction(spend).unwrap();
+        blockchain.mine_pending_transactions().unwrap();
+        assert_eq!(blockchain.get_balance("bob"), COIN / 2);
+        blockchain.validate_chain().unwrap();
+    }
+
+    #[test]
+    fn test_inputs_must_satisfy_locking_scripts() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        let key = k256::ecdsa::SigningKey::from_slice(&[1; 32]).unwrap();
+        let pubkey = key.verifying_key().to_sec1_bytes().to_vec();
+        let locked = TxOut {
+            value: 49 * COIN,
+            script_pubkey: script::p2pkh(&script::hash160(&pubkey)),
+        };
+        let genesis_output = OutPoint::new(
+            blockchain
+                .get_block_by_height(0)
+                .unwrap()
+                .unwrap()
+                .transactions[0]
+                .txid(),
+            0,
+        );
+        let fund = signed(
+            Transaction::new(vec![TxIn::new(genesis_output)], vec![locked.clone()]),
+            GENESIS_ADDRESS,
+        );
+        blockchain.add_transaction(fund.clone()).unwrap();
+        blockchain.mine_pending_transactions().unwrap();
+
+        let mut spend = Transaction::new(
+            vec![TxIn::new(OutPoint::new(fund.txid(), 0))],
+            vec![TxOut::to_address(48 * COIN, "alice")],
+        );
+        assert_eq!(
+            blockchain.add_transaction(spend.clone()),
+            Err(TransactionError::ScriptFailed {
+                input: 0,
+                error: ScriptError::InvalidStackOperation
+            })
+        );
 
-    for tx in transactions_block2 {
-        blockchain.add_transaction(tx);
+        // Signed by the right key, but for a different payment
+        let unlock = |tx: &Transaction| {
+            let signature = script::sign(tx, 0, &locked.script_pubkey, &key, script::SIGHASH_ALL);
+            script::Builder::new()
+                .push_slice(&signature)
+                .push_slice(&pubkey)
+                .into_bytes()
+        };
+        let mut other = spend.clone();
+        other.outputs[0].value = 47 * COIN;
+        spend.inputs[0].script_sig = unlock(&other);
+        assert_eq!(
+            blockchain.add_transaction(spend.clone()),
+            Err(TransactionError::ScriptFailed {
+                input: 0,
+                error: ScriptError::EvalFalse
+            })
+        );
+        let forged = mine_on_tip(&blockchain, vec![spend.clone()], INITIAL_SUBSIDY);
+        assert!(matches!(
+            blockchain.submit_block(forged),
+            Err(ChainError::Invalid(ValidationError {
+                kind: ValidationErrorKind::InvalidTransaction(UtxoError::ScriptFailed {
+                    input: 0,
+                    error: ScriptError::EvalFalse,
+                    ..
+                }),
+                ..
+            }))
+        ));
+
+        spend.inputs[0].script_sig = unlock(&spend);
+        blockchain.add_transaction(spend).unwrap();
+        blockchain.mine_pending_transactions().unwrap();
+        assert_eq!(blockchain.get_balance("alice"), 48 * COIN);
+        blockchain.validate_chain().unwrap();
     }
 
-    // Mine the second block
-    blockchain.mine_pending_transactions()?;
+    #[test]
+    fn test_transactions_wait_for_their_lock_time() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        let genesis_output = OutPoint::new(
+            blockchain
+                .get_block_by_height(0)
+                .unwrap()
+                .unwrap()
+                .transactions[0]
+                .txid(),
+            0,
+        );
+        let mut tx = Transaction::new(
+            vec![TxIn::new(genesis_output)],
+            vec![TxOut::to_address(49 * COIN, "alice")],
+        );
+        tx.lock_time = 1;
 
-    // Add transactions for the third block
-    println!("\n💳 Adding transactions to Block #3...");
-    let transactions_block3 = vec![
-        Transaction::new(
-            "tx_006".to_string(),
-            vec!["1JfbZRwdDHKZmuiZgYArJZhcuuzuw2HuMu".to_string()],
-            vec!["1F1tAaz5x1HUXrCNLbtMDqcw6o5GNn4xqX".to_string()],
-            4_00000000, // 4 BTC
-        ),
-        Transaction::new(
-            "tx_007".to_string(),
-            vec!["1dice8EMZmqKvrGE4Qc9bUFf9PX3xaYDp".to_string()],
-            vec!["1Eqk7vVT3w5GF8mJVWK5vJfYahb4D5nVZ7".to_string()],
-            6_50000000, // 6.5 BTC
-        ),
-        Transaction::new(
-            "tx_008".to_string(),
-            vec!["1BoatSLRHtKNngkdXEeobR76b53LETtpyT".to_string()],
-            vec!["1HLoD9E4SDFFPDiYfNYnkBLQ85Y51J3Zb1".to_string()],
-            3_75000000, // 3.75 BTC
-        ),
-    ];
+        // Final sequences waive the lock time
+        assert!(
+            blockchain
+                .check_transaction(&signed(tx.clone(), GENESIS_ADDRESS))
+                .is_ok()
+        );
 
-    for tx in transactions_block3 {
-        blockchain.add_transaction(tx);
+        // Locked until height 2, one past the next block
+        tx.inputs[0].sequence = 0;
+        let tx = signed(tx, GENESIS_ADDRESS);
+        assert_eq!(
+            blockchain.add_transaction(tx.clone()),
+            Err(TransactionError::NonFinal)
+        );
+        let early = mine_on_tip(&blockchain, vec![tx.clone()], INITIAL_SUBSIDY);
+        assert!(matches!(
+            blockchain.submit_block(early),
+            Err(ChainError::Invalid(ValidationError {
+                kind: ValidationErrorKind::NonFinalTransaction { txid },
+                ..
+            })) if txid == tx.txid()
+        ));
+
+        let empty = mine_on_tip(&blockchain, vec![], INITIAL_SUBSIDY);
+        blockchain.submit_block(empty).unwrap();
+        blockchain.add_transaction(tx).unwrap();
+        blockchain.mine_pending_transactions().unwrap();
+        assert_eq!(blockchain.get_balance("alice"), 49 * COIN);
+        blockchain.validate_chain().unwrap();
     }
 
-    // Mine the third block
-    blockchain.mine_pending_transactions()?;
+    #[test]
+    fn test_relative_lock_holds_coins_until_old_enough() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        // Spendable by anyone two blocks after it confirms
+        let locked = TxOut {
+            value: 49 * COIN,
+            script_pubkey: script::Builder::new()
+                .push_int(2)
+                .push_opcode(script::OP_CHECKSEQUENCEVERIFY)
+                .push_opcode(script::OP_DROP)
+                .push_int(1)
+                .into_bytes(),
+        };
+        let genesis_output = OutPoint::new(
+            blockchain
+                .get_block_by_height(0)
+                .unwrap()
+                .unwrap()
+                .transactions[0]
+                .txid(),
+            0,
+        );
+        let fund = signed(
+            Transaction::new(vec![TxIn::new(genesis_output)], vec![locked]),
+            GENESIS_ADDRESS,
+        );
+        blockchain.add_transaction(fund.clone()).unwrap();
+        blockchain.mine_pending_transactions().unwrap();
 
-    // Validate the entire blockchain
-    println!("\n🔍 Validating blockchain...");
-    blockchain.validate_chain();
+        let mut spend = Transaction::new(
+            vec![TxIn::new(OutPoint::new(fund.txid(), 0))],
+            vec![TxOut::to_address(48 * COIN, "alice")],
+        );
+        spend.version = 2;
+        assert_eq!(
+            blockchain.add_transaction(spend.clone()),
+            Err(TransactionError::ScriptFailed {
+                input: 0,
+                error: ScriptError::UnsatisfiedLockTime
+            })
+        );
 
-    // Print the complete blockchain
-    blockchain.print_chain();
+        // Satisfies the script, but the coin is only one block old at height 2
+        spend.inputs[0].sequence = 2;
+        assert_eq!(
+            blockchain.add_transaction(spend.clone()),
+            Err(TransactionError::SequenceLocked)
+        );
+        let early = mine_on_tip(&blockchain, vec![spend.clone()], INITIAL_SUBSIDY);
+        assert!(matches!(
+            blockchain.submit_block(early),
+            Err(ChainError::Invalid(ValidationError {
+                kind: ValidationErrorKind::SequenceLocked { txid },
+                ..
+            })) if txid == spend.txid()
+        ));
+
+        let empty = mine_on_tip(&blockchain, vec![], INITIAL_SUBSIDY);
+        blockchain.submit_block(empty).unwrap();
+        blockchain.add_transaction(spend).unwrap();
+        blockchain.mine_pending_transactions().unwrap();
+        assert_eq!(blockchain.get_balance("alice"), 48 * COIN);
+        blockchain.validate_chain().unwrap();
+    }
 
-    // Show some balance information
-    println!("\n💰 WALLET BALANCES");
-    println!("=====================================");
-    let addresses = vec![
-        "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", // Genesis
-        "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
-        "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
-        "1JfbZRwdDHKZmuiZgYArJZhcuuzuw2HuMu",
-        "1dice8EMZmqKvrGE4Qc9bUFf9PX3xaYDp",
-    ];
+    #[test]
+    fn test_time_based_sequence_locks() {
+        let mut tx = Transaction::new(
+            vec![TxIn::new(OutPoint::new([1; 32], 0))],
+            vec![TxOut::to_address(COIN, "alice")],
+        );
+        tx.version = 2;
+        // One 512 second unit after the median time past of 1000 before height 5
+        tx.inputs[0].sequence = script::SEQUENCE_LOCKTIME_TYPE_FLAG | 1;
+        let median_time_past = |height| if height == 4 { 1000 } else { 0 };
+        assert!(!tx.sequence_locks_met(&[5], 100, 1511, median_time_past));
+        assert!(tx.sequence_locks_met(&[5], 100, 1512, median_time_past));
+
+        tx.inputs[0].sequence |= script::SEQUENCE_LOCKTIME_DISABLE_FLAG;
+        assert!(tx.sequence_locks_met(&[5], 100, 0, median_time_past));
+        tx.inputs[0].sequence = 1;
+        assert!(!tx.sequence_locks_met(&[5], 5, 0, median_time_past));
+        tx.version = 1;
+        assert!(tx.sequence_locks_met(&[5], 5, 0, median_time_past));
+    }
 
-    for addr in addresses {
-        let balance = blockchain.get_balance(addr);
-        println!(
-            "{}: {} satoshis ({:.8} BTC)",
-            addr,
-            balance,
-            balance as f64 / 100_000_000.0
+    #[test]
+    fn test_block_merkle_proofs() {
+        let transactions: Vec<Transaction> = (0..3)
+            .map(|height| Transaction::coinbase(height, 0, "miner", COIN))
+            .collect();
+        let block = Block::new(1, [0; 32], transactions, 0x207fffff, 0);
+        let txid = block.transactions[2].txid();
+
+        let proof = block.merkle_proof(&txid).unwrap();
+        assert_eq!(proof.index, 2);
+        assert!(merkle::verify_merkle_proof(
+            &txid,
+            &proof,
+            &block.header.merkle_root
+        ));
+        assert_eq!(block.merkle_proof(&[0; 32]), None);
+
+        let bytes = block.merkle_block(&[txid]).serialize();
+        let merkle_block = MerkleBlock::deserialize(&bytes).unwrap();
+        assert_eq!(merkle_block.header, block.header);
+        assert_eq!(merkle_block.extract_matches(), Ok(vec![(txid, 2)]));
+
+        // A tree that doesn't commit to the header is refused
+        let mut forged = merkle_block;
+        forged.header.merkle_root = [0; 32];
+        assert!(matches!(
+            forged.extract_matches(),
+            Err(merkle::MerkleError::RootMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_chain_persists_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let params = ChainParams::custom(Target::from_leading_zero_bits(4));
+
+        let mut blockchain = Blockchain::open(dir.path(), params.clone()).unwrap();
+        blockchain.miner.reward_address = "miner".to_string();
+        let tx = blockchain
+            .create_transaction(GENESIS_ADDRESS, "alice", COIN)
+            .unwrap();
+        let fee = blockchain.check_transaction(&tx).unwrap();
+        blockchain.add_transaction(tx).unwrap();
+        blockchain.mine_pending_transactions().unwrap();
+        let tip = blockchain.get_latest_block().unwrap().hash();
+        drop(blockchain);
+
+        let reopened = Blockchain::open(dir.path(), params).unwrap();
+        assert_eq!(reopened.block_count(), 2);
+        assert_eq!(reopened.get_latest_block().unwrap().hash(), tip);
+        assert_eq!(reopened.get_balance("alice"), COIN);
+        assert_eq!(reopened.get_balance("miner"), INITIAL_SUBSIDY + fee);
+
+        // Stored blocks must belong to the chain they're loaded as
+        assert!(matches!(
+            Blockchain::open(dir.path(), ChainParams::regtest()),
+            Err(ChainError::Invalid(ValidationError {
+                height: 0,
+                kind: ValidationErrorKind::WrongGenesis,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_unreadable_block_is_reported_not_fatal() {
+        let dir = tempfile::tempdir().unwrap();
+        let params = ChainParams::custom(Target::from_leading_zero_bits(4));
+        let mut blockchain = Blockchain::open(dir.path(), params).unwrap();
+        let block = mine_on_tip(&blockchain, vec![], INITIAL_SUBSIDY);
+        blockchain.submit_block(block).unwrap();
+
+        // Flip the last payload byte of the tip's record behind the chain's back
+        let path = dir.path().join("blk00000.dat");
+        let mut bytes = std::fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, bytes).unwrap();
+
+        assert!(matches!(
+            blockchain.validate_chain(),
+            Err(ChainError::Store(StoreError::Corrupt { .. }))
+        ));
+        assert!(matches!(
+            blockchain.get_latest_block(),
+            Err(StoreError::Corrupt { .. })
+        ));
+        assert!(blockchain.get_block_by_height(0).unwrap().is_some());
+    }
+
+    // Mined block on top of `parent` at its difficulty, whatever the active chain
+    fn mine_on(
+        blockchain: &Blockchain,
+        parent: &Block,
+        transactions: Vec<Transaction>,
+        miner: &str,
+        reward: u64,
+    ) -> Block {
+        let height = parent.height + 1;
+        let mut block_transactions = vec![Transaction::coinbase(height, 0, miner, reward)];
+        block_transactions.extend(transactions);
+        let mut block = Block::new(
+            1,
+            parent.hash(),
+            block_transactions,
+            parent.header.bits,
+            height,
         );
+        block.header.timestamp = parent.header.timestamp + 1;
+        blockchain.miner.mine(block).unwrap().block
     }
 
-    println!("\n🎉 Mining simulation completed successfully!");
+    #[test]
+    fn test_reorg_to_heavier_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = Target::from_leading_zero_bits(4);
+        let params = ChainParams::custom(target);
+        let mut blockchain = Blockchain::open(dir.path(), params.clone()).unwrap();
+        let genesis = blockchain.get_block_by_height(0).unwrap().unwrap();
+        let payment = blockchain
+            .create_transaction(GENESIS_ADDRESS, "alice", COIN)
+            .unwrap();
+        let submit = |blockchain: &mut Blockchain, block: &Block| {
+            blockchain.submit_block(block.clone()).unwrap()
+        };
 
-    Ok(())
+        let a1 = mine_on(
+            &blockchain,
+            &genesis,
+            vec![payment.clone()],
+            "a",
+            INITIAL_SUBSIDY,
+        );
+        let a2 = mine_on(&blockchain, &a1, vec![], "a", INITIAL_SUBSIDY);
+        assert_eq!(submit(&mut blockchain, &a1), ChainUpdate::Extended);
+        assert_eq!(submit(&mut blockchain, &a2), ChainUpdate::Extended);
+
+        // A competing branch without the payment only wins once it has more work
+        let b1 = mine_on(&blockchain, &genesis, vec![], "b", INITIAL_SUBSIDY);
+        let b2 = mine_on(&blockchain, &b1, vec![], "b", INITIAL_SUBSIDY);
+        let b3 = mine_on(&blockchain, &b2, vec![], "b", INITIAL_SUBSIDY);
+        assert_eq!(submit(&mut blockchain, &b1), ChainUpdate::SideBranch);
+        assert_eq!(submit(&mut blockchain, &b2), ChainUpdate::SideBranch);
+        assert_eq!(blockchain.get_latest_block().unwrap().hash(), a2.hash());
+
+        assert_eq!(
+            submit(&mut blockchain, &b3),
+            ChainUpdate::Reorganized {
+                depth: 2,
+                fork_height: 0
+            }
+        );
+        assert_eq!(blockchain.get_latest_block().unwrap().hash(), b3.hash());
+        assert_eq!(blockchain.get_balance("a"), 0);
+        assert_eq!(blockchain.get_balance("b"), 3 * INITIAL_SUBSIDY);
+        assert_eq!(blockchain.get_balance("alice"), 0);
+        // The unconfirmed payment is back in the mempool
+        assert_eq!(blockchain.mempool.transactions(), vec![payment.clone()]);
+        blockchain.validate_chain().unwrap();
+
+        // The first branch can take over again, confirming the payment once more
+        let a3 = mine_on(&blockchain, &a2, vec![], "a", INITIAL_SUBSIDY);
+        let a4 = mine_on(&blockchain, &a3, vec![], "a", INITIAL_SUBSIDY);
+        assert_eq!(submit(&mut blockchain, &a3), ChainUpdate::SideBranch);
+        assert_eq!(
+            submit(&mut blockchain, &a4),
+            ChainUpdate::Reorganized {
+                depth: 3,
+                fork_height: 0
+            }
+        );
+        assert_eq!(blockchain.get_balance("alice"), COIN);
+        assert_eq!(blockchain.get_balance("b"), 0);
+        assert!(blockchain.mempool.is_empty());
+        assert_eq!(
+            blockchain.chainwork(),
+            genesis.header.target().unwrap().work() + 4 * target.work()
+        );
+        drop(blockchain);
+
+        // The store holds exactly the active chain
+        let reopened = Blockchain::open(dir.path(), params).unwrap();
+        assert_eq!(reopened.block_count(), 5);
+        assert_eq!(reopened.get_latest_block().unwrap().hash(), a4.hash());
+        assert_eq!(reopened.get_balance("alice"), COIN);
+    }
+
+    // A MemoryStore whose next inserts or pops can be made to fail, as a full or
+    // failing disk would
+    #[derive(Debug, Default)]
+    struct FlakyStore {
+        inner: MemoryStore,
+        // Operations of each kind left to succeed before one fails
+        inserts_before_failure: Rc<Cell<Option<u32>>>,
+        pops_before_failure: Rc<Cell<Option<u32>>>,
+    }
+
+    fn fail_after(countdown: &Cell<Option<u32>>) -> Result<(), StoreError> {
+        match countdown.get() {
+            Some(0) => {
+                countdown.set(None);
+                Err(StoreError::Io(std::io::Error::other("disk failure")))
+            }
+            remaining => {
+                countdown.set(remaining.map(|n| n - 1));
+                Ok(())
+            }
+        }
+    }
+
+    impl ChainStore for FlakyStore {
+        fn get(&self, hash: &[u8; 32]) -> Result<Option<Block>, StoreError> {
+            self.inner.get(hash)
+        }
+
+        fn get_by_height(&self, height: u32) -> Result<Option<Block>, StoreError> {
+            self.inner.get_by_height(height)
+        }
+
+        fn tip(&self) -> Option<[u8; 32]> {
+            self.inner.tip()
+        }
+
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+
+        fn insert(&mut self, block: Block) -> Result<(), StoreError> {
+            fail_after(&self.inserts_before_failure)?;
+            self.inner.insert(block)
+        }
+
+        fn pop(&mut self) -> Result<Option<Block>, StoreError> {
+            fail_after(&self.pops_before_failure)?;
+            self.inner.pop()
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = Result<Block, StoreError>> + '_> {
+            self.inner.iter()
+        }
+    }
+
+    #[test]
+    fn test_store_failure_restores_active_chain() {
+        let store = FlakyStore::default();
+        let inserts = store.inserts_before_failure.clone();
+        let pops = store.pops_before_failure.clone();
+        let params = ChainParams::custom(Target::from_leading_zero_bits(4));
+        let mut blockchain = Blockchain::with_store(Box::new(store), params).unwrap();
+        let genesis = blockchain.get_block_by_height(0).unwrap().unwrap();
+        let payment = blockchain
+            .create_transaction(GENESIS_ADDRESS, "alice", COIN)
+            .unwrap();
+
+        let a1 = mine_on(
+            &blockchain,
+            &genesis,
+            vec![payment.clone()],
+            "a",
+            INITIAL_SUBSIDY,
+        );
+        let a2 = mine_on(&blockchain, &a1, vec![], "a", INITIAL_SUBSIDY);
+        blockchain.submit_block(a1.clone()).unwrap();
+        blockchain.submit_block(a2.clone()).unwrap();
+        let mut branch = vec![genesis];
+        for _ in 0..5 {
+            let parent = branch.last().unwrap();
+            branch.push(mine_on(&blockchain, parent, vec![], "b", INITIAL_SUBSIDY));
+        }
+        for block in &branch[1..3] {
+            blockchain.submit_block(block.clone()).unwrap();
+        }
+        let assert_on_a2 = |blockchain: &Blockchain| {
+            assert_eq!(blockchain.get_latest_block().unwrap().hash(), a2.hash());
+            assert_eq!(blockchain.get_balance("alice"), COIN);
+            assert_eq!(blockchain.get_balance("b"), 0);
+            assert!(blockchain.mempool.is_empty());
+            blockchain.validate_chain().unwrap();
+        };
+
+        // Storing the first block of the heavier branch fails after the reorg has
+        // disconnected a1 and a2, so they're connected again
+        inserts.set(Some(0));
+        assert!(matches!(
+            blockchain.submit_block(branch[3].clone()),
+            Err(ChainError::Store(StoreError::Io(_)))
+        ));
+        assert_on_a2(&blockchain);
+
+        // Rewinding fails with a2 already off, so it goes back on
+        pops.set(Some(1));
+        assert!(matches!(
+            blockchain.submit_block(branch[4].clone()),
+            Err(ChainError::Store(StoreError::Io(_)))
+        ));
+        assert_on_a2(&blockchain);
+
+        // The branch blocks kept through the failures still win once the store works
+        assert_eq!(
+            blockchain.submit_block(branch[5].clone()).unwrap(),
+            ChainUpdate::Reorganized {
+                depth: 2,
+                fork_height: 0
+            }
+        );
+        assert_eq!(blockchain.get_balance("b"), 5 * INITIAL_SUBSIDY);
+        assert_eq!(blockchain.mempool.transactions(), vec![payment]);
+        blockchain.validate_chain().unwrap();
+    }
+
+    #[test]
+    fn test_invalid_branch_leaves_active_chain() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        let genesis = blockchain.get_block_by_height(0).unwrap().unwrap();
+        let a1 = mine_on(&blockchain, &genesis, vec![], "a", INITIAL_SUBSIDY);
+        blockchain.submit_block(a1.clone()).unwrap();
+
+        assert!(matches!(
+            blockchain.submit_block(a1.clone()),
+            Err(ChainError::Invalid(ValidationError {
+                kind: ValidationErrorKind::DuplicateBlock,
+                ..
+            }))
+        ));
+        let mut orphan = a1.clone();
+        orphan.header.previous_hash = [0xaa; 32];
+        assert!(matches!(
+            blockchain.submit_block(orphan),
+            Err(ChainError::Invalid(ValidationError {
+                kind: ValidationErrorKind::UnknownParent,
+                ..
+            }))
+        ));
+
+        // The heavier branch overpays its coinbase, which only shows when connecting it
+        let b1 = mine_on(&blockchain, &genesis, vec![], "b", INITIAL_SUBSIDY);
+        let b2 = mine_on(&blockchain, &b1, vec![], "b", INITIAL_SUBSIDY + 1);
+        assert_eq!(
+            blockchain.submit_block(b1.clone()).unwrap(),
+            ChainUpdate::SideBranch
+        );
+        assert!(matches!(
+            blockchain.submit_block(b2.clone()),
+            Err(ChainError::Invalid(ValidationError {
+                height: 2,
+                kind: ValidationErrorKind::CoinbaseOverpays { .. },
+                ..
+            }))
+        ));
+
+        assert_eq!(blockchain.get_latest_block().unwrap().hash(), a1.hash());
+        assert_eq!(blockchain.get_balance("a"), INITIAL_SUBSIDY);
+        assert_eq!(blockchain.get_balance("b"), 0);
+        blockchain.validate_chain().unwrap();
+
+        let b3 = mine_on(&blockchain, &b2, vec![], "b", INITIAL_SUBSIDY);
+        assert!(matches!(
+            blockchain.submit_block(b3),
+            Err(ChainError::Invalid(ValidationError {
+                kind: ValidationErrorKind::InvalidParent,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_difficulty_retargets_every_interval() {
+        let params = ChainParams {
+            retarget_interval: 4,
+            ..ChainParams::custom(Target::from_leading_zero_bits(4))
+        };
+        let mut blockchain = Blockchain::new(params);
+        let mut tip = blockchain.get_block_by_height(0).unwrap().unwrap();
+        for _ in 1..4 {
+            tip = mine_on(&blockchain, &tip, vec![], "miner", INITIAL_SUBSIDY);
+            blockchain.submit_block(tip.clone()).unwrap();
+        }
+
+        // Four blocks a second apart instead of ten minutes: the target shrinks by the
+        // most it can
+        let expected = Target::from_leading_zero_bits(6).to_compact();
+        assert_eq!(blockchain.next_bits(), expected);
+
+        let stale = mine_on(&blockchain, &tip, vec![], "miner", INITIAL_SUBSIDY);
+        assert!(matches!(
+            blockchain.submit_block(stale.clone()),
+            Err(ChainError::Invalid(ValidationError {
+                kind: ValidationErrorKind::BadBits { expected: bits },
+                ..
+            })) if bits == expected
+        ));
+
+        let mut retargeted = stale;
+        retargeted.header.bits = expected;
+        let retargeted = blockchain.miner.mine(retargeted).unwrap().block;
+        blockchain.submit_block(retargeted).unwrap();
+        // Blocks within the interval keep the new target
+        assert_eq!(blockchain.next_bits(), expected);
+        blockchain.validate_chain().unwrap();
+
+        blockchain.miner.reward_address = "miner".to_string();
+        let tx = blockchain
+            .create_transaction(GENESIS_ADDRESS, "alice", COIN)
+            .unwrap();
+        blockchain.add_transaction(tx).unwrap();
+        blockchain.mine_pending_transactions().unwrap();
+        assert_eq!(blockchain.get_latest_block().unwrap().header.bits, expected);
+    }
+
+    #[test]
+    fn test_chain_keeps_its_difficulty_algorithm() {
+        let dir = tempfile::tempdir().unwrap();
+        let params = ChainParams::custom(Target::from_leading_zero_bits(4));
+        // Blocks a second apart are 599 seconds early, a whole half-life
+        let asert = difficulty::Asert {
+            half_life: 599,
+            ..difficulty::Asert::new(params.pow_limit)
+        };
+        let open = |difficulty: Box<dyn DifficultyAlgorithm>| {
+            let store = Box::new(FileStore::open(dir.path()).unwrap());
+            Blockchain::with_difficulty(store, params.clone(), difficulty)
+        };
+
+        let mut blockchain = open(Box::new(asert.clone())).unwrap();
+        let genesis = blockchain.get_block_by_height(0).unwrap().unwrap();
+        let first = mine_on(&blockchain, &genesis, vec![], "miner", INITIAL_SUBSIDY);
+        blockchain.submit_block(first.clone()).unwrap();
+
+        // ASERT halves the target where Bitcoin's retargeting would keep it
+        let bits = blockchain.next_bits();
+        assert_eq!(bits, Target::from_leading_zero_bits(5).to_compact());
+        let coinbase = Transaction::coinbase(2, 0, "miner", INITIAL_SUBSIDY);
+        let mut second = Block::new(1, first.hash(), vec![coinbase], bits, 2);
+        second.header.timestamp = first.header.timestamp + 1;
+        let second = blockchain.miner.mine(second).unwrap().block;
+        blockchain.submit_block(second).unwrap();
+        drop(blockchain);
+
+        assert_eq!(open(Box::new(asert)).unwrap().block_count(), 3);
+        assert!(matches!(
+            open(params.difficulty()),
+            Err(ChainError::Invalid(ValidationError {
+                height: 2,
+                kind: ValidationErrorKind::BadBits { .. },
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_block_timestamp_bounds() {
+        let mut blockchain =
+            Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+        let genesis = blockchain.get_block_by_height(0).unwrap().unwrap();
+        let now = genesis.header.timestamp + 1000;
+        let clock = Arc::new(ManualClock::new(now));
+        blockchain.set_clock(clock.clone());
+        let mine_at = |timestamp: u32| {
+            let coinbase = Transaction::coinbase(1, 0, "miner", INITIAL_SUBSIDY);
+            let mut block = Block::new(1, genesis.hash(), vec![coinbase], genesis.header.bits, 1);
+            block.header.timestamp = timestamp;
+            blockchain.miner.mine(block).unwrap().block
+        };
+
+        let too_late = mine_at(now + MAX_FUTURE_BLOCK_TIME + 1);
+        let latest = mine_at(now + MAX_FUTURE_BLOCK_TIME);
+        assert!(matches!(
+            blockchain.submit_block(too_late.clone()),
+            Err(ChainError::Invalid(ValidationError {
+                kind: ValidationErrorKind::TimestampTooFarAhead { latest },
+                ..
+            })) if latest == now + MAX_FUTURE_BLOCK_TIME
+        ));
+        assert_eq!(
+            blockchain.submit_block(latest).unwrap(),
+            ChainUpdate::Extended
+        );
+
+        // Once the clock catches up, the same block is fine
+        clock.set(now + 1);
+        assert_eq!(
+            blockchain.submit_block(too_late).unwrap(),
+            ChainUpdate::SideBranch
+        );
+
+        // With the clock behind the median time past, the miner stamps just past that
+        let tx = blockchain
+            .create_transaction(GENESIS_ADDRESS, "alice", COIN)
+            .unwrap();
+        blockchain.add_transaction(tx).unwrap();
+        let median = blockchain.median_time_past();
+        assert!(median > now + 1);
+        blockchain.mine_pending_transactions().unwrap();
+        assert_eq!(
+            blockchain.get_latest_block().unwrap().header.timestamp,
+            median + 1
+        );
+
+        // Otherwise it stamps the clock's time
+        clock.set(median + 600);
+        let tx = blockchain
+            .create_transaction("alice", "bob", COIN / 2)
+            .unwrap();
+        blockchain.add_transaction(tx).unwrap();
+        blockchain.mine_pending_transactions().unwrap();
+        assert_eq!(
+            blockchain.get_latest_block().unwrap().header.timestamp,
+            median + 600
+        );
+    }
+
+    #[test]
+    fn test_pinned_clock_mines_identical_chains() {
+        let mine_chain = || {
+            let mut blockchain =
+                Blockchain::new(ChainParams::custom(Target::from_leading_zero_bits(4)));
+            let clock = Arc::new(ManualClock::new(1_700_000_000));
+            blockchain.set_clock(clock.clone());
+            blockchain.miner.threads = 1;
+
+            for amount in [COIN, 2 * COIN] {
+                let tx = blockchain
+                    .create_transaction(GENESIS_ADDRESS, "alice", amount)
+                    .unwrap();
+                blockchain.add_transaction(tx).unwrap();
+                blockchain.mine_pending_transactions().unwrap();
+                clock.advance(Duration::from_secs(600));
+            }
+            blockchain
+        };
+
+        let first = mine_chain();
+        let second = mine_chain();
+        assert_eq!(
+            first
+                .get_block_by_height(0)
+                .unwrap()
+                .unwrap()
+                .header
+                .timestamp,
+            GENESIS_TIMESTAMP
+        );
+        assert_eq!(
+            first
+                .get_block_by_height(1)
+                .unwrap()
+                .unwrap()
+                .header
+                .timestamp,
+            1_700_000_000
+        );
+        assert_eq!(
+            first.get_latest_block().unwrap().header.timestamp,
+            1_700_000_600
+        );
+        for height in 0..3 {
+            assert_eq!(
+                first.get_block_by_height(height).unwrap().unwrap().hash(),
+                second.get_block_by_height(height).unwrap().unwrap().hash()
+            );
+        }
+    }
 }
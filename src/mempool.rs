@@ -0,0 +1,425 @@
+use crate::{Block, OutPoint, Transaction, WITNESS_SCALE_FACTOR};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+// Bitcoin Core's -maxmempool default, in bytes of serialized transactions
+pub const DEFAULT_MAX_MEMPOOL_SIZE: usize = 300_000_000;
+// Bitcoin Core's -mempoolexpiry default of two weeks, in seconds
+pub const DEFAULT_MEMPOOL_EXPIRY: u32 = 14 * 24 * 60 * 60;
+// Bitcoin Core's -minrelaytxfee default, in satoshis per 1000 bytes
+pub const DEFAULT_MIN_RELAY_FEE_RATE: u64 = 1000;
+
+// Fee a transaction of `size` bytes pays at `fee_rate` satoshis per 1000 bytes,
+// rounded up
+pub fn fee_for_size(fee_rate: u64, size: usize) -> u64 {
+    (fee_rate as u128 * size as u128).div_ceil(1000) as u64
+}
+
+// A transaction waiting to be mined, with what it pays and when it arrived
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MempoolEntry {
+    pub transaction: Transaction,
+    pub txid: [u8; 32],
+    pub fee: u64,
+    pub size: usize,
+    pub time: u32,
+    // Arrival order, which puts parents before their children
+    sequence: u64,
+}
+
+impl MempoolEntry {
+    // Satoshis per 1000 bytes, the unit Bitcoin Core quotes fee rates in
+    pub fn fee_rate(&self) -> u64 {
+        (self.fee as u128 * 1000 / self.size.max(1) as u128) as u64
+    }
+
+    pub fn weight(&self) -> usize {
+        self.size * WITNESS_SCALE_FACTOR
+    }
+}
+
+// Fee rate first so the lowest-paying entry sorts first, newest first among equals
+type FeeRateKey = (u64, Reverse<u64>, [u8; 32]);
+
+// Unconfirmed transactions, held up to `max_size` bytes and for at most `expiry`
+// seconds. Entries are validated before they're added; the pool only tracks how they
+// depend on and conflict with each other.
+#[derive(Debug, Clone)]
+pub struct Mempool {
+    pub max_size: usize,
+    pub expiry: u32,
+    // Relay policy: new transactions must pay at least this many satoshis per 1000 bytes
+    pub min_fee_rate: u64,
+    entries: HashMap<[u8; 32], MempoolEntry>,
+    by_fee_rate: BTreeSet<FeeRateKey>,
+    // Which entry spends each outpoint
+    spenders: HashMap<OutPoint, [u8; 32]>,
+    total_size: usize,
+    next_sequence: u64,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_MEMPOOL_SIZE, DEFAULT_MEMPOOL_EXPIRY)
+    }
+}
+
+impl Mempool {
+    pub fn new(max_size: usize, expiry: u32) -> Self {
+        Self {
+            max_size,
+            expiry,
+            min_fee_rate: DEFAULT_MIN_RELAY_FEE_RATE,
+            entries: HashMap::new(),
+            by_fee_rate: BTreeSet::new(),
+            spenders: HashMap::new(),
+            total_size: 0,
+            next_sequence: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Serialized bytes of every entry, which `max_size` bounds
+    pub fn total_size(&self) -> usize {
+        self.total_size
+    }
+
+    pub fn contains(&self, txid: &[u8; 32]) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    pub fn get(&self, txid: &[u8; 32]) -> Option<&MempoolEntry> {
+        self.entries.get(txid)
+    }
+
+    // The entry spending `outpoint`, if any
+    pub fn spender(&self, outpoint: &OutPoint) -> Option<[u8; 32]> {
+        self.spenders.get(outpoint).copied()
+    }
+
+    // Entries in arrival order, so every transaction comes after those it spends from
+    pub fn entries(&self) -> Vec<&MempoolEntry> {
+        let mut entries: Vec<_> = self.entries.values().collect();
+        entries.sort_by_key(|entry| entry.sequence);
+        entries
+    }
+
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.entries()
+            .into_iter()
+            .map(|entry| entry.transaction.clone())
+            .collect()
+    }
+
+    // Whether a transaction of `size` bytes paying `fee` meets the relay policy. Not
+    // checked by `add`, so transactions from disconnected blocks can return whatever
+    // they pay.
+    pub fn check_fee(&self, fee: u64, size: usize) -> Result<(), MempoolError> {
+        let required = fee_for_size(self.min_fee_rate, size);
+        if fee < required {
+            return Err(MempoolError::FeeTooLow { fee, required });
+        }
+        Ok(())
+    }
+
+    // Entries from the highest fee rate to the lowest
+    pub fn by_fee_rate(&self) -> impl Iterator<Item = &MempoolEntry> {
+        self.by_fee_rate
+            .iter()
+            .rev()
+            .map(|(_, _, txid)| &self.entries[txid])
+    }
+
+    // Add a transaction that was validated against the chain and this pool, paying
+    // `fee` and arriving at `time`. If that takes the pool over its size limit, the
+    // lowest fee rate entries are evicted along with anything spending from them.
+    // Returns the evicted entries.
+    pub fn add(
+        &mut self,
+        transaction: Transaction,
+        fee: u64,
+        time: u32,
+    ) -> Result<Vec<MempoolEntry>, MempoolError> {
+        let txid = transaction.txid();
+        let entry = MempoolEntry {
+            size: transaction.size(),
+            transaction,
+            txid,
+            fee,
+            time,
+            sequence: self.next_sequence,
+        };
+        self.next_sequence += 1;
+        self.insert(entry);
+
+        let mut evicted = Vec::new();
+        while self.total_size > self.max_size {
+            let (_, _, lowest) = *self.by_fee_rate.first().unwrap();
+            evicted.extend(self.remove(&lowest));
+        }
+        // A transaction paying too little to stay leaves the pool as it was, so whatever
+        // it pushed out on the way goes back in
+        if evicted.iter().any(|entry| entry.txid == txid) {
+            for entry in evicted.into_iter().filter(|entry| entry.txid != txid) {
+                self.insert(entry);
+            }
+            return Err(MempoolError::Full);
+        }
+        Ok(evicted)
+    }
+
+    // Remove an entry and every entry spending from it, which would otherwise be left
+    // spending outputs that no longer exist
+    pub fn remove(&mut self, txid: &[u8; 32]) -> Vec<MempoolEntry> {
+        let mut removed = Vec::new();
+        let mut queue = vec![*txid];
+        while let Some(txid) = queue.pop() {
+            let Some(entry) = self.take(&txid) else {
+                continue;
+            };
+            for vout in 0..entry.transaction.outputs.len() {
+                if let Some(child) = self.spender(&OutPoint::new(txid, vout as u32)) {
+                    queue.push(child);
+                }
+            }
+            removed.push(entry);
+        }
+        removed
+    }
+
+    // Drop entries that arrived more than `expiry` seconds before `now`
+    pub fn expire(&mut self, now: u32) -> Vec<MempoolEntry> {
+        let cutoff = now.saturating_sub(self.expiry);
+        let expired: Vec<[u8; 32]> = self
+            .entries
+            .values()
+            .filter(|entry| entry.time < cutoff)
+            .map(|entry| entry.txid)
+            .collect();
+        expired.iter().flat_map(|txid| self.remove(txid)).collect()
+    }
+
+    // Drop the transactions `block` confirms, whose children stay valid, and those
+    // spending the same outputs, whose children don't
+    pub fn remove_for_block(&mut self, block: &Block) -> Vec<MempoolEntry> {
+        let mut removed = Vec::new();
+        for tx in block.transactions.iter().skip(1) {
+            if let Some(entry) = self.take(&tx.txid()) {
+                removed.push(entry);
+                continue;
+            }
+            for input in &tx.inputs {
+                if let Some(conflict) = self.spender(&input.previous_output) {
+                    removed.extend(self.remove(&conflict));
+                }
+            }
+        }
+        removed
+    }
+
+    // Remove every entry, in arrival order
+    pub fn clear(&mut self) -> Vec<MempoolEntry> {
+        let mut entries: Vec<_> = self.entries.drain().map(|(_, entry)| entry).collect();
+        entries.sort_by_key(|entry| entry.sequence);
+        self.by_fee_rate.clear();
+        self.spenders.clear();
+        self.total_size = 0;
+        entries
+    }
+
+    fn insert(&mut self, entry: MempoolEntry) {
+        for input in &entry.transaction.inputs {
+            self.spenders.insert(input.previous_output, entry.txid);
+        }
+        self.by_fee_rate.insert(fee_rate_key(&entry));
+        self.total_size += entry.size;
+        self.entries.insert(entry.txid, entry);
+    }
+
+    // Remove just this entry
+    fn take(&mut self, txid: &[u8; 32]) -> Option<MempoolEntry> {
+        let entry = self.entries.remove(txid)?;
+        for input in &entry.transaction.inputs {
+            self.spenders.remove(&input.previous_output);
+        }
+        self.by_fee_rate.remove(&fee_rate_key(&entry));
+        self.total_size -= entry.size;
+        Some(entry)
+    }
+}
+
+fn fee_rate_key(entry: &MempoolEntry) -> FeeRateKey {
+    (entry.fee_rate(), Reverse(entry.sequence), entry.txid)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MempoolError {
+    // The transaction's fee rate is too low to displace anything already waiting
+    Full,
+    FeeTooLow { fee: u64, required: u64 },
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MempoolError::Full => write!(f, "Mempool is full and the fee rate is too low"),
+            MempoolError::FeeTooLow { fee, required } => write!(
+                f,
+                "Fee of {} satoshis is below the minimum relay fee of {}",
+                fee, required
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TxIn, TxOut};
+
+    // A transaction spending `outpoint` into `outputs` outputs
+    fn spend(outpoint: OutPoint, outputs: usize) -> Transaction {
+        Transaction::new(
+            vec![TxIn::new(outpoint)],
+            (0..outputs).map(|_| TxOut::to_address(1, "bob")).collect(),
+        )
+    }
+
+    fn funding(n: u8) -> OutPoint {
+        OutPoint::new([n; 32], 0)
+    }
+
+    #[test]
+    fn test_orders_by_fee_rate() {
+        let mut mempool = Mempool::default();
+        let low = spend(funding(1), 1);
+        let high = spend(funding(2), 1);
+        let middle = spend(funding(3), 1);
+        mempool.add(low.clone(), 100, 0).unwrap();
+        mempool.add(high.clone(), 300, 0).unwrap();
+        mempool.add(middle.clone(), 200, 0).unwrap();
+
+        let order: Vec<_> = mempool.by_fee_rate().map(|entry| entry.txid).collect();
+        assert_eq!(order, vec![high.txid(), middle.txid(), low.txid()]);
+        assert_eq!(mempool.transactions(), vec![low, high, middle]);
+        assert_eq!(
+            mempool.total_size(),
+            mempool
+                .entries()
+                .iter()
+                .map(|entry| entry.size)
+                .sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn test_evicts_lowest_fee_rate_when_full() {
+        let parent = spend(funding(1), 1);
+        let child = spend(OutPoint::new(parent.txid(), 0), 1);
+        let rich = spend(funding(2), 1);
+        let size = parent.size() + child.size();
+        let mut mempool = Mempool::new(size, DEFAULT_MEMPOOL_EXPIRY);
+        mempool.add(parent.clone(), 100, 0).unwrap();
+        mempool.add(child.clone(), 1000, 0).unwrap();
+
+        // Too cheap to displace anything: the pool is unchanged
+        let poor = spend(funding(3), 1);
+        assert_eq!(mempool.add(poor, 1, 0), Err(MempoolError::Full));
+        assert_eq!(mempool.len(), 2);
+
+        // Evicting the parent takes its child with it
+        let evicted = mempool.add(rich.clone(), 500, 0).unwrap();
+        let evicted: Vec<_> = evicted.iter().map(|entry| entry.txid).collect();
+        assert_eq!(evicted, vec![parent.txid(), child.txid()]);
+        assert_eq!(mempool.transactions(), vec![rich]);
+        assert_eq!(mempool.spender(&funding(1)), None);
+    }
+
+    #[test]
+    fn test_rejected_transaction_restores_what_it_evicted() {
+        let cheap = spend(funding(1), 1);
+        let rich = spend(funding(2), 1);
+        let mut mempool = Mempool::new(cheap.size() + rich.size(), DEFAULT_MEMPOOL_EXPIRY);
+        mempool.add(cheap.clone(), 100, 0).unwrap();
+        mempool.add(rich.clone(), 1000, 0).unwrap();
+
+        // Outbids `cheap` but is too big to fit without also displacing `rich`
+        let large = spend(funding(3), 2);
+        assert!(large.size() > cheap.size());
+        assert_eq!(mempool.add(large, 500, 0), Err(MempoolError::Full));
+        assert_eq!(mempool.transactions(), vec![cheap, rich]);
+        assert_eq!(
+            mempool.spender(&funding(1)),
+            Some(mempool.entries()[0].txid)
+        );
+        assert_eq!(mempool.spender(&funding(3)), None);
+        assert_eq!(mempool.total_size(), mempool.max_size);
+    }
+
+    #[test]
+    fn test_minimum_relay_fee() {
+        let mempool = Mempool::default();
+        assert_eq!(fee_for_size(1000, 250), 250);
+        assert_eq!(fee_for_size(1500, 225), 338);
+        assert_eq!(mempool.check_fee(250, 250), Ok(()));
+        assert_eq!(
+            mempool.check_fee(249, 250),
+            Err(MempoolError::FeeTooLow {
+                fee: 249,
+                required: 250
+            })
+        );
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut mempool = Mempool::new(DEFAULT_MAX_MEMPOOL_SIZE, 100);
+        let old = spend(funding(1), 1);
+        let new = spend(funding(2), 1);
+        mempool.add(old.clone(), 100, 1000).unwrap();
+        mempool.add(new.clone(), 100, 1050).unwrap();
+
+        assert!(mempool.expire(1100).is_empty());
+        let expired = mempool.expire(1101);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].txid, old.txid());
+        assert_eq!(mempool.transactions(), vec![new]);
+    }
+
+    #[test]
+    fn test_remove_for_block() {
+        let mut mempool = Mempool::default();
+        let confirmed = spend(funding(1), 1);
+        let child = spend(OutPoint::new(confirmed.txid(), 0), 1);
+        let conflicted = spend(funding(2), 1);
+        let grandchild = spend(OutPoint::new(conflicted.txid(), 0), 1);
+        let unrelated = spend(funding(3), 1);
+        for tx in [&confirmed, &child, &conflicted, &grandchild, &unrelated] {
+            mempool.add(tx.clone(), 100, 0).unwrap();
+        }
+
+        // The block double-spends `conflicted`'s input
+        let double_spend = spend(funding(2), 2);
+        let coinbase = Transaction::coinbase(1, 0, "miner", 50);
+        let block = Block::new(
+            1,
+            [0; 32],
+            vec![coinbase, confirmed, double_spend],
+            0x207fffff,
+            1,
+        );
+
+        assert_eq!(mempool.remove_for_block(&block).len(), 3);
+        assert_eq!(mempool.transactions(), vec![child, unrelated]);
+    }
+}
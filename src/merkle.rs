@@ -0,0 +1,483 @@
+use crate::encode::{Reader, write_compact_size};
+use crate::{BLOCK_HEADER_SIZE, BlockHeader, DecodeError, double_sha256, hash_to_hex};
+use std::fmt;
+
+// Parent of two nodes: double SHA-256 of their concatenation in internal byte order
+pub fn merkle_parent(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut data = [0u8; 64];
+    data[..32].copy_from_slice(left);
+    data[32..].copy_from_slice(right);
+    double_sha256(&data)
+}
+
+// Bitcoin merkle root of a list of txids. Odd levels pair their last hash with itself,
+// and an empty list yields all zeros, as in Bitcoin Core.
+//
+// The second value is true when some level pairs two identical hashes (CVE-2012-2459):
+// a block whose trailing transactions were duplicated would then share the root of
+// the original, so such trees must be rejected rather than trusted.
+pub fn merkle_root(txids: &[[u8; 32]]) -> ([u8; 32], bool) {
+    if txids.is_empty() {
+        return ([0; 32], false);
+    }
+
+    let mut level = txids.to_vec();
+    let mut mutated = false;
+
+    while level.len() > 1 {
+        mutated |= level
+            .chunks(2)
+            .any(|pair| pair.len() == 2 && pair[0] == pair[1]);
+
+        level = level
+            .chunks(2)
+            .map(|pair| merkle_parent(&pair[0], pair.last().unwrap()))
+            .collect();
+    }
+
+    (level[0], mutated)
+}
+
+// Sibling hashes from a transaction up to the root. Bit i of `index` says whether the
+// node at level i is a right child.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub index: u32,
+    pub branch: Vec<[u8; 32]>,
+}
+
+// Proof that the txid at `index` is part of the tree over `txids`
+pub fn merkle_proof(txids: &[[u8; 32]], index: usize) -> Option<MerkleProof> {
+    if index >= txids.len() {
+        return None;
+    }
+
+    let mut branch = Vec::new();
+    let mut level = txids.to_vec();
+    let mut position = index;
+
+    while level.len() > 1 {
+        // A last node without a sibling was paired with itself
+        let sibling = level.get(position ^ 1).unwrap_or(&level[position]);
+        branch.push(*sibling);
+
+        level = level
+            .chunks(2)
+            .map(|pair| merkle_parent(&pair[0], pair.last().unwrap()))
+            .collect();
+        position /= 2;
+    }
+
+    Some(MerkleProof {
+        index: index as u32,
+        branch,
+    })
+}
+
+pub fn verify_merkle_proof(txid: &[u8; 32], proof: &MerkleProof, merkle_root: &[u8; 32]) -> bool {
+    // Index bits above the branch length would name a position outside the tree
+    if proof.branch.len() < 32 && proof.index >> proof.branch.len() != 0 {
+        return false;
+    }
+
+    let mut hash = *txid;
+    for (level, sibling) in proof.branch.iter().enumerate() {
+        hash = if (proof.index >> level) & 1 == 1 {
+            merkle_parent(sibling, &hash)
+        } else {
+            merkle_parent(&hash, sibling)
+        };
+    }
+    hash == *merkle_root
+}
+
+// BIP37 partial merkle tree: the hashes and depth-first traversal flags needed to
+// rebuild the root from a subset of matched transactions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialMerkleTree {
+    pub total_transactions: u32,
+    pub hashes: Vec<[u8; 32]>,
+    pub flags: Vec<bool>,
+}
+
+impl PartialMerkleTree {
+    // `matches[i]` says whether txids[i] should be extractable from the tree
+    pub fn new(txids: &[[u8; 32]], matches: &[bool]) -> Self {
+        assert_eq!(txids.len(), matches.len());
+
+        let mut tree = Self {
+            total_transactions: txids.len() as u32,
+            hashes: Vec::new(),
+            flags: Vec::new(),
+        };
+        tree.build(tree.height(), 0, txids, matches);
+        tree
+    }
+
+    // Number of nodes at `height` above the leaves
+    fn width(&self, height: u32) -> u32 {
+        ((self.total_transactions as u64 + (1 << height) - 1) >> height) as u32
+    }
+
+    fn height(&self) -> u32 {
+        let mut height = 0;
+        while self.width(height) > 1 {
+            height += 1;
+        }
+        height
+    }
+
+    fn hash(&self, height: u32, position: u32, txids: &[[u8; 32]]) -> [u8; 32] {
+        if height == 0 {
+            return txids[position as usize];
+        }
+
+        let left = self.hash(height - 1, position * 2, txids);
+        let right = if position * 2 + 1 < self.width(height - 1) {
+            self.hash(height - 1, position * 2 + 1, txids)
+        } else {
+            left
+        };
+        merkle_parent(&left, &right)
+    }
+
+    fn build(&mut self, height: u32, position: u32, txids: &[[u8; 32]], matches: &[bool]) {
+        let first = (position as usize) << height;
+        let last = ((position as usize + 1) << height).min(txids.len());
+        let parent_of_match = matches[first..last].iter().any(|matched| *matched);
+        self.flags.push(parent_of_match);
+
+        if height == 0 || !parent_of_match {
+            let hash = self.hash(height, position, txids);
+            self.hashes.push(hash);
+        } else {
+            self.build(height - 1, position * 2, txids, matches);
+            if position * 2 + 1 < self.width(height - 1) {
+                self.build(height - 1, position * 2 + 1, txids, matches);
+            }
+        }
+    }
+
+    // Recompute the merkle root, collecting the matched txids and their positions.
+    // The caller must compare the root against a header it trusts.
+    pub fn extract_matches(&self) -> Result<([u8; 32], Vec<MerkleMatch>), MerkleError> {
+        if self.total_transactions == 0 {
+            return Err(MerkleError::NoTransactions);
+        }
+        if self.hashes.len() > self.total_transactions as usize {
+            return Err(MerkleError::TooManyHashes);
+        }
+        if self.flags.len() < self.hashes.len() {
+            return Err(MerkleError::NotEnoughFlags);
+        }
+
+        let mut traversal = Traversal {
+            flags_used: 0,
+            hashes_used: 0,
+            matches: Vec::new(),
+        };
+        let root = self.extract(self.height(), 0, &mut traversal)?;
+
+        // Everything must be consumed, apart from padding bits in the last flag byte
+        if traversal.flags_used.div_ceil(8) != self.flags.len().div_ceil(8) {
+            return Err(MerkleError::UnusedFlags);
+        }
+        if traversal.hashes_used != self.hashes.len() {
+            return Err(MerkleError::UnusedHashes);
+        }
+
+        Ok((root, traversal.matches))
+    }
+
+    fn extract(
+        &self,
+        height: u32,
+        position: u32,
+        traversal: &mut Traversal,
+    ) -> Result<[u8; 32], MerkleError> {
+        let parent_of_match = *self
+            .flags
+            .get(traversal.flags_used)
+            .ok_or(MerkleError::NotEnoughFlags)?;
+        traversal.flags_used += 1;
+
+        if height == 0 || !parent_of_match {
+            let hash = *self
+                .hashes
+                .get(traversal.hashes_used)
+                .ok_or(MerkleError::NotEnoughHashes)?;
+            traversal.hashes_used += 1;
+            if height == 0 && parent_of_match {
+                traversal.matches.push((hash, position));
+            }
+            return Ok(hash);
+        }
+
+        let left = self.extract(height - 1, position * 2, traversal)?;
+        let right = if position * 2 + 1 < self.width(height - 1) {
+            let right = self.extract(height - 1, position * 2 + 1, traversal)?;
+            if right == left {
+                return Err(MerkleError::DuplicateHashes);
+            }
+            right
+        } else {
+            left
+        };
+        Ok(merkle_parent(&left, &right))
+    }
+
+    // Wire format: u32 transaction count, CompactSize-counted hashes, then the flags
+    // packed least significant bit first into CompactSize-counted bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.total_transactions.to_le_bytes());
+
+        write_compact_size(&mut bytes, self.hashes.len() as u64);
+        for hash in &self.hashes {
+            bytes.extend_from_slice(hash);
+        }
+
+        let mut flag_bytes = vec![0u8; self.flags.len().div_ceil(8)];
+        for (i, flag) in self.flags.iter().enumerate() {
+            flag_bytes[i / 8] |= u8::from(*flag) << (i % 8);
+        }
+        write_compact_size(&mut bytes, flag_bytes.len() as u64);
+        bytes.extend_from_slice(&flag_bytes);
+
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let tree = Self::read(&mut reader)?;
+        reader.finish()?;
+        Ok(tree)
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let total_transactions = reader.read_u32()?;
+
+        let hash_count = reader.read_count()?;
+        let hashes = (0..hash_count)
+            .map(|_| reader.read_array::<32>())
+            .collect::<Result<_, _>>()?;
+
+        let flag_count = reader.read_count()?;
+        let flags = reader
+            .read_bytes(flag_count)?
+            .iter()
+            .flat_map(|byte| (0..8).map(move |bit| (byte >> bit) & 1 == 1))
+            .collect();
+
+        Ok(Self {
+            total_transactions,
+            hashes,
+            flags,
+        })
+    }
+}
+
+// A matched txid and its position in the block
+pub type MerkleMatch = ([u8; 32], u32);
+
+// Progress through the hashes and flags while rebuilding a partial tree
+struct Traversal {
+    flags_used: usize,
+    hashes_used: usize,
+    matches: Vec<MerkleMatch>,
+}
+
+// A block header with a partial merkle tree over some of its transactions, as sent to
+// light clients in a `merkleblock` message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleBlock {
+    pub header: BlockHeader,
+    pub tree: PartialMerkleTree,
+}
+
+impl MerkleBlock {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = self.header.serialize().to_vec();
+        bytes.extend(self.tree.serialize());
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let header = BlockHeader::deserialize(reader.read_bytes(BLOCK_HEADER_SIZE)?)?;
+        let tree = PartialMerkleTree::read(&mut reader)?;
+        reader.finish()?;
+        Ok(Self { header, tree })
+    }
+
+    // Matched txids with their positions, provided the tree commits to the header's root
+    pub fn extract_matches(&self) -> Result<Vec<MerkleMatch>, MerkleError> {
+        let (root, matches) = self.tree.extract_matches()?;
+        if root != self.header.merkle_root {
+            return Err(MerkleError::RootMismatch { root });
+        }
+        Ok(matches)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MerkleError {
+    NoTransactions,
+    TooManyHashes,
+    NotEnoughHashes,
+    NotEnoughFlags,
+    UnusedHashes,
+    UnusedFlags,
+    DuplicateHashes,
+    RootMismatch { root: [u8; 32] },
+}
+
+impl fmt::Display for MerkleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MerkleError::NoTransactions => write!(f, "Partial merkle tree has no transactions"),
+            MerkleError::TooManyHashes => {
+                write!(f, "Partial merkle tree has more hashes than transactions")
+            }
+            MerkleError::NotEnoughHashes => write!(f, "Partial merkle tree ran out of hashes"),
+            MerkleError::NotEnoughFlags => write!(f, "Partial merkle tree ran out of flags"),
+            MerkleError::UnusedHashes => write!(f, "Partial merkle tree has unused hashes"),
+            MerkleError::UnusedFlags => write!(f, "Partial merkle tree has unused flags"),
+            MerkleError::DuplicateHashes => {
+                write!(
+                    f,
+                    "Partial merkle tree pairs a node with an identical sibling"
+                )
+            }
+            MerkleError::RootMismatch { root } => write!(
+                f,
+                "Partial merkle tree root {} doesn't match the header",
+                hash_to_hex(root)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MerkleError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hash_from_hex, hash_to_hex};
+
+    // Mainnet block 100000
+    const BLOCK_100000_TXIDS: [&str; 4] = [
+        "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+        "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+        "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+        "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+    ];
+    const BLOCK_100000_MERKLE_ROOT: &str =
+        "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766";
+
+    fn txids(hexes: &[&str]) -> Vec<[u8; 32]> {
+        hexes
+            .iter()
+            .map(|hex| hash_from_hex(hex).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_mainnet_merkle_root() {
+        let (root, mutated) = merkle_root(&txids(&BLOCK_100000_TXIDS));
+        assert_eq!(hash_to_hex(&root), BLOCK_100000_MERKLE_ROOT);
+        assert!(!mutated);
+
+        // A single transaction is its own root
+        let coinbase = txids(&BLOCK_100000_TXIDS[..1]);
+        assert_eq!(merkle_root(&coinbase), (coinbase[0], false));
+        assert_eq!(merkle_root(&[]), ([0; 32], false));
+    }
+
+    #[test]
+    fn test_duplicated_transactions_are_flagged() {
+        let three = txids(&BLOCK_100000_TXIDS[..3]);
+        let mut four = three.clone();
+        four.push(three[2]);
+
+        // Both lists commit to the same root, but only the padded one is mutated
+        let (root, mutated) = merkle_root(&three);
+        assert!(!mutated);
+        assert_eq!(merkle_root(&four), (root, true));
+    }
+
+    #[test]
+    fn test_proofs_verify_for_every_position() {
+        // Five leaves exercise a self-paired node on every level but the last
+        let leaves: Vec<[u8; 32]> = (0..5u8).map(|i| double_sha256(&[i])).collect();
+        let (root, _) = merkle_root(&leaves);
+
+        for (index, txid) in leaves.iter().enumerate() {
+            let proof = merkle_proof(&leaves, index).unwrap();
+            assert_eq!(proof.branch.len(), 3);
+            assert!(verify_merkle_proof(txid, &proof, &root));
+
+            // The last leaf is its own sibling, so only the others can be misplaced
+            if index < 4 {
+                let mut wrong_index = proof.clone();
+                wrong_index.index ^= 1;
+                assert!(!verify_merkle_proof(txid, &wrong_index, &root));
+            }
+        }
+
+        let proof = merkle_proof(&leaves, 0).unwrap();
+        assert!(!verify_merkle_proof(&leaves[1], &proof, &root));
+        assert!(!verify_merkle_proof(
+            &leaves[0],
+            &MerkleProof { index: 8, ..proof },
+            &root
+        ));
+        assert_eq!(merkle_proof(&leaves, 5), None);
+    }
+
+    #[test]
+    fn test_partial_merkle_tree_round_trip() {
+        let leaves = txids(&BLOCK_100000_TXIDS);
+        let tree = PartialMerkleTree::new(&leaves, &[false, false, true, false]);
+
+        // Root, unmatched left subtree, then the matched leaf and its sibling
+        assert_eq!(tree.flags, vec![true, false, true, true, false]);
+        assert_eq!(tree.hashes.len(), 3);
+
+        let bytes = tree.serialize();
+        assert_eq!(bytes.len(), 4 + 1 + 3 * 32 + 1 + 1);
+        assert_eq!(bytes[bytes.len() - 1], 0b01101);
+
+        let decoded = PartialMerkleTree::deserialize(&bytes).unwrap();
+        // Flags come back padded to a whole byte
+        assert_eq!(decoded.flags.len(), 8);
+        let (root, matches) = decoded.extract_matches().unwrap();
+        assert_eq!(hash_to_hex(&root), BLOCK_100000_MERKLE_ROOT);
+        assert_eq!(matches, vec![(leaves[2], 2)]);
+    }
+
+    #[test]
+    fn test_partial_merkle_tree_rejects_tampering() {
+        let leaves = txids(&BLOCK_100000_TXIDS);
+        let tree = PartialMerkleTree::new(&leaves, &[false, false, true, false]);
+
+        let mut extra_hash = tree.clone();
+        extra_hash.hashes.push([0; 32]);
+        assert_eq!(extra_hash.extract_matches(), Err(MerkleError::UnusedHashes));
+
+        let mut missing_hash = tree.clone();
+        missing_hash.hashes.pop();
+        assert_eq!(
+            missing_hash.extract_matches(),
+            Err(MerkleError::NotEnoughHashes)
+        );
+
+        // Claiming the last leaf twice, to pass off three transactions as four
+        let three = &leaves[..3];
+        let mut padded = PartialMerkleTree::new(three, &[false, false, true]);
+        padded.total_transactions = 4;
+        padded.flags = vec![true, false, true, true, true];
+        padded.hashes = vec![padded.hashes[0], three[2], three[2]];
+        assert_eq!(padded.extract_matches(), Err(MerkleError::DuplicateHashes));
+    }
+}
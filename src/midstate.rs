@@ -0,0 +1,90 @@
+use crate::{BLOCK_HEADER_SIZE, BlockHeader};
+use sha2::compress256;
+use sha2::digest::generic_array::GenericArray;
+
+// SHA-256 initial hash values
+const SHA256_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+// Offset of the nonce within the second 64-byte chunk of a serialized header
+const NONCE_OFFSET: usize = 76 - 64;
+
+// Header hasher for the mining loop. The first 64 bytes of a header don't depend on
+// the nonce, so their SHA-256 state is computed once and each nonce costs only the
+// final chunk plus the second SHA-256 pass.
+#[derive(Debug, Clone)]
+pub struct HeaderHasher {
+    midstate: [u32; 8],
+    tail: [u8; 64],
+}
+
+impl HeaderHasher {
+    pub fn new(header: &BlockHeader) -> Self {
+        let bytes = header.serialize();
+
+        let mut midstate = SHA256_IV;
+        compress256(&mut midstate, &[*GenericArray::from_slice(&bytes[..64])]);
+
+        // Remaining 16 header bytes, then padding for an 80-byte message
+        let mut tail = [0u8; 64];
+        tail[..16].copy_from_slice(&bytes[64..]);
+        tail[16] = 0x80;
+        tail[56..].copy_from_slice(&((BLOCK_HEADER_SIZE as u64) * 8).to_be_bytes());
+
+        Self { midstate, tail }
+    }
+
+    // Same result as `BlockHeader::hash` with the header's nonce replaced
+    pub fn hash_with_nonce(&self, nonce: u32) -> [u8; 32] {
+        let mut tail = self.tail;
+        tail[NONCE_OFFSET..NONCE_OFFSET + 4].copy_from_slice(&nonce.to_le_bytes());
+
+        let mut state = self.midstate;
+        compress256(&mut state, &[tail.into()]);
+
+        // Second pass over the 32-byte digest, which fits in one padded chunk
+        let mut chunk = [0u8; 64];
+        for (i, word) in state.iter().enumerate() {
+            chunk[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+        }
+        chunk[32] = 0x80;
+        chunk[56..].copy_from_slice(&256u64.to_be_bytes());
+
+        let mut state = SHA256_IV;
+        compress256(&mut state, &[chunk.into()]);
+
+        let mut hash = [0u8; 32];
+        for (i, word) in state.iter().enumerate() {
+            hash[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+        }
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_from_hex;
+
+    #[test]
+    fn test_midstate_matches_full_hash() {
+        let mut header = BlockHeader {
+            version: 1,
+            previous_hash: [0; 32],
+            merkle_root: hash_from_hex(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            )
+            .unwrap(),
+            timestamp: 1231006505,
+            bits: 0x1d00ffff,
+            nonce: 0,
+        };
+        let hasher = HeaderHasher::new(&header);
+
+        for nonce in [0, 1, 2083236893, u32::MAX] {
+            header.nonce = nonce;
+            assert_eq!(hasher.hash_with_nonce(nonce), header.hash());
+        }
+    }
+}
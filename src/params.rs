@@ -0,0 +1,205 @@
+use crate::difficulty::{
+    DEFAULT_RETARGET_INTERVAL, DEFAULT_TARGET_SPACING, DifficultyAlgorithm, Fixed, Retarget,
+};
+use crate::target::Target;
+use crate::{
+    Block, GENESIS_ADDRESS, GENESIS_TIMESTAMP, HALVING_INTERVAL, INITIAL_SUBSIDY, OutPoint,
+    Transaction, TxIn, TxOut,
+};
+
+// Blocks a coinbase output must wait before it can be spent
+pub const COINBASE_MATURITY: u32 = 100;
+// Bitcoin's limit before segwit, in serialized bytes
+pub const MAX_BLOCK_SIZE: usize = 1_000_000;
+// BIP9 version bits with no deployments signalled
+pub const BLOCK_VERSION: u32 = 0x20000000;
+
+// Satoshi's coinbase: "The Times 03/Jan/2009 Chancellor on brink of second bailout for banks"
+const GENESIS_SCRIPT_SIG: &str = "04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73";
+const GENESIS_SCRIPT_PUBKEY: &str = "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac";
+
+// Consensus rules that differ between chains
+#[derive(Debug, Clone)]
+pub struct ChainParams {
+    pub genesis: Block,
+    // The easiest target allowed
+    pub pow_limit: Target,
+    pub retarget_interval: u32,
+    // Seconds between blocks that difficulty adjustment aims for
+    pub target_spacing: u32,
+    // Every block keeps the genesis difficulty, as on regtest
+    pub no_retargeting: bool,
+    pub initial_subsidy: u64,
+    pub halving_interval: u32,
+    pub coinbase_maturity: u32,
+    pub max_block_size: usize,
+    pub block_version: u32,
+}
+
+impl ChainParams {
+    pub fn mainnet() -> Self {
+        let pow_limit = Target::MAX >> 32;
+        Self {
+            genesis: bitcoin_genesis(1231006505, 0x1d00ffff, 2083236893),
+            ..Self::bitcoin(pow_limit)
+        }
+    }
+
+    // Testnet3, without its rule allowing minimum-difficulty blocks after 20 minutes
+    pub fn testnet() -> Self {
+        let pow_limit = Target::MAX >> 32;
+        Self {
+            genesis: bitcoin_genesis(1296688602, 0x1d00ffff, 414098458),
+            ..Self::bitcoin(pow_limit)
+        }
+    }
+
+    pub fn regtest() -> Self {
+        let pow_limit = Target::MAX >> 1;
+        Self {
+            genesis: bitcoin_genesis(1296688602, 0x207fffff, 2),
+            no_retargeting: true,
+            halving_interval: 150,
+            ..Self::bitcoin(pow_limit)
+        }
+    }
+
+    // A private chain at `pow_limit` whose genesis output pays GENESIS_ADDRESS, spendable
+    // right away since coinbases mature immediately
+    pub fn custom(pow_limit: Target) -> Self {
+        let coinbase = Transaction::coinbase(0, 0, GENESIS_ADDRESS, INITIAL_SUBSIDY);
+        let mut genesis = Block::new(1, [0; 32], vec![coinbase], pow_limit.to_compact(), 0);
+        genesis.header.timestamp = GENESIS_TIMESTAMP;
+
+        Self {
+            genesis,
+            coinbase_maturity: 0,
+            ..Self::bitcoin(pow_limit)
+        }
+    }
+
+    // Coins created by a block at `height`, halving every `halving_interval` blocks
+    pub fn block_subsidy(&self, height: u32) -> u64 {
+        let halvings = height / self.halving_interval;
+        if halvings >= 64 {
+            return 0;
+        }
+        self.initial_subsidy >> halvings
+    }
+
+    // Bitcoin's retargeting under these parameters
+    pub fn difficulty(&self) -> Box<dyn DifficultyAlgorithm> {
+        if self.no_retargeting {
+            return Box::new(Fixed {
+                pow_limit: self.pow_limit,
+            });
+        }
+        Box::new(Retarget {
+            interval: self.retarget_interval,
+            target_spacing: self.target_spacing,
+            pow_limit: self.pow_limit,
+        })
+    }
+
+    // Bitcoin's rules around a placeholder genesis block
+    fn bitcoin(pow_limit: Target) -> Self {
+        Self {
+            genesis: Block::new(1, [0; 32], vec![], pow_limit.to_compact(), 0),
+            pow_limit,
+            retarget_interval: DEFAULT_RETARGET_INTERVAL,
+            target_spacing: DEFAULT_TARGET_SPACING,
+            no_retargeting: false,
+            initial_subsidy: INITIAL_SUBSIDY,
+            halving_interval: HALVING_INTERVAL,
+            coinbase_maturity: COINBASE_MATURITY,
+            max_block_size: MAX_BLOCK_SIZE,
+            block_version: BLOCK_VERSION,
+        }
+    }
+}
+
+// The genesis block shared by Bitcoin's networks, which differ only in its header
+fn bitcoin_genesis(timestamp: u32, bits: u32, nonce: u32) -> Block {
+    let coinbase = Transaction {
+        version: 1,
+        inputs: vec![TxIn {
+            previous_output: OutPoint::NULL,
+            script_sig: hex::decode(GENESIS_SCRIPT_SIG).unwrap(),
+            sequence: u32::MAX,
+        }],
+        outputs: vec![TxOut {
+            value: INITIAL_SUBSIDY,
+            script_pubkey: hex::decode(GENESIS_SCRIPT_PUBKEY).unwrap(),
+        }],
+        lock_time: 0,
+    };
+
+    let mut genesis = Block::new(1, [0; 32], vec![coinbase], bits, 0);
+    genesis.header.timestamp = timestamp;
+    genesis.header.nonce = nonce;
+    genesis
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_to_hex;
+
+    #[test]
+    fn test_genesis_blocks_match_bitcoin() {
+        let cases = [
+            (
+                ChainParams::mainnet(),
+                "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+            ),
+            (
+                ChainParams::testnet(),
+                "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+            ),
+            (
+                ChainParams::regtest(),
+                "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+            ),
+        ];
+
+        for (params, hash) in cases {
+            // Computed from the coinbase, so it checks the transaction encoding too
+            assert_eq!(
+                hash_to_hex(&params.genesis.header.merkle_root),
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+            );
+            assert_eq!(hash_to_hex(&params.genesis.hash()), hash);
+            assert!(params.genesis.header.meets_target());
+            assert_eq!(params.genesis.header.bits, params.pow_limit.to_compact());
+        }
+    }
+
+    #[test]
+    fn test_block_subsidy_halving() {
+        let mainnet = ChainParams::mainnet();
+        assert_eq!(mainnet.block_subsidy(0), 50 * crate::COIN);
+        assert_eq!(mainnet.block_subsidy(209_999), 50 * crate::COIN);
+        assert_eq!(mainnet.block_subsidy(210_000), 25 * crate::COIN);
+        assert_eq!(mainnet.block_subsidy(420_000), 1_250_000_000);
+        assert_eq!(mainnet.block_subsidy(64 * 210_000), 0);
+    }
+
+    #[test]
+    fn test_subsidy_and_difficulty_follow_params() {
+        let regtest = ChainParams::regtest();
+        assert_eq!(regtest.block_subsidy(149), 50 * crate::COIN);
+        assert_eq!(regtest.block_subsidy(150), 25 * crate::COIN);
+        assert_eq!(regtest.block_subsidy(64 * 150), 0);
+        assert_eq!(regtest.difficulty().pow_limit(), Target::MAX >> 1);
+
+        let custom = ChainParams::custom(Target::from_leading_zero_bits(4));
+        assert_eq!(
+            custom.genesis.transactions[0].total_output(),
+            Some(INITIAL_SUBSIDY)
+        );
+        assert_eq!(
+            custom.genesis.header.bits,
+            Target::from_leading_zero_bits(4).to_compact()
+        );
+    }
+}
@@ -0,0 +1,1488 @@
+use crate::{Transaction, double_sha256};
+use k256::ecdsa::signature::hazmat::{PrehashSigner, PrehashVerifier};
+use k256::ecdsa::{Signature, SigningKey, VerifyingKey};
+use ripemd::Ripemd160;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+// Push value
+pub const OP_0: u8 = 0x00;
+pub const OP_PUSHDATA1: u8 = 0x4c;
+pub const OP_PUSHDATA2: u8 = 0x4d;
+pub const OP_PUSHDATA4: u8 = 0x4e;
+pub const OP_1NEGATE: u8 = 0x4f;
+pub const OP_RESERVED: u8 = 0x50;
+pub const OP_1: u8 = 0x51;
+pub const OP_16: u8 = 0x60;
+
+// Control
+pub const OP_NOP: u8 = 0x61;
+pub const OP_VER: u8 = 0x62;
+pub const OP_IF: u8 = 0x63;
+pub const OP_NOTIF: u8 = 0x64;
+pub const OP_VERIF: u8 = 0x65;
+pub const OP_VERNOTIF: u8 = 0x66;
+pub const OP_ELSE: u8 = 0x67;
+pub const OP_ENDIF: u8 = 0x68;
+pub const OP_VERIFY: u8 = 0x69;
+pub const OP_RETURN: u8 = 0x6a;
+
+// Stack
+pub const OP_TOALTSTACK: u8 = 0x6b;
+pub const OP_FROMALTSTACK: u8 = 0x6c;
+pub const OP_2DROP: u8 = 0x6d;
+pub const OP_2DUP: u8 = 0x6e;
+pub const OP_3DUP: u8 = 0x6f;
+pub const OP_2OVER: u8 = 0x70;
+pub const OP_2ROT: u8 = 0x71;
+pub const OP_2SWAP: u8 = 0x72;
+pub const OP_IFDUP: u8 = 0x73;
+pub const OP_DEPTH: u8 = 0x74;
+pub const OP_DROP: u8 = 0x75;
+pub const OP_DUP: u8 = 0x76;
+pub const OP_NIP: u8 = 0x77;
+pub const OP_OVER: u8 = 0x78;
+pub const OP_PICK: u8 = 0x79;
+pub const OP_ROLL: u8 = 0x7a;
+pub const OP_ROT: u8 = 0x7b;
+pub const OP_SWAP: u8 = 0x7c;
+pub const OP_TUCK: u8 = 0x7d;
+
+// Splice
+pub const OP_CAT: u8 = 0x7e;
+pub const OP_SUBSTR: u8 = 0x7f;
+pub const OP_LEFT: u8 = 0x80;
+pub const OP_RIGHT: u8 = 0x81;
+pub const OP_SIZE: u8 = 0x82;
+
+// Bitwise logic
+pub const OP_INVERT: u8 = 0x83;
+pub const OP_AND: u8 = 0x84;
+pub const OP_OR: u8 = 0x85;
+pub const OP_XOR: u8 = 0x86;
+pub const OP_EQUAL: u8 = 0x87;
+pub const OP_EQUALVERIFY: u8 = 0x88;
+pub const OP_RESERVED1: u8 = 0x89;
+pub const OP_RESERVED2: u8 = 0x8a;
+
+// Numeric
+pub const OP_1ADD: u8 = 0x8b;
+pub const OP_1SUB: u8 = 0x8c;
+pub const OP_2MUL: u8 = 0x8d;
+pub const OP_2DIV: u8 = 0x8e;
+pub const OP_NEGATE: u8 = 0x8f;
+pub const OP_ABS: u8 = 0x90;
+pub const OP_NOT: u8 = 0x91;
+pub const OP_0NOTEQUAL: u8 = 0x92;
+pub const OP_ADD: u8 = 0x93;
+pub const OP_SUB: u8 = 0x94;
+pub const OP_MUL: u8 = 0x95;
+pub const OP_DIV: u8 = 0x96;
+pub const OP_MOD: u8 = 0x97;
+pub const OP_LSHIFT: u8 = 0x98;
+pub const OP_RSHIFT: u8 = 0x99;
+pub const OP_BOOLAND: u8 = 0x9a;
+pub const OP_BOOLOR: u8 = 0x9b;
+pub const OP_NUMEQUAL: u8 = 0x9c;
+pub const OP_NUMEQUALVERIFY: u8 = 0x9d;
+pub const OP_NUMNOTEQUAL: u8 = 0x9e;
+pub const OP_LESSTHAN: u8 = 0x9f;
+pub const OP_GREATERTHAN: u8 = 0xa0;
+pub const OP_LESSTHANOREQUAL: u8 = 0xa1;
+pub const OP_GREATERTHANOREQUAL: u8 = 0xa2;
+pub const OP_MIN: u8 = 0xa3;
+pub const OP_MAX: u8 = 0xa4;
+pub const OP_WITHIN: u8 = 0xa5;
+
+// Crypto
+pub const OP_RIPEMD160: u8 = 0xa6;
+pub const OP_SHA1: u8 = 0xa7;
+pub const OP_SHA256: u8 = 0xa8;
+pub const OP_HASH160: u8 = 0xa9;
+pub const OP_HASH256: u8 = 0xaa;
+pub const OP_CODESEPARATOR: u8 = 0xab;
+pub const OP_CHECKSIG: u8 = 0xac;
+pub const OP_CHECKSIGVERIFY: u8 = 0xad;
+pub const OP_CHECKMULTISIG: u8 = 0xae;
+pub const OP_CHECKMULTISIGVERIFY: u8 = 0xaf;
+
+// Expansion
+pub const OP_NOP1: u8 = 0xb0;
+pub const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+pub const OP_CHECKSEQUENCEVERIFY: u8 = 0xb2;
+pub const OP_NOP4: u8 = 0xb3;
+pub const OP_NOP10: u8 = 0xb9;
+
+// Resource limits, as in Bitcoin Core
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+// Counts every opcode above OP_16, plus each key of a CHECKMULTISIG
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+// Main and alt stack together
+pub const MAX_STACK_SIZE: usize = 1000;
+pub const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+// Arithmetic inputs are at most 4 bytes; lock times get 5 to reach past 2^31
+const MAX_NUM_SIZE: usize = 4;
+const MAX_LOCK_TIME_SIZE: usize = 5;
+
+// Lock times below this are block heights, from it on Unix timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+// A final sequence opts its input out of lock times
+pub const SEQUENCE_FINAL: u32 = u32::MAX;
+// BIP68 relative lock time encoding in an input's sequence
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+
+// Which parts of the transaction a signature commits to
+pub const SIGHASH_ALL: u8 = 0x01;
+pub const SIGHASH_NONE: u8 = 0x02;
+pub const SIGHASH_SINGLE: u8 = 0x03;
+pub const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptError {
+    ScriptSize,
+    PushSize,
+    OpCount,
+    StackSize,
+    SigCount,
+    PubkeyCount,
+    BadOpcode,
+    DisabledOpcode,
+    UnbalancedConditional,
+    InvalidStackOperation,
+    InvalidAltstackOperation,
+    NumberOverflow,
+    OpReturn,
+    // A VERIFY opcode found false
+    Verify,
+    // The script finished without true on top of the stack
+    EvalFalse,
+    NegativeLockTime,
+    UnsatisfiedLockTime,
+    SigPushOnly,
+    SigDer,
+    SigNullDummy,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            ScriptError::ScriptSize => "Script is too large",
+            ScriptError::PushSize => "Pushed element is too large",
+            ScriptError::OpCount => "Script runs too many opcodes",
+            ScriptError::StackSize => "Stack is too large",
+            ScriptError::SigCount => "Signature count is out of range",
+            ScriptError::PubkeyCount => "Public key count is out of range",
+            ScriptError::BadOpcode => "Invalid or truncated opcode",
+            ScriptError::DisabledOpcode => "Script uses a disabled opcode",
+            ScriptError::UnbalancedConditional => "Unbalanced conditional",
+            ScriptError::InvalidStackOperation => "Operation needs more stack elements",
+            ScriptError::InvalidAltstackOperation => "Operation needs more altstack elements",
+            ScriptError::NumberOverflow => "Number is too long",
+            ScriptError::OpReturn => "OP_RETURN was executed",
+            ScriptError::Verify => "Verification failed",
+            ScriptError::EvalFalse => "Script evaluated to false",
+            ScriptError::NegativeLockTime => "Lock time is negative",
+            ScriptError::UnsatisfiedLockTime => "Lock time has not been reached",
+            ScriptError::SigPushOnly => "Unlocking script does more than push data",
+            ScriptError::SigDer => "Signature is not strict DER",
+            ScriptError::SigNullDummy => "CHECKMULTISIG dummy element is not empty",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+// Builds scripts with minimal pushes
+#[derive(Debug, Clone, Default)]
+pub struct Builder {
+    script: Vec<u8>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_opcode(mut self, opcode: u8) -> Self {
+        self.script.push(opcode);
+        self
+    }
+
+    pub fn push_slice(mut self, data: &[u8]) -> Self {
+        match data.len() {
+            0..=0x4b => self.script.push(data.len() as u8),
+            0x4c..=0xff => self.script.extend([OP_PUSHDATA1, data.len() as u8]),
+            0x100..=0xffff => {
+                self.script.push(OP_PUSHDATA2);
+                self.script
+                    .extend_from_slice(&(data.len() as u16).to_le_bytes());
+            }
+            _ => {
+                self.script.push(OP_PUSHDATA4);
+                self.script
+                    .extend_from_slice(&(data.len() as u32).to_le_bytes());
+            }
+        }
+        self.script.extend_from_slice(data);
+        self
+    }
+
+    pub fn push_int(self, n: i64) -> Self {
+        match n {
+            0 => self.push_opcode(OP_0),
+            -1 => self.push_opcode(OP_1NEGATE),
+            1..=16 => self.push_opcode(OP_1 + n as u8 - 1),
+            _ => self.push_slice(&encode_num(n)),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.script
+    }
+}
+
+// RIPEMD-160 of SHA-256, which addresses commit to
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    Ripemd160::digest(Sha256::digest(data)).into()
+}
+
+// Pay to public key hash: spent by <signature> <public key>
+pub fn p2pkh(pubkey_hash: &[u8; 20]) -> Vec<u8> {
+    Builder::new()
+        .push_opcode(OP_DUP)
+        .push_opcode(OP_HASH160)
+        .push_slice(pubkey_hash)
+        .push_opcode(OP_EQUALVERIFY)
+        .push_opcode(OP_CHECKSIG)
+        .into_bytes()
+}
+
+// Pay to script hash: spent by pushes satisfying the redeem script, then the redeem
+// script itself
+pub fn p2sh(script_hash: &[u8; 20]) -> Vec<u8> {
+    Builder::new()
+        .push_opcode(OP_HASH160)
+        .push_slice(script_hash)
+        .push_opcode(OP_EQUAL)
+        .into_bytes()
+}
+
+// `required` of `pubkeys` must sign
+pub fn multisig(required: usize, pubkeys: &[Vec<u8>]) -> Vec<u8> {
+    let builder = pubkeys
+        .iter()
+        .fold(Builder::new().push_int(required as i64), |builder, key| {
+            builder.push_slice(key)
+        });
+    builder
+        .push_int(pubkeys.len() as i64)
+        .push_opcode(OP_CHECKMULTISIG)
+        .into_bytes()
+}
+
+pub fn is_p2sh(script: &[u8]) -> bool {
+    script.len() == 23 && script[0] == OP_HASH160 && script[1] == 20 && script[22] == OP_EQUAL
+}
+
+// Whether the script only pushes data, as unlocking scripts must
+pub fn is_push_only(script: &[u8]) -> bool {
+    let mut pc = 0;
+    while pc < script.len() {
+        match read_instruction(script, pc) {
+            Ok((opcode, _, next)) if opcode <= OP_16 => pc = next,
+            _ => return false,
+        }
+    }
+    true
+}
+
+// The opcode at `pc`, its push data if any, and where the next instruction starts
+fn read_instruction(script: &[u8], pc: usize) -> Result<(u8, Option<&[u8]>, usize), ScriptError> {
+    let opcode = script[pc];
+    let mut pc = pc + 1;
+    let length = match opcode {
+        0x01..=0x4b => opcode as usize,
+        OP_PUSHDATA1 | OP_PUSHDATA2 | OP_PUSHDATA4 => {
+            let width = match opcode {
+                OP_PUSHDATA1 => 1,
+                OP_PUSHDATA2 => 2,
+                _ => 4,
+            };
+            let bytes = script.get(pc..pc + width).ok_or(ScriptError::BadOpcode)?;
+            pc += width;
+            bytes
+                .iter()
+                .rev()
+                .fold(0usize, |length, byte| (length << 8) | *byte as usize)
+        }
+        _ => return Ok((opcode, None, pc)),
+    };
+    let data = pc
+        .checked_add(length)
+        .and_then(|end| script.get(pc..end))
+        .ok_or(ScriptError::BadOpcode)?;
+    Ok((opcode, Some(data), pc + length))
+}
+
+// Drop every instruction-aligned occurrence of `pattern` from `script`
+fn find_and_delete(script: &[u8], pattern: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(script.len());
+    let mut pc = 0;
+    while pc < script.len() {
+        let next = read_instruction(script, pc).map_or(script.len(), |(_, _, next)| next);
+        if script[pc..next] != *pattern {
+            result.extend_from_slice(&script[pc..next]);
+        }
+        pc = next;
+    }
+    result
+}
+
+// Script numbers are little-endian with the sign in the top bit of the last byte
+fn decode_num(bytes: &[u8], max_size: usize) -> Result<i64, ScriptError> {
+    if bytes.len() > max_size {
+        return Err(ScriptError::NumberOverflow);
+    }
+    let Some(&last) = bytes.last() else {
+        return Ok(0);
+    };
+    let magnitude = bytes
+        .iter()
+        .rev()
+        .fold(0i64, |value, byte| (value << 8) | *byte as i64)
+        & !(0x80 << (8 * (bytes.len() - 1)));
+    Ok(if last & 0x80 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    })
+}
+
+fn encode_num(n: i64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut magnitude = n.unsigned_abs();
+    while magnitude > 0 {
+        bytes.push(magnitude as u8);
+        magnitude >>= 8;
+    }
+    // The sign bit needs a byte of its own if the magnitude already uses it
+    match bytes.last_mut() {
+        Some(last) if *last & 0x80 != 0 => bytes.push(if n < 0 { 0x80 } else { 0 }),
+        Some(last) if n < 0 => *last |= 0x80,
+        _ => {}
+    }
+    bytes
+}
+
+// Any non-zero byte is true, except a lone sign bit at the end: negative zero
+fn cast_to_bool(bytes: &[u8]) -> bool {
+    bytes
+        .iter()
+        .enumerate()
+        .any(|(i, byte)| *byte != 0 && !(i == bytes.len() - 1 && *byte == 0x80))
+}
+
+fn from_bool(value: bool) -> Vec<u8> {
+    if value { vec![1] } else { vec![] }
+}
+
+// Legacy signature hash: a copy of the transaction with the other inputs' scripts
+// blanked and this input's replaced by `script_code`, trimmed according to
+// `hash_type`, then double SHA-256 with the hash type appended. Keeps Bitcoin's quirk
+// of signing the number one for SIGHASH_SINGLE without a matching output.
+pub fn signature_hash(
+    transaction: &Transaction,
+    input: usize,
+    script_code: &[u8],
+    hash_type: u8,
+) -> [u8; 32] {
+    let mut one = [0; 32];
+    one[0] = 1;
+    let base_type = hash_type & 0x1f;
+    if input >= transaction.inputs.len()
+        || (base_type == SIGHASH_SINGLE && input >= transaction.outputs.len())
+    {
+        return one;
+    }
+
+    let script_code = find_and_delete(script_code, &[OP_CODESEPARATOR]);
+    let mut copy = transaction.clone();
+    for (i, tx_in) in copy.inputs.iter_mut().enumerate() {
+        if i == input {
+            tx_in.script_sig = script_code.clone();
+        } else {
+            tx_in.script_sig.clear();
+            if base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE {
+                tx_in.sequence = 0;
+            }
+        }
+    }
+    if hash_type & SIGHASH_ANYONECANPAY != 0 {
+        copy.inputs = vec![copy.inputs.swap_remove(input)];
+    }
+    match base_type {
+        SIGHASH_NONE => copy.outputs.clear(),
+        SIGHASH_SINGLE => {
+            copy.outputs.truncate(input + 1);
+            for output in &mut copy.outputs[..input] {
+                output.value = u64::MAX;
+                output.script_pubkey.clear();
+            }
+        }
+        _ => {}
+    }
+
+    let mut bytes = copy.serialize();
+    bytes.extend_from_slice(&(hash_type as u32).to_le_bytes());
+    double_sha256(&bytes)
+}
+
+// Signature for `input` of `transaction`, DER-encoded with the hash type appended
+pub fn sign(
+    transaction: &Transaction,
+    input: usize,
+    script_code: &[u8],
+    key: &SigningKey,
+    hash_type: u8,
+) -> Vec<u8> {
+    let hash = signature_hash(transaction, input, script_code, hash_type);
+    let signature: Signature = key.sign_prehash(&hash).unwrap();
+    let signature = signature.normalize_s().unwrap_or(signature);
+    let mut bytes = signature.to_der().as_bytes().to_vec();
+    bytes.push(hash_type);
+    bytes
+}
+
+// BIP66's IsValidSignatureEncoding: `signature`, with its hash type byte, is
+// 0x30 <len> 0x02 <len R> <R> 0x02 <len S> <S>, where R and S are minimally encoded,
+// non-negative integers. Only the encoding is checked, not that R and S are in range.
+fn is_strict_der(signature: &[u8]) -> bool {
+    let len = signature.len();
+    if !(9..=73).contains(&len) || signature[0] != 0x30 || signature[1] as usize != len - 3 {
+        return false;
+    }
+    let len_r = signature[3] as usize;
+    if 5 + len_r >= len {
+        return false;
+    }
+    let len_s = signature[5 + len_r] as usize;
+    if len_r + len_s + 7 != len {
+        return false;
+    }
+
+    let integer_ok = |start: usize, int_len: usize| {
+        let int = &signature[start..start + int_len];
+        signature[start - 2] == 0x02
+            && int_len != 0
+            && int[0] & 0x80 == 0
+            && !(int_len > 1 && int[0] == 0 && int[1] & 0x80 == 0)
+    };
+    integer_ok(4, len_r) && integer_ok(6 + len_r, len_s)
+}
+
+// What signature and lock time opcodes check against: one input of a transaction
+#[derive(Debug, Clone, Copy)]
+pub struct TransactionChecker<'a> {
+    pub transaction: &'a Transaction,
+    pub input: usize,
+}
+
+impl<'a> TransactionChecker<'a> {
+    pub fn new(transaction: &'a Transaction, input: usize) -> Self {
+        Self { transaction, input }
+    }
+
+    // An empty or unparseable key or signature just fails, but a signature that isn't
+    // strict DER fails the script (BIP66). Strict DER with r or s out of range is only
+    // a failed check, as in Bitcoin Core. High-S signatures are valid by consensus, so
+    // they're normalized before k256, which only takes low-S, verifies them.
+    fn check_signature(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+    ) -> Result<bool, ScriptError> {
+        let Some((&hash_type, der)) = signature.split_last() else {
+            return Ok(false);
+        };
+        if !is_strict_der(signature) {
+            return Err(ScriptError::SigDer);
+        }
+        let Ok(signature) = Signature::from_der(der) else {
+            return Ok(false);
+        };
+        let signature = signature.normalize_s().unwrap_or(signature);
+        let Ok(key) = VerifyingKey::from_sec1_bytes(pubkey) else {
+            return Ok(false);
+        };
+        let hash = signature_hash(self.transaction, self.input, script_code, hash_type);
+        Ok(key.verify_prehash(&hash, &signature).is_ok())
+    }
+
+    // BIP65: the transaction's lock time is of the same kind and at least `lock_time`,
+    // and not disabled by a final sequence
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        let threshold = LOCKTIME_THRESHOLD as i64;
+        let tx_lock_time = self.transaction.lock_time as i64;
+        if (tx_lock_time < threshold) != (lock_time < threshold) || lock_time > tx_lock_time {
+            return false;
+        }
+        self.transaction.inputs[self.input].sequence != SEQUENCE_FINAL
+    }
+
+    // BIP112: the input's relative lock time is of the same kind and at least
+    // `sequence`. Only the sequence field is compared here; the chain enforces the
+    // relative lock itself (BIP68) with `Transaction::sequence_locks_met`.
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let tx_sequence = self.transaction.inputs[self.input].sequence as i64;
+        if self.transaction.version < 2 || tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 != 0
+        {
+            return false;
+        }
+        let mask = (SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK) as i64;
+        let type_flag = SEQUENCE_LOCKTIME_TYPE_FLAG as i64;
+        let (tx_sequence, sequence) = (tx_sequence & mask, sequence & mask);
+        if (tx_sequence < type_flag) != (sequence < type_flag) {
+            return false;
+        }
+        sequence <= tx_sequence
+    }
+}
+
+// Run the unlocking script, then the locking script on the stack it leaves, which must
+// end with true on top. For pay-to-script-hash outputs the pushed redeem script then
+// runs on what the unlocking script left, which may only push data (BIP16).
+pub fn verify_script(
+    script_sig: &[u8],
+    script_pubkey: &[u8],
+    checker: &TransactionChecker,
+) -> Result<(), ScriptError> {
+    let mut stack = Vec::new();
+    eval_script(&mut stack, script_sig, checker)?;
+    let mut redeem_stack = stack.clone();
+    eval_script(&mut stack, script_pubkey, checker)?;
+    if !stack.last().is_some_and(|top| cast_to_bool(top)) {
+        return Err(ScriptError::EvalFalse);
+    }
+
+    if is_p2sh(script_pubkey) {
+        if !is_push_only(script_sig) {
+            return Err(ScriptError::SigPushOnly);
+        }
+        // The hash matched, so the unlocking script pushed the redeem script
+        let redeem_script = redeem_stack.pop().unwrap();
+        eval_script(&mut redeem_stack, &redeem_script, checker)?;
+        if !redeem_stack.last().is_some_and(|top| cast_to_bool(top)) {
+            return Err(ScriptError::EvalFalse);
+        }
+    }
+    Ok(())
+}
+
+type Stack = Vec<Vec<u8>>;
+
+// Fails unless the stack has at least `count` elements
+fn need(stack: &Stack, count: usize) -> Result<(), ScriptError> {
+    if stack.len() < count {
+        return Err(ScriptError::InvalidStackOperation);
+    }
+    Ok(())
+}
+
+fn pop(stack: &mut Stack) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::InvalidStackOperation)
+}
+
+fn pop_num(stack: &mut Stack) -> Result<i64, ScriptError> {
+    decode_num(&pop(stack)?, MAX_NUM_SIZE)
+}
+
+// Index of the element `depth` from the top, 1 being the top itself
+fn from_top(stack: &Stack, depth: usize) -> usize {
+    stack.len() - depth
+}
+
+pub fn eval_script(
+    stack: &mut Stack,
+    script: &[u8],
+    checker: &TransactionChecker,
+) -> Result<(), ScriptError> {
+    if script.len() > MAX_SCRIPT_SIZE {
+        return Err(ScriptError::ScriptSize);
+    }
+    let mut altstack: Stack = Vec::new();
+    // Whether each enclosing IF branch is being executed
+    let mut conditions: Vec<bool> = Vec::new();
+    let mut op_count = 0;
+    // Signatures commit to the script from the last OP_CODESEPARATOR on
+    let mut code_start = 0;
+
+    let mut pc = 0;
+    while pc < script.len() {
+        let executing = conditions.iter().all(|condition| *condition);
+        let (opcode, data, next) = read_instruction(script, pc)?;
+        pc = next;
+
+        if data.is_some_and(|data| data.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+            return Err(ScriptError::PushSize);
+        }
+        if opcode > OP_16 {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(ScriptError::OpCount);
+            }
+        }
+        // Disabled opcodes fail the script even in an unexecuted branch
+        if matches!(
+            opcode,
+            OP_CAT
+                | OP_SUBSTR
+                | OP_LEFT
+                | OP_RIGHT
+                | OP_INVERT
+                | OP_AND
+                | OP_OR
+                | OP_XOR
+                | OP_2MUL
+                | OP_2DIV
+                | OP_MUL
+                | OP_DIV
+                | OP_MOD
+                | OP_LSHIFT
+                | OP_RSHIFT
+        ) {
+            return Err(ScriptError::DisabledOpcode);
+        }
+
+        if let Some(data) = data {
+            if executing {
+                stack.push(data.to_vec());
+            }
+        } else if executing || (OP_IF..=OP_ENDIF).contains(&opcode) {
+            match opcode {
+                OP_0 => stack.push(Vec::new()),
+                OP_1NEGATE | OP_1..=OP_16 => {
+                    stack.push(encode_num(opcode as i64 - (OP_1 - 1) as i64));
+                }
+
+                OP_NOP | OP_NOP1 | OP_NOP4..=OP_NOP10 => {}
+                OP_CHECKLOCKTIMEVERIFY | OP_CHECKSEQUENCEVERIFY => {
+                    need(stack, 1)?;
+                    let n = decode_num(stack.last().unwrap(), MAX_LOCK_TIME_SIZE)?;
+                    if n < 0 {
+                        return Err(ScriptError::NegativeLockTime);
+                    }
+                    let satisfied = if opcode == OP_CHECKLOCKTIMEVERIFY {
+                        checker.check_lock_time(n)
+                    } else {
+                        // A disabled relative lock time makes it a NOP
+                        n & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 != 0 || checker.check_sequence(n)
+                    };
+                    if !satisfied {
+                        return Err(ScriptError::UnsatisfiedLockTime);
+                    }
+                }
+
+                OP_IF | OP_NOTIF => {
+                    let mut condition = false;
+                    if executing {
+                        condition = cast_to_bool(&pop(stack)?) == (opcode == OP_IF);
+                    }
+                    conditions.push(condition);
+                }
+                OP_ELSE => {
+                    let condition = conditions
+                        .last_mut()
+                        .ok_or(ScriptError::UnbalancedConditional)?;
+                    *condition = !*condition;
+                }
+                OP_ENDIF => {
+                    conditions.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                }
+                OP_VERIFY => {
+                    if !cast_to_bool(&pop(stack)?) {
+                        return Err(ScriptError::Verify);
+                    }
+                }
+                OP_RETURN => return Err(ScriptError::OpReturn),
+
+                OP_TOALTSTACK => altstack.push(pop(stack)?),
+                OP_FROMALTSTACK => {
+                    let value = altstack
+                        .pop()
+                        .ok_or(ScriptError::InvalidAltstackOperation)?;
+                    stack.push(value);
+                }
+                OP_2DROP => {
+                    need(stack, 2)?;
+                    stack.truncate(stack.len() - 2);
+                }
+                OP_2DUP | OP_3DUP | OP_2OVER => {
+                    let (depth, count) = match opcode {
+                        OP_2DUP => (2, 2),
+                        OP_3DUP => (3, 3),
+                        _ => (4, 2),
+                    };
+                    need(stack, depth)?;
+                    let start = from_top(stack, depth);
+                    stack.extend_from_within(start..start + count);
+                }
+                OP_2ROT => {
+                    need(stack, 6)?;
+                    let start = from_top(stack, 6);
+                    let pair: Vec<_> = stack.drain(start..start + 2).collect();
+                    stack.extend(pair);
+                }
+                OP_2SWAP => {
+                    need(stack, 4)?;
+                    let start = from_top(stack, 4);
+                    stack[start..].rotate_left(2);
+                }
+                OP_IFDUP => {
+                    need(stack, 1)?;
+                    let top = stack.last().unwrap();
+                    if cast_to_bool(top) {
+                        stack.push(top.clone());
+                    }
+                }
+                OP_DEPTH => stack.push(encode_num(stack.len() as i64)),
+                OP_DROP => {
+                    pop(stack)?;
+                }
+                OP_DUP => {
+                    need(stack, 1)?;
+                    stack.push(stack.last().unwrap().clone());
+                }
+                OP_NIP => {
+                    need(stack, 2)?;
+                    stack.remove(from_top(stack, 2));
+                }
+                OP_OVER => {
+                    need(stack, 2)?;
+                    stack.push(stack[from_top(stack, 2)].clone());
+                }
+                OP_PICK | OP_ROLL => {
+                    need(stack, 2)?;
+                    let n = pop_num(stack)?;
+                    if n < 0 || n as usize >= stack.len() {
+                        return Err(ScriptError::InvalidStackOperation);
+                    }
+                    let index = from_top(stack, n as usize + 1);
+                    let value = if opcode == OP_ROLL {
+                        stack.remove(index)
+                    } else {
+                        stack[index].clone()
+                    };
+                    stack.push(value);
+                }
+                OP_ROT => {
+                    need(stack, 3)?;
+                    let start = from_top(stack, 3);
+                    stack[start..].rotate_left(1);
+                }
+                OP_SWAP => {
+                    need(stack, 2)?;
+                    let len = stack.len();
+                    stack.swap(len - 2, len - 1);
+                }
+                OP_TUCK => {
+                    need(stack, 2)?;
+                    let top = stack.last().unwrap().clone();
+                    stack.insert(from_top(stack, 2), top);
+                }
+
+                OP_SIZE => {
+                    need(stack, 1)?;
+                    stack.push(encode_num(stack.last().unwrap().len() as i64));
+                }
+
+                OP_EQUAL | OP_EQUALVERIFY => {
+                    need(stack, 2)?;
+                    let equal = pop(stack)? == pop(stack)?;
+                    if opcode == OP_EQUALVERIFY {
+                        if !equal {
+                            return Err(ScriptError::Verify);
+                        }
+                    } else {
+                        stack.push(from_bool(equal));
+                    }
+                }
+
+                OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                    let n = pop_num(stack)?;
+                    let result = match opcode {
+                        OP_1ADD => n + 1,
+                        OP_1SUB => n - 1,
+                        OP_NEGATE => -n,
+                        OP_ABS => n.abs(),
+                        OP_NOT => (n == 0) as i64,
+                        _ => (n != 0) as i64,
+                    };
+                    stack.push(encode_num(result));
+                }
+                OP_ADD
+                | OP_SUB
+                | OP_BOOLAND
+                | OP_BOOLOR
+                | OP_NUMEQUAL
+                | OP_NUMEQUALVERIFY
+                | OP_NUMNOTEQUAL
+                | OP_LESSTHAN
+                | OP_GREATERTHAN
+                | OP_LESSTHANOREQUAL
+                | OP_GREATERTHANOREQUAL
+                | OP_MIN
+                | OP_MAX => {
+                    need(stack, 2)?;
+                    let b = pop_num(stack)?;
+                    let a = pop_num(stack)?;
+                    let result = match opcode {
+                        OP_ADD => a + b,
+                        OP_SUB => a - b,
+                        OP_BOOLAND => (a != 0 && b != 0) as i64,
+                        OP_BOOLOR => (a != 0 || b != 0) as i64,
+                        OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                        OP_NUMNOTEQUAL => (a != b) as i64,
+                        OP_LESSTHAN => (a < b) as i64,
+                        OP_GREATERTHAN => (a > b) as i64,
+                        OP_LESSTHANOREQUAL => (a <= b) as i64,
+                        OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                        OP_MIN => a.min(b),
+                        _ => a.max(b),
+                    };
+                    if opcode == OP_NUMEQUALVERIFY {
+                        if result == 0 {
+                            return Err(ScriptError::Verify);
+                        }
+                    } else {
+                        stack.push(encode_num(result));
+                    }
+                }
+                OP_WITHIN => {
+                    need(stack, 3)?;
+                    let max = pop_num(stack)?;
+                    let min = pop_num(stack)?;
+                    let n = pop_num(stack)?;
+                    stack.push(from_bool(min <= n && n < max));
+                }
+
+                OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                    let value = pop(stack)?;
+                    let hash = match opcode {
+                        OP_RIPEMD160 => Ripemd160::digest(&value).to_vec(),
+                        OP_SHA1 => Sha1::digest(&value).to_vec(),
+                        OP_SHA256 => Sha256::digest(&value).to_vec(),
+                        OP_HASH160 => hash160(&value).to_vec(),
+                        _ => double_sha256(&value).to_vec(),
+                    };
+                    stack.push(hash);
+                }
+                OP_CODESEPARATOR => code_start = pc,
+                OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                    need(stack, 2)?;
+                    let pubkey = pop(stack)?;
+                    let signature = pop(stack)?;
+                    // A signature can't sign itself, so it's removed from what it signs
+                    let script_code = find_and_delete(
+                        &script[code_start..],
+                        &Builder::new().push_slice(&signature).into_bytes(),
+                    );
+                    let valid = checker.check_signature(&signature, &pubkey, &script_code)?;
+                    if opcode == OP_CHECKSIGVERIFY {
+                        if !valid {
+                            return Err(ScriptError::Verify);
+                        }
+                    } else {
+                        stack.push(from_bool(valid));
+                    }
+                }
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    // Stack: dummy <sig>... m <pubkey>... n
+                    let key_count = pop_num(stack)?;
+                    if !(0..=MAX_PUBKEYS_PER_MULTISIG).contains(&key_count) {
+                        return Err(ScriptError::PubkeyCount);
+                    }
+                    op_count += key_count as usize;
+                    if op_count > MAX_OPS_PER_SCRIPT {
+                        return Err(ScriptError::OpCount);
+                    }
+                    need(stack, key_count as usize)?;
+                    let pubkeys = stack.split_off(stack.len() - key_count as usize);
+
+                    let sig_count = pop_num(stack)?;
+                    if !(0..=key_count).contains(&sig_count) {
+                        return Err(ScriptError::SigCount);
+                    }
+                    need(stack, sig_count as usize)?;
+                    let signatures = stack.split_off(stack.len() - sig_count as usize);
+
+                    let script_code = signatures.iter().fold(
+                        script[code_start..].to_vec(),
+                        |script_code, signature| {
+                            let push = Builder::new().push_slice(signature).into_bytes();
+                            find_and_delete(&script_code, &push)
+                        },
+                    );
+
+                    // Signatures must match keys in order, so each key is tried once,
+                    // giving up as soon as too few keys are left
+                    let (mut sigs_left, mut keys_left) = (signatures.len(), pubkeys.len());
+                    let mut valid = true;
+                    while valid && sigs_left > 0 {
+                        let signature = &signatures[sigs_left - 1];
+                        let key = &pubkeys[keys_left - 1];
+                        if checker.check_signature(signature, key, &script_code)? {
+                            sigs_left -= 1;
+                        }
+                        keys_left -= 1;
+                        valid = sigs_left <= keys_left;
+                    }
+
+                    // Bitcoin's off-by-one pops an extra element, which must be empty
+                    if !pop(stack)?.is_empty() {
+                        return Err(ScriptError::SigNullDummy);
+                    }
+                    if opcode == OP_CHECKMULTISIGVERIFY {
+                        if !valid {
+                            return Err(ScriptError::Verify);
+                        }
+                    } else {
+                        stack.push(from_bool(valid));
+                    }
+                }
+
+                OP_RESERVED | OP_VER | OP_VERIF | OP_VERNOTIF | OP_RESERVED1 | OP_RESERVED2 => {
+                    return Err(ScriptError::BadOpcode);
+                }
+                _ => return Err(ScriptError::BadOpcode),
+            }
+        }
+
+        if stack.len() + altstack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+    }
+
+    if !conditions.is_empty() {
+        return Err(ScriptError::UnbalancedConditional);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OutPoint, TxIn, TxOut};
+
+    fn spending_tx() -> Transaction {
+        Transaction::new(
+            vec![TxIn::new(OutPoint::new([1; 32], 0))],
+            vec![TxOut::to_address(1, "bob")],
+        )
+    }
+
+    fn eval(script: &[u8]) -> Result<Stack, ScriptError> {
+        let tx = spending_tx();
+        let mut stack = Vec::new();
+        eval_script(&mut stack, script, &TransactionChecker::new(&tx, 0))?;
+        Ok(stack)
+    }
+
+    fn ops(opcodes: &[u8]) -> Vec<u8> {
+        opcodes
+            .iter()
+            .fold(Builder::new(), |builder, opcode| {
+                builder.push_opcode(*opcode)
+            })
+            .into_bytes()
+    }
+
+    fn key(seed: u8) -> (SigningKey, Vec<u8>) {
+        let key = SigningKey::from_slice(&[seed; 32]).unwrap();
+        let pubkey = key.verifying_key().to_sec1_bytes().to_vec();
+        (key, pubkey)
+    }
+
+    #[test]
+    fn test_script_numbers() {
+        for n in [
+            0,
+            1,
+            -1,
+            127,
+            128,
+            -128,
+            255,
+            -256,
+            0x7fff_ffff,
+            -0x7fff_ffff,
+        ] {
+            assert_eq!(decode_num(&encode_num(n), MAX_NUM_SIZE), Ok(n));
+        }
+        assert_eq!(encode_num(0), Vec::<u8>::new());
+        assert_eq!(encode_num(-1), vec![0x81]);
+        assert_eq!(encode_num(128), vec![0x80, 0x00]);
+        assert_eq!(encode_num(-128), vec![0x80, 0x80]);
+        assert_eq!(
+            decode_num(&encode_num(1 << 31), MAX_NUM_SIZE),
+            Err(ScriptError::NumberOverflow)
+        );
+        assert_eq!(
+            decode_num(&encode_num(1 << 31), MAX_LOCK_TIME_SIZE),
+            Ok(1 << 31)
+        );
+
+        // Negative zero is false
+        assert!(!cast_to_bool(&[0, 0x80]));
+        assert!(cast_to_bool(&[0x80, 0]));
+        assert!(!cast_to_bool(&[]));
+    }
+
+    #[test]
+    fn test_stack_arithmetic_and_hash_opcodes() {
+        let script = Builder::new()
+            .push_int(2)
+            .push_int(3)
+            .push_opcode(OP_ADD)
+            .push_int(5)
+            .push_opcode(OP_NUMEQUALVERIFY)
+            .push_int(1)
+            .push_int(2)
+            .push_int(3)
+            .push_opcode(OP_ROT)
+            .into_bytes();
+        assert_eq!(eval(&script), Ok(vec![vec![2], vec![3], vec![1]]));
+
+        let script = Builder::new()
+            .push_int(1)
+            .push_int(2)
+            .push_int(3)
+            .push_int(4)
+            .push_opcode(OP_2SWAP)
+            .push_int(3)
+            .push_opcode(OP_ROLL)
+            .push_int(1)
+            .push_opcode(OP_PICK)
+            .into_bytes();
+        assert_eq!(
+            eval(&script),
+            Ok(vec![vec![4], vec![1], vec![2], vec![3], vec![2]])
+        );
+
+        let script = Builder::new()
+            .push_int(-5)
+            .push_opcode(OP_ABS)
+            .push_int(0)
+            .push_int(5)
+            .push_opcode(OP_WITHIN)
+            .push_int(7)
+            .push_opcode(OP_NEGATE)
+            .push_opcode(OP_SIZE)
+            .into_bytes();
+        assert_eq!(eval(&script), Ok(vec![vec![], vec![0x87], vec![1]]));
+
+        let hash = |opcode: u8, data: &[u8]| {
+            let script = Builder::new().push_slice(data).push_opcode(opcode);
+            hex::encode(&eval(&script.into_bytes()).unwrap()[0])
+        };
+        assert_eq!(
+            hash(OP_SHA256, b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hash(OP_SHA1, b"abc"),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            hash(OP_RIPEMD160, b"abc"),
+            "8eb208f7e05d987a9b044a8e98c6b087f15a0bfc"
+        );
+        assert_eq!(hash(OP_HASH256, b"abc"), hex::encode(double_sha256(b"abc")));
+
+        assert_eq!(
+            eval(&ops(&[OP_DROP])),
+            Err(ScriptError::InvalidStackOperation)
+        );
+        assert_eq!(
+            eval(&ops(&[OP_FROMALTSTACK])),
+            Err(ScriptError::InvalidAltstackOperation)
+        );
+        assert_eq!(
+            eval(&ops(&[OP_1, OP_TOALTSTACK, OP_FROMALTSTACK, OP_DUP])),
+            Ok(vec![vec![1], vec![1]])
+        );
+        assert_eq!(
+            eval(&ops(&[OP_1, OP_1 + 1, OP_EQUALVERIFY])),
+            Err(ScriptError::Verify)
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let choose = |condition: u8| {
+            eval(&ops(&[
+                condition,
+                OP_IF,
+                OP_1 + 1,
+                OP_ELSE,
+                OP_1 + 2,
+                OP_ENDIF,
+            ]))
+        };
+        assert_eq!(choose(OP_1), Ok(vec![vec![2]]));
+        assert_eq!(choose(OP_0), Ok(vec![vec![3]]));
+        assert_eq!(
+            eval(&ops(&[
+                OP_0,
+                OP_NOTIF,
+                OP_1,
+                OP_IF,
+                OP_1 + 3,
+                OP_ENDIF,
+                OP_ENDIF
+            ])),
+            Ok(vec![vec![4]])
+        );
+
+        assert_eq!(
+            eval(&ops(&[OP_1, OP_IF])),
+            Err(ScriptError::UnbalancedConditional)
+        );
+        assert_eq!(
+            eval(&ops(&[OP_ENDIF])),
+            Err(ScriptError::UnbalancedConditional)
+        );
+        assert_eq!(
+            eval(&ops(&[OP_IF])),
+            Err(ScriptError::InvalidStackOperation)
+        );
+
+        // Unexecuted branches may hold reserved opcodes, but not disabled ones or
+        // OP_VERIF
+        assert_eq!(
+            eval(&ops(&[OP_0, OP_IF, OP_RESERVED, OP_RETURN, OP_ENDIF])),
+            Ok(vec![])
+        );
+        assert_eq!(
+            eval(&ops(&[OP_0, OP_IF, OP_CAT, OP_ENDIF])),
+            Err(ScriptError::DisabledOpcode)
+        );
+        assert_eq!(
+            eval(&ops(&[OP_0, OP_IF, OP_VERIF, OP_ENDIF])),
+            Err(ScriptError::BadOpcode)
+        );
+        assert_eq!(eval(&ops(&[OP_RESERVED])), Err(ScriptError::BadOpcode));
+        assert_eq!(eval(&ops(&[OP_RETURN])), Err(ScriptError::OpReturn));
+        assert_eq!(eval(&ops(&[0xba])), Err(ScriptError::BadOpcode));
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let push = |size: usize| Builder::new().push_slice(&vec![1; size]).into_bytes();
+        assert!(eval(&push(MAX_SCRIPT_ELEMENT_SIZE)).is_ok());
+        assert_eq!(
+            eval(&push(MAX_SCRIPT_ELEMENT_SIZE + 1)),
+            Err(ScriptError::PushSize)
+        );
+        assert_eq!(eval(&[0x05, 1, 2]), Err(ScriptError::BadOpcode));
+        assert_eq!(eval(&[OP_PUSHDATA2, 1]), Err(ScriptError::BadOpcode));
+
+        assert!(eval(&vec![OP_NOP; MAX_OPS_PER_SCRIPT]).is_ok());
+        assert_eq!(
+            eval(&vec![OP_NOP; MAX_OPS_PER_SCRIPT + 1]),
+            Err(ScriptError::OpCount)
+        );
+        assert_eq!(
+            eval(&vec![OP_0; MAX_SCRIPT_SIZE + 1]),
+            Err(ScriptError::ScriptSize)
+        );
+
+        assert!(eval(&vec![OP_1; MAX_STACK_SIZE]).is_ok());
+        assert_eq!(
+            eval(&vec![OP_1; MAX_STACK_SIZE + 1]),
+            Err(ScriptError::StackSize)
+        );
+        let mut both_stacks = vec![OP_1; MAX_STACK_SIZE];
+        both_stacks.extend([OP_TOALTSTACK, OP_1, OP_1]);
+        assert_eq!(eval(&both_stacks), Err(ScriptError::StackSize));
+
+        // Keys of a CHECKMULTISIG count towards the opcode limit
+        let multisig_ops = |keys: i64| {
+            // Dummy, no signatures, then `keys` empty keys
+            let mut script = vec![OP_NOP; MAX_OPS_PER_SCRIPT - 1 - keys as usize];
+            script.extend(std::iter::repeat_n(OP_0, keys as usize + 2));
+            script.extend(Builder::new().push_int(keys).into_bytes());
+            script.push(OP_CHECKMULTISIG);
+            script
+        };
+        assert_eq!(eval(&multisig_ops(20)), Ok(vec![vec![1]]));
+        let mut over = multisig_ops(20);
+        over.insert(0, OP_NOP);
+        assert_eq!(eval(&over), Err(ScriptError::OpCount));
+
+        let too_many_keys = Builder::new()
+            .push_int(MAX_PUBKEYS_PER_MULTISIG + 1)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_bytes();
+        assert_eq!(eval(&too_many_keys), Err(ScriptError::PubkeyCount));
+    }
+
+    #[test]
+    fn test_pay_to_public_key_hash() {
+        let (key, pubkey) = key(1);
+        let script_pubkey = p2pkh(&hash160(&pubkey));
+        let unlock = |tx: &Transaction, hash_type: u8| {
+            let signature = sign(tx, 0, &script_pubkey, &key, hash_type);
+            Builder::new()
+                .push_slice(&signature)
+                .push_slice(&pubkey)
+                .into_bytes()
+        };
+        let verify = |tx: &Transaction, script_sig: &[u8]| {
+            verify_script(script_sig, &script_pubkey, &TransactionChecker::new(tx, 0))
+        };
+
+        let tx = spending_tx();
+        let script_sig = unlock(&tx, SIGHASH_ALL);
+        assert_eq!(verify(&tx, &script_sig), Ok(()));
+
+        // SIGHASH_ALL commits to the outputs, SIGHASH_NONE doesn't
+        let mut redirected = tx.clone();
+        redirected.outputs[0] = TxOut::to_address(1, "mallory");
+        assert_eq!(
+            verify(&redirected, &script_sig),
+            Err(ScriptError::EvalFalse)
+        );
+        let script_sig = unlock(&tx, SIGHASH_NONE);
+        assert_eq!(verify(&redirected, &script_sig), Ok(()));
+
+        // SIGHASH_SINGLE | SIGHASH_ANYONECANPAY lets others add inputs and outputs
+        let script_sig = unlock(&tx, SIGHASH_SINGLE | SIGHASH_ANYONECANPAY);
+        let mut extended = tx.clone();
+        extended.inputs[0].script_sig = script_sig.clone();
+        extended.inputs.push(TxIn::new(OutPoint::new([2; 32], 0)));
+        extended.outputs.push(TxOut::to_address(1, "carol"));
+        assert_eq!(verify(&extended, &script_sig), Ok(()));
+
+        // Another key, a malformed signature and a script that does more than push
+        let (_, other_pubkey) = self::key(2);
+        let mut wrong_key = unlock(&tx, SIGHASH_ALL);
+        let len = wrong_key.len();
+        wrong_key.splice(len - pubkey.len().., other_pubkey);
+        assert_eq!(verify(&tx, &wrong_key), Err(ScriptError::Verify));
+        let not_der = Builder::new()
+            .push_slice(&[0x30, 0x01, SIGHASH_ALL])
+            .push_slice(&pubkey)
+            .into_bytes();
+        assert_eq!(verify(&tx, &not_der), Err(ScriptError::SigDer));
+        // Only pay-to-script-hash spends must be push-only
+        let mut not_push_only = unlock(&tx, SIGHASH_ALL);
+        not_push_only.push(OP_NOP);
+        assert_eq!(verify(&tx, &not_push_only), Ok(()));
+        assert_eq!(verify(&tx, &[]), Err(ScriptError::InvalidStackOperation));
+    }
+
+    #[test]
+    fn test_high_s_signatures_are_valid() {
+        let (key, pubkey) = key(1);
+        let script_pubkey = Builder::new()
+            .push_slice(&pubkey)
+            .push_opcode(OP_CHECKSIG)
+            .into_bytes();
+        let tx = spending_tx();
+        let low = sign(&tx, 0, &script_pubkey, &key, SIGHASH_ALL);
+
+        // Same signature with s replaced by n - s
+        let (_, der) = low.split_last().unwrap();
+        let (r, s) = Signature::from_der(der).unwrap().split_scalars();
+        let high = Signature::from_scalars(r, -s).unwrap();
+        assert!(high.normalize_s().is_some());
+        let mut signature = high.to_der().as_bytes().to_vec();
+        signature.push(SIGHASH_ALL);
+
+        let script_sig = Builder::new().push_slice(&signature).into_bytes();
+        let checker = TransactionChecker::new(&tx, 0);
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &checker), Ok(()));
+    }
+
+    #[test]
+    fn test_out_of_range_der_signature_just_fails() {
+        let (_, pubkey) = key(1);
+        let script_pubkey = Builder::new()
+            .push_slice(&pubkey)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_NOT)
+            .into_bytes();
+        let tx = spending_tx();
+        let checker = TransactionChecker::new(&tx, 0);
+
+        // r = 0 is strict DER but no valid signature, so OP_CHECKSIG pushes false
+        let zero_r = [0x30, 0x06, 0x02, 0x01, 0x00, 0x02, 0x01, 0x01, SIGHASH_ALL];
+        let script_sig = Builder::new().push_slice(&zero_r).into_bytes();
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &checker), Ok(()));
+
+        // Padding R with a needless zero byte breaks the encoding itself
+        let padded_r = [
+            0x30,
+            0x07,
+            0x02,
+            0x02,
+            0x00,
+            0x01,
+            0x02,
+            0x01,
+            0x01,
+            SIGHASH_ALL,
+        ];
+        let script_sig = Builder::new().push_slice(&padded_r).into_bytes();
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &checker),
+            Err(ScriptError::SigDer)
+        );
+    }
+
+    #[test]
+    fn test_multisig_and_pay_to_script_hash() {
+        let keys: Vec<_> = (1..=3).map(key).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|(_, pubkey)| pubkey.clone()).collect();
+        let redeem_script = multisig(2, &pubkeys);
+        let tx = spending_tx();
+        let checker = TransactionChecker::new(&tx, 0);
+
+        let unlock = |signers: &[usize], dummy: &[u8], script_code: &[u8]| {
+            signers
+                .iter()
+                .fold(Builder::new().push_slice(dummy), |builder, &i| {
+                    builder.push_slice(&sign(&tx, 0, script_code, &keys[i].0, SIGHASH_ALL))
+                })
+        };
+
+        for signers in [[0, 1], [0, 2], [1, 2]] {
+            let script_sig = unlock(&signers, &[], &redeem_script).into_bytes();
+            assert_eq!(verify_script(&script_sig, &redeem_script, &checker), Ok(()));
+        }
+        // Signatures must come in the same order as their keys
+        let out_of_order = unlock(&[2, 0], &[], &redeem_script).into_bytes();
+        assert_eq!(
+            verify_script(&out_of_order, &redeem_script, &checker),
+            Err(ScriptError::EvalFalse)
+        );
+        let dummy = unlock(&[0, 1], &[1], &redeem_script).into_bytes();
+        assert_eq!(
+            verify_script(&dummy, &redeem_script, &checker),
+            Err(ScriptError::SigNullDummy)
+        );
+
+        // The same multisig behind a script hash
+        let script_pubkey = p2sh(&hash160(&redeem_script));
+        let script_sig = unlock(&[0, 2], &[], &redeem_script)
+            .push_slice(&redeem_script)
+            .into_bytes();
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &checker), Ok(()));
+
+        // Pay-to-script-hash unlocking scripts may only push data
+        let not_push_only = unlock(&[0, 2], &[], &redeem_script)
+            .push_opcode(OP_NOP)
+            .push_slice(&redeem_script)
+            .into_bytes();
+        assert_eq!(
+            verify_script(&not_push_only, &script_pubkey, &checker),
+            Err(ScriptError::SigPushOnly)
+        );
+
+        // The hash matches, but the redeem script's signatures don't
+        let script_sig = unlock(&[2, 0], &[], &redeem_script)
+            .push_slice(&redeem_script)
+            .into_bytes();
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &checker),
+            Err(ScriptError::EvalFalse)
+        );
+        let other_redeem_script = multisig(1, &pubkeys);
+        let script_sig = unlock(&[0], &[], &other_redeem_script)
+            .push_slice(&other_redeem_script)
+            .into_bytes();
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &checker),
+            Err(ScriptError::EvalFalse)
+        );
+    }
+
+    #[test]
+    fn test_lock_time_opcodes() {
+        let lock_script = |n: i64, opcode: u8| {
+            Builder::new()
+                .push_int(n)
+                .push_opcode(opcode)
+                .push_opcode(OP_DROP)
+                .push_int(1)
+                .into_bytes()
+        };
+        let run = |tx: &Transaction, n: i64, opcode: u8| {
+            verify_script(
+                &[],
+                &lock_script(n, opcode),
+                &TransactionChecker::new(tx, 0),
+            )
+        };
+
+        // Absolute lock: the transaction's lock time must have reached it
+        let mut tx = spending_tx();
+        tx.lock_time = 100;
+        tx.inputs[0].sequence = 0;
+        assert_eq!(run(&tx, 100, OP_CHECKLOCKTIMEVERIFY), Ok(()));
+        assert_eq!(
+            run(&tx, 101, OP_CHECKLOCKTIMEVERIFY),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+        assert_eq!(
+            run(&tx, LOCKTIME_THRESHOLD as i64, OP_CHECKLOCKTIMEVERIFY),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+        assert_eq!(
+            run(&tx, -1, OP_CHECKLOCKTIMEVERIFY),
+            Err(ScriptError::NegativeLockTime)
+        );
+        tx.inputs[0].sequence = SEQUENCE_FINAL;
+        assert_eq!(
+            run(&tx, 100, OP_CHECKLOCKTIMEVERIFY),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+
+        // Relative lock: the input's sequence must have reached it, in version 2
+        let mut tx = spending_tx();
+        tx.version = 2;
+        tx.inputs[0].sequence = 10;
+        assert_eq!(run(&tx, 10, OP_CHECKSEQUENCEVERIFY), Ok(()));
+        assert_eq!(
+            run(&tx, 11, OP_CHECKSEQUENCEVERIFY),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+        assert_eq!(
+            run(
+                &tx,
+                (SEQUENCE_LOCKTIME_TYPE_FLAG | 1) as i64,
+                OP_CHECKSEQUENCEVERIFY
+            ),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+        assert_eq!(
+            run(
+                &tx,
+                SEQUENCE_LOCKTIME_DISABLE_FLAG as i64,
+                OP_CHECKSEQUENCEVERIFY
+            ),
+            Ok(())
+        );
+        tx.version = 1;
+        assert_eq!(
+            run(&tx, 10, OP_CHECKSEQUENCEVERIFY),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+    }
+}
@@ -0,0 +1,722 @@
+use crate::{Block, double_sha256, hash_to_hex};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Block files roll over once they would grow past this, as in Bitcoin Core
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 128 * 1024 * 1024;
+
+// Every block record starts with the mainnet network magic, then the payload length
+// and the first four bytes of its double SHA-256. The payload is the block in wire
+// format.
+const BLOCK_MAGIC: [u8; 4] = [0xf9, 0xbe, 0xb4, 0xd9];
+const RECORD_HEADER_SIZE: u64 = 12;
+
+// Index records: hash, height, file number, offset, length, checksum
+const INDEX_RECORD_SIZE: usize = 56;
+const INDEX_FILE: &str = "index.dat";
+
+// Storage for a chain of blocks, indexed by hash and by height
+pub trait ChainStore: fmt::Debug {
+    fn get(&self, hash: &[u8; 32]) -> Result<Option<Block>, StoreError>;
+
+    fn get_by_height(&self, height: u32) -> Result<Option<Block>, StoreError>;
+
+    // Hash of the highest block
+    fn tip(&self) -> Option<[u8; 32]>;
+
+    // Number of blocks, so the height the next one goes at
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Add a block on top of the tip
+    fn insert(&mut self, block: Block) -> Result<(), StoreError>;
+
+    // Remove and return the tip, when a reorg disconnects it
+    fn pop(&mut self) -> Result<Option<Block>, StoreError>;
+
+    // Every block, in height order
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Block, StoreError>> + '_>;
+}
+
+// Blocks kept in memory, for tests and short-lived chains. Like FileStore, it only
+// takes the block at the next height.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    blocks: Vec<Block>,
+    by_hash: HashMap<[u8; 32], usize>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ChainStore for MemoryStore {
+    fn get(&self, hash: &[u8; 32]) -> Result<Option<Block>, StoreError> {
+        Ok(self.by_hash.get(hash).map(|&i| self.blocks[i].clone()))
+    }
+
+    fn get_by_height(&self, height: u32) -> Result<Option<Block>, StoreError> {
+        Ok(self.blocks.get(height as usize).cloned())
+    }
+
+    fn tip(&self) -> Option<[u8; 32]> {
+        self.blocks.last().map(|block| block.hash())
+    }
+
+    fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn insert(&mut self, block: Block) -> Result<(), StoreError> {
+        let expected = self.blocks.len() as u32;
+        if block.height != expected {
+            return Err(StoreError::UnexpectedHeight {
+                expected,
+                actual: block.height,
+            });
+        }
+        self.by_hash.insert(block.hash(), self.blocks.len());
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Option<Block>, StoreError> {
+        let block = self.blocks.pop();
+        if let Some(block) = &block {
+            self.by_hash.remove(&block.hash());
+        }
+        Ok(block)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Block, StoreError>> + '_> {
+        Box::new(self.blocks.iter().cloned().map(Ok))
+    }
+}
+
+// Where a block's record lives in the block files, and the height it's stored at,
+// which the wire format leaves out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockLocation {
+    pub height: u32,
+    pub file: u32,
+    pub offset: u64,
+    pub length: u32,
+}
+
+// Append-only block files (blk00000.dat, ...) plus an index file mapping each block
+// hash and height to its record. Blocks are written and synced before their index
+// record, so a crash leaves at most one unindexed or partial block at the end, which
+// `open` re-indexes or truncates.
+#[derive(Debug)]
+pub struct FileStore {
+    dir: PathBuf,
+    pub max_file_size: u64,
+    locations: HashMap<[u8; 32], BlockLocation>,
+    heights: Vec<[u8; 32]>,
+    // Block file the next record goes into, and its length
+    current_file: u32,
+    current_size: u64,
+}
+
+impl FileStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, StoreError> {
+        fs::create_dir_all(dir.as_ref())?;
+
+        let mut store = Self {
+            dir: dir.as_ref().to_path_buf(),
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            locations: HashMap::new(),
+            heights: Vec::new(),
+            current_file: 0,
+            current_size: 0,
+        };
+        store.load_index()?;
+        store.recover_unindexed()?;
+
+        Ok(store)
+    }
+
+    // Write `block` on top of the stored chain. It's durable once this returns. Its
+    // height must be the next one, since recovery relies on heights running in order.
+    pub fn append(&mut self, block: &Block) -> Result<(), StoreError> {
+        let expected = self.heights.len() as u32;
+        if block.height != expected {
+            return Err(StoreError::UnexpectedHeight {
+                expected,
+                actual: block.height,
+            });
+        }
+
+        let payload = block.serialize();
+        let record_size = RECORD_HEADER_SIZE + payload.len() as u64;
+        if self.current_size > 0 && self.current_size + record_size > self.max_file_size {
+            self.current_file += 1;
+            self.current_size = 0;
+        }
+
+        let mut record = Vec::with_capacity(record_size as usize);
+        record.extend_from_slice(&BLOCK_MAGIC);
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&checksum(&payload));
+        record.extend_from_slice(&payload);
+
+        // Cut off anything a failed earlier write left behind before appending
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.block_path(self.current_file))?;
+        file.set_len(self.current_size)?;
+        file.seek(SeekFrom::Start(self.current_size))?;
+        file.write_all(&record)?;
+        file.sync_data()?;
+
+        let location = BlockLocation {
+            height: block.height,
+            file: self.current_file,
+            offset: self.current_size,
+            length: payload.len() as u32,
+        };
+        self.current_size += record_size;
+
+        let hash = block.hash();
+        self.write_index_record(&hash, &location)?;
+        self.locations.insert(hash, location);
+        self.heights.push(hash);
+
+        Ok(())
+    }
+
+    fn block_path(&self, file: u32) -> PathBuf {
+        self.dir.join(format!("blk{:05}.dat", file))
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join(INDEX_FILE)
+    }
+
+    fn read_block(&self, hash: &[u8; 32], location: &BlockLocation) -> Result<Block, StoreError> {
+        let mut file = File::open(self.block_path(location.file))?;
+        file.seek(SeekFrom::Start(location.offset))?;
+
+        let corrupt = || StoreError::Corrupt { hash: *hash };
+        let block = read_record(&mut file)?
+            .filter(|payload| payload.len() == location.length as usize)
+            .and_then(|payload| Block::deserialize(&payload, location.height).ok())
+            .ok_or_else(corrupt)?;
+        if block.hash() != *hash {
+            return Err(corrupt());
+        }
+        Ok(block)
+    }
+
+    // Load index records, dropping any torn or dangling ones at the end
+    fn load_index(&mut self) -> Result<(), StoreError> {
+        let bytes = match fs::read(self.index_path()) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut valid_records = 0;
+        for record in bytes.chunks(INDEX_RECORD_SIZE) {
+            let Some((hash, location)) = self.parse_index_record(record) else {
+                break;
+            };
+            if location.height as usize != self.heights.len() {
+                break;
+            }
+
+            self.locations.insert(hash, location);
+            self.heights.push(hash);
+            self.current_file = location.file;
+            self.current_size = location.offset + RECORD_HEADER_SIZE + location.length as u64;
+            valid_records += 1;
+        }
+
+        if valid_records * INDEX_RECORD_SIZE != bytes.len() {
+            let file = OpenOptions::new().write(true).open(self.index_path())?;
+            file.set_len((valid_records * INDEX_RECORD_SIZE) as u64)?;
+            file.sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    fn parse_index_record(&self, record: &[u8]) -> Option<([u8; 32], BlockLocation)> {
+        if record.len() != INDEX_RECORD_SIZE || record[52..56] != checksum(&record[..52]) {
+            return None;
+        }
+
+        let hash: [u8; 32] = record[0..32].try_into().unwrap();
+        let location = BlockLocation {
+            height: u32::from_le_bytes(record[32..36].try_into().unwrap()),
+            file: u32::from_le_bytes(record[36..40].try_into().unwrap()),
+            offset: u64::from_le_bytes(record[40..48].try_into().unwrap()),
+            length: u32::from_le_bytes(record[48..52].try_into().unwrap()),
+        };
+
+        // The block record it points at must be fully on disk
+        let file_size = fs::metadata(self.block_path(location.file)).ok()?.len();
+        if location.offset + RECORD_HEADER_SIZE + location.length as u64 > file_size {
+            return None;
+        }
+
+        Some((hash, location))
+    }
+
+    fn write_index_record(
+        &self,
+        hash: &[u8; 32],
+        location: &BlockLocation,
+    ) -> Result<(), StoreError> {
+        let mut record = Vec::with_capacity(INDEX_RECORD_SIZE);
+        record.extend_from_slice(hash);
+        record.extend_from_slice(&location.height.to_le_bytes());
+        record.extend_from_slice(&location.file.to_le_bytes());
+        record.extend_from_slice(&location.offset.to_le_bytes());
+        record.extend_from_slice(&location.length.to_le_bytes());
+        let check = checksum(&record);
+        record.extend_from_slice(&check);
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.index_path())?;
+        file.write_all(&record)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    // Index complete blocks written after the last index record, and truncate
+    // whatever follows the last complete one
+    fn recover_unindexed(&mut self) -> Result<(), StoreError> {
+        loop {
+            let path = self.block_path(self.current_file);
+            let mut file = match File::open(&path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+                Err(err) => return Err(err.into()),
+            };
+            let file_size = file.metadata()?.len();
+
+            if self.current_size >= file_size {
+                // Clean end of this file; carry on if the writer had rolled over
+                if self.block_path(self.current_file + 1).exists() {
+                    self.current_file += 1;
+                    self.current_size = 0;
+                    continue;
+                }
+                return Ok(());
+            }
+
+            // Records come in height order, each on top of the one before
+            file.seek(SeekFrom::Start(self.current_size))?;
+            let height = self.heights.len() as u32;
+            let payload = read_record(&mut file)?;
+            let block = payload
+                .as_ref()
+                .and_then(|payload| Block::deserialize(payload, height).ok());
+
+            match (payload, block) {
+                (Some(payload), Some(block))
+                    if self
+                        .heights
+                        .last()
+                        .is_none_or(|tip| *tip == block.header.previous_hash) =>
+                {
+                    let location = BlockLocation {
+                        height,
+                        file: self.current_file,
+                        offset: self.current_size,
+                        length: payload.len() as u32,
+                    };
+                    let hash = block.hash();
+                    self.write_index_record(&hash, &location)?;
+                    self.locations.insert(hash, location);
+                    self.heights.push(hash);
+                    self.current_size += RECORD_HEADER_SIZE + payload.len() as u64;
+                }
+                _ => {
+                    self.truncate_from_current()?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Drop the current file from `current_size` on, and any files after it
+    fn truncate_from_current(&self) -> Result<(), StoreError> {
+        let file = OpenOptions::new()
+            .write(true)
+            .open(self.block_path(self.current_file))?;
+        file.set_len(self.current_size)?;
+        file.sync_data()?;
+
+        let mut next = self.current_file + 1;
+        while self.block_path(next).exists() {
+            fs::remove_file(self.block_path(next))?;
+            next += 1;
+        }
+        Ok(())
+    }
+}
+
+impl ChainStore for FileStore {
+    fn get(&self, hash: &[u8; 32]) -> Result<Option<Block>, StoreError> {
+        match self.locations.get(hash) {
+            Some(location) => self.read_block(hash, location).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn get_by_height(&self, height: u32) -> Result<Option<Block>, StoreError> {
+        match self.heights.get(height as usize) {
+            Some(hash) => self.get(hash),
+            None => Ok(None),
+        }
+    }
+
+    fn tip(&self) -> Option<[u8; 32]> {
+        self.heights.last().copied()
+    }
+
+    fn len(&self) -> usize {
+        self.heights.len()
+    }
+
+    fn insert(&mut self, block: Block) -> Result<(), StoreError> {
+        self.append(&block)
+    }
+
+    // The tip's record is always the last one written, so the files are cut back to
+    // where it starts. The index goes first: a crash in between just brings the block
+    // back on reopening, still on top of its parent.
+    fn pop(&mut self) -> Result<Option<Block>, StoreError> {
+        let Some(hash) = self.heights.last().copied() else {
+            return Ok(None);
+        };
+        let location = self.locations[&hash];
+        let block = self.read_block(&hash, &location)?;
+
+        let file = OpenOptions::new().write(true).open(self.index_path())?;
+        file.set_len(((self.heights.len() - 1) * INDEX_RECORD_SIZE) as u64)?;
+        file.sync_data()?;
+
+        self.heights.pop();
+        self.locations.remove(&hash);
+        self.current_file = location.file;
+        self.current_size = location.offset;
+        self.truncate_from_current()?;
+
+        Ok(Some(block))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Block, StoreError>> + '_> {
+        Box::new(
+            self.heights
+                .iter()
+                .map(|hash| self.read_block(hash, &self.locations[hash])),
+        )
+    }
+}
+
+fn checksum(data: &[u8]) -> [u8; 4] {
+    double_sha256(data)[..4].try_into().unwrap()
+}
+
+// Payload of the block record at the reader's position, or None if it's incomplete
+// or fails its checks
+fn read_record(reader: &mut impl Read) -> Result<Option<Vec<u8>>, StoreError> {
+    let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+    if !read_full(reader, &mut header)? || header[..4] != BLOCK_MAGIC {
+        return Ok(None);
+    }
+
+    let length = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    let mut payload = Vec::new();
+    reader.take(length as u64).read_to_end(&mut payload)?;
+    if payload.len() != length as usize || header[8..12] != checksum(&payload) {
+        return Ok(None);
+    }
+
+    Ok(Some(payload))
+}
+
+// Like read_exact, but reports a short read as false rather than an error
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool, StoreError> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    UnexpectedHeight { expected: u32, actual: u32 },
+    Corrupt { hash: [u8; 32] },
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Io(err) => write!(f, "Block store I/O error: {}", err),
+            StoreError::UnexpectedHeight { expected, actual } => write!(
+                f,
+                "Can't store block at height {} on top of height {}",
+                actual,
+                *expected as i64 - 1
+            ),
+            StoreError::Corrupt { hash } => {
+                write!(f, "Stored block {} is corrupt", hash_to_hex(hash))
+            }
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        StoreError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{COIN, Transaction};
+
+    fn sample_chain(length: u32) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for height in 0..length {
+            let previous_hash = blocks.last().map_or([0; 32], |block| block.hash());
+            let coinbase = Transaction::coinbase(height, 0, "miner", COIN);
+            blocks.push(Block::new(
+                1,
+                previous_hash,
+                vec![coinbase],
+                0x207fffff,
+                height,
+            ));
+        }
+        blocks
+    }
+
+    fn filled_store(dir: &Path, blocks: &[Block]) -> FileStore {
+        let mut store = FileStore::open(dir).unwrap();
+        for block in blocks {
+            store.append(block).unwrap();
+        }
+        store
+    }
+
+    fn stored_hashes(store: &FileStore) -> Vec<[u8; 32]> {
+        store.iter().map(|block| block.unwrap().hash()).collect()
+    }
+
+    fn hashes(blocks: &[Block]) -> Vec<[u8; 32]> {
+        blocks.iter().map(|block| block.hash()).collect()
+    }
+
+    #[test]
+    fn test_blocks_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = sample_chain(3);
+        drop(filled_store(dir.path(), &blocks));
+
+        let store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.tip(), Some(blocks[2].hash()));
+        assert_eq!(
+            store.get(&blocks[1].hash()).unwrap().unwrap().hash(),
+            blocks[1].hash()
+        );
+        assert_eq!(
+            store.get_by_height(2).unwrap().unwrap().hash(),
+            blocks[2].hash()
+        );
+        assert!(store.get(&[0; 32]).unwrap().is_none());
+        assert!(store.get_by_height(3).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_blocks_are_stored_in_wire_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = sample_chain(1);
+        drop(filled_store(dir.path(), &blocks));
+
+        let bytes = fs::read(dir.path().join("blk00000.dat")).unwrap();
+        assert_eq!(bytes[..4], BLOCK_MAGIC);
+        assert_eq!(bytes[RECORD_HEADER_SIZE as usize..], blocks[0].serialize());
+    }
+
+    #[test]
+    fn test_appends_must_extend_the_tip() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = sample_chain(3);
+        let mut store = filled_store(dir.path(), &blocks[..1]);
+        assert!(matches!(
+            store.append(&blocks[2]),
+            Err(StoreError::UnexpectedHeight {
+                expected: 1,
+                actual: 2
+            })
+        ));
+
+        let mut store = MemoryStore::new();
+        store.insert(blocks[0].clone()).unwrap();
+        assert!(matches!(
+            store.insert(blocks[2].clone()),
+            Err(StoreError::UnexpectedHeight {
+                expected: 1,
+                actual: 2
+            })
+        ));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_block_files_roll_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = sample_chain(5);
+        let mut store = FileStore::open(dir.path()).unwrap();
+        store.max_file_size = 1;
+        for block in &blocks {
+            store.append(block).unwrap();
+        }
+
+        assert!(dir.path().join("blk00004.dat").exists());
+        let store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(stored_hashes(&store), hashes(&blocks));
+    }
+
+    #[test]
+    fn test_torn_block_write_is_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = sample_chain(3);
+        drop(filled_store(dir.path(), &blocks[..2]));
+
+        // The start of a third block made it to disk, but not all of it
+        let path = dir.path().join("blk00000.dat");
+        let intact_size = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&BLOCK_MAGIC).unwrap();
+        file.write_all(&[0xff; 10]).unwrap();
+        drop(file);
+
+        let mut store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(stored_hashes(&store), hashes(&blocks[..2]));
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact_size);
+
+        store.append(&blocks[2]).unwrap();
+        let store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(stored_hashes(&store), hashes(&blocks));
+    }
+
+    #[test]
+    fn test_unindexed_block_is_recovered() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = sample_chain(3);
+        drop(filled_store(dir.path(), &blocks));
+
+        // Lose the last index record and tear the one before it
+        let index = dir.path().join(INDEX_FILE);
+        let file = OpenOptions::new().write(true).open(&index).unwrap();
+        file.set_len((INDEX_RECORD_SIZE * 2 - 5) as u64).unwrap();
+        drop(file);
+
+        let store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(stored_hashes(&store), hashes(&blocks));
+        assert_eq!(
+            fs::metadata(&index).unwrap().len(),
+            (INDEX_RECORD_SIZE * 3) as u64
+        );
+    }
+
+    #[test]
+    fn test_corrupt_block_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = sample_chain(2);
+        drop(filled_store(dir.path(), &blocks));
+
+        // Flip a payload byte of the genesis record, which the index still points at
+        let path = dir.path().join("blk00000.dat");
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[RECORD_HEADER_SIZE as usize + 1] ^= 1;
+        fs::write(&path, bytes).unwrap();
+
+        let store = FileStore::open(dir.path()).unwrap();
+        assert!(matches!(
+            store.get_by_height(0),
+            Err(StoreError::Corrupt { .. })
+        ));
+        assert!(store.get_by_height(1).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_popped_blocks_stay_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = sample_chain(3);
+        let mut store = filled_store(dir.path(), &blocks);
+        store.pop().unwrap();
+        drop(store);
+
+        let mut store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(stored_hashes(&store), hashes(&blocks[..2]));
+
+        // A replacement block takes the popped one's place in the file
+        let mut replacement = blocks[2].clone();
+        replacement.header.nonce += 1;
+        store.append(&replacement).unwrap();
+        drop(store);
+        let store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(store.tip(), Some(replacement.hash()));
+    }
+
+    #[test]
+    fn test_stores_behave_alike() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = sample_chain(3);
+        let stores: [Box<dyn ChainStore>; 2] = [
+            Box::new(MemoryStore::new()),
+            Box::new(FileStore::open(dir.path()).unwrap()),
+        ];
+
+        for mut store in stores {
+            assert!(store.is_empty());
+            assert_eq!(store.tip(), None);
+            for block in &blocks {
+                store.insert(block.clone()).unwrap();
+            }
+
+            assert_eq!(store.len(), 3);
+            assert_eq!(store.tip(), Some(blocks[2].hash()));
+            assert_eq!(store.get(&blocks[1].hash()).unwrap().unwrap().height, 1);
+            assert_eq!(
+                store.get_by_height(0).unwrap().unwrap().hash(),
+                blocks[0].hash()
+            );
+            assert!(store.get_by_height(3).unwrap().is_none());
+            let stored: Vec<[u8; 32]> = store.iter().map(|block| block.unwrap().hash()).collect();
+            assert_eq!(stored, hashes(&blocks));
+
+            let popped = store.pop().unwrap().unwrap();
+            assert_eq!(popped.hash(), blocks[2].hash());
+            assert_eq!(store.tip(), Some(blocks[1].hash()));
+            assert!(store.get(&popped.hash()).unwrap().is_none());
+            store.insert(popped).unwrap();
+            assert_eq!(store.len(), 3);
+        }
+    }
+}
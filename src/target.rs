@@ -0,0 +1,422 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Shl, Shr};
+
+// Compact encoding of the difficulty 1 target (the Bitcoin mainnet proof-of-work limit)
+pub const DIFFICULTY_ONE_BITS: u32 = 0x1d00ffff;
+
+// 256-bit proof-of-work target, stored as little-endian 64-bit words
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Target {
+    words: [u64; 4],
+}
+
+impl Target {
+    pub const ZERO: Target = Target { words: [0; 4] };
+    pub const ONE: Target = Target {
+        words: [1, 0, 0, 0],
+    };
+    pub const MAX: Target = Target {
+        words: [u64::MAX; 4],
+    };
+
+    pub fn from_le_bytes(bytes: [u8; 32]) -> Self {
+        let mut words = [0u64; 4];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = u64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap());
+        }
+        Self { words }
+    }
+
+    pub fn to_le_bytes(self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, word) in self.words.iter().enumerate() {
+            bytes[i * 8..i * 8 + 8].copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    // Target that requires `zero_bits` leading zero bits in the hash
+    pub fn from_leading_zero_bits(zero_bits: u32) -> Self {
+        if zero_bits >= 256 {
+            return Self::ZERO;
+        }
+        Self::MAX.shr(zero_bits)
+    }
+
+    // Decode Bitcoin's compact nBits form: 1 byte size, 3 byte mantissa, 0x00800000 sign bit.
+    // Returns None for negative or overflowing encodings, which consensus rejects.
+    pub fn from_compact(bits: u32) -> Option<Self> {
+        let size = bits >> 24;
+        let mut mantissa = bits & 0x007fffff;
+
+        // Like Bitcoin Core, the sign and overflow checks look at the mantissa after
+        // small sizes shift it, so one shifted down to zero is just zero
+        if size <= 3 {
+            mantissa >>= 8 * (3 - size);
+        }
+        if mantissa == 0 {
+            return Some(Self::ZERO);
+        }
+        if bits & 0x00800000 != 0 {
+            return None;
+        }
+        if size > 34 || (mantissa > 0xff && size > 33) || (mantissa > 0xffff && size > 32) {
+            return None;
+        }
+
+        let value = Self::from_u64(mantissa as u64);
+        if size <= 3 {
+            Some(value)
+        } else {
+            Some(value.shl(8 * (size - 3)))
+        }
+    }
+
+    pub fn to_compact(self) -> u32 {
+        let mut size = self.bit_length().div_ceil(8);
+        let mut mantissa = if size <= 3 {
+            (self.words[0] << (8 * (3 - size))) as u32
+        } else {
+            self.shr(8 * (size - 3)).words[0] as u32
+        };
+
+        // The mantissa is signed, so keep its top bit clear by growing the exponent
+        if mantissa & 0x00800000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+
+        mantissa | (size << 24)
+    }
+
+    // A hash meets the target when, read as a little-endian integer, it is not above it
+    pub fn is_met_by(&self, hash: &[u8; 32]) -> bool {
+        Self::from_le_bytes(*hash) <= *self
+    }
+
+    // Difficulty relative to the difficulty 1 target, as reported by bitcoind
+    pub fn difficulty(&self) -> f64 {
+        let one = Self::from_compact(DIFFICULTY_ONE_BITS).unwrap();
+        one.to_f64() / self.to_f64()
+    }
+
+    pub fn from_difficulty(difficulty: f64) -> Self {
+        let one = Self::from_compact(DIFFICULTY_ONE_BITS).unwrap();
+        Self::from_f64(one.to_f64() / difficulty)
+    }
+
+    // Expected number of hashes to meet this target, 2^256 / (target + 1), which is how
+    // Bitcoin weighs chains. Saturates at u128::MAX, far beyond any real chain's work.
+    pub fn work(&self) -> u128 {
+        // 2^256 doesn't fit, so compute (2^256 - target - 1) / (target + 1) + 1
+        let divisor = self.wrapping_add(Self::from_u64(1));
+        if divisor == Self::ZERO {
+            return 1;
+        }
+
+        let quotient = self.not().div(divisor);
+        if quotient.words[2] != 0 || quotient.words[3] != 0 {
+            return u128::MAX;
+        }
+        (quotient.words[0] as u128 | (quotient.words[1] as u128) << 64).saturating_add(1)
+    }
+
+    // None if the product doesn't fit in 256 bits
+    pub fn checked_mul_u64(self, factor: u64) -> Option<Self> {
+        let mut words = [0u64; 4];
+        let mut carry = 0u128;
+        for (i, word) in words.iter_mut().enumerate() {
+            let product = self.words[i] as u128 * factor as u128 + carry;
+            *word = product as u64;
+            carry = product >> 64;
+        }
+        (carry == 0).then_some(Self { words })
+    }
+
+    // Rounds down. `divisor` must not be zero.
+    pub fn div_u64(self, divisor: u64) -> Self {
+        let mut words = [0u64; 4];
+        let mut remainder = 0u128;
+        for i in (0..4).rev() {
+            let dividend = remainder << 64 | self.words[i] as u128;
+            words[i] = (dividend / divisor as u128) as u64;
+            remainder = dividend % divisor as u128;
+        }
+        Self { words }
+    }
+
+    // self * numerator / denominator, saturating at MAX. Targets near 2^256 overflow
+    // when multiplied first, so those divide first at a small loss of precision.
+    pub fn scale(self, numerator: u64, denominator: u64) -> Self {
+        match self.checked_mul_u64(numerator) {
+            Some(product) => product.div_u64(denominator),
+            None => self
+                .div_u64(denominator)
+                .checked_mul_u64(numerator)
+                .unwrap_or(Self::MAX),
+        }
+    }
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        let sum = self.wrapping_add(other);
+        if sum < self { Self::MAX } else { sum }
+    }
+
+    // Number of significant bits
+    pub fn bit_length(&self) -> u32 {
+        for i in (0..4).rev() {
+            if self.words[i] != 0 {
+                return 64 * i as u32 + (64 - self.words[i].leading_zeros());
+            }
+        }
+        0
+    }
+
+    fn from_u64(value: u64) -> Self {
+        Self {
+            words: [value, 0, 0, 0],
+        }
+    }
+
+    fn to_f64(self) -> f64 {
+        self.words
+            .iter()
+            .enumerate()
+            .map(|(i, word)| *word as f64 * 2f64.powi(64 * i as i32))
+            .sum()
+    }
+
+    fn from_f64(value: f64) -> Self {
+        if value.is_nan() || value < 1.0 {
+            return Self::ZERO;
+        }
+        if value >= 2f64.powi(256) {
+            return Self::MAX;
+        }
+
+        let mut remaining = value;
+        let mut words = [0u64; 4];
+        for i in (0..4).rev() {
+            let scale = 2f64.powi(64 * i as i32);
+            let word = (remaining / scale).floor();
+            words[i] = word as u64;
+            remaining -= word * scale;
+        }
+        Self { words }
+    }
+
+    fn not(self) -> Self {
+        Self {
+            words: self.words.map(|word| !word),
+        }
+    }
+
+    fn wrapping_add(self, other: Self) -> Self {
+        let mut words = [0u64; 4];
+        let mut carry = false;
+        for (i, word) in words.iter_mut().enumerate() {
+            let (sum, overflow1) = self.words[i].overflowing_add(other.words[i]);
+            let (sum, overflow2) = sum.overflowing_add(carry as u64);
+            *word = sum;
+            carry = overflow1 || overflow2;
+        }
+        Self { words }
+    }
+
+    fn wrapping_sub(self, other: Self) -> Self {
+        let mut words = [0u64; 4];
+        let mut borrow = false;
+        for (i, word) in words.iter_mut().enumerate() {
+            let (difference, overflow1) = self.words[i].overflowing_sub(other.words[i]);
+            let (difference, overflow2) = difference.overflowing_sub(borrow as u64);
+            *word = difference;
+            borrow = overflow1 || overflow2;
+        }
+        Self { words }
+    }
+
+    // Binary long division, rounding down. `divisor` must not be zero.
+    fn div(self, divisor: Self) -> Self {
+        let mut quotient = Self::ZERO;
+        let mut remainder = Self::ZERO;
+
+        for bit in (0..self.bit_length()).rev() {
+            // A remainder with its top bit set exceeds any divisor once shifted
+            let overflow = remainder.words[3] >> 63 == 1;
+            remainder = remainder.shl(1);
+            remainder.words[0] |= (self.words[bit as usize / 64] >> (bit % 64)) & 1;
+
+            if overflow || remainder >= divisor {
+                remainder = remainder.wrapping_sub(divisor);
+                quotient.words[bit as usize / 64] |= 1 << (bit % 64);
+            }
+        }
+        quotient
+    }
+
+    fn shl(self, shift: u32) -> Self {
+        let mut words = [0u64; 4];
+        let word_shift = (shift / 64) as usize;
+        let bit_shift = shift % 64;
+
+        for (i, word) in words.iter_mut().enumerate().skip(word_shift) {
+            *word = self.words[i - word_shift] << bit_shift;
+            if bit_shift > 0 && i > word_shift {
+                *word |= self.words[i - word_shift - 1] >> (64 - bit_shift);
+            }
+        }
+        Self { words }
+    }
+
+    fn shr(self, shift: u32) -> Self {
+        let mut words = [0u64; 4];
+        let word_shift = (shift / 64) as usize;
+        let bit_shift = shift % 64;
+
+        for (i, word) in words
+            .iter_mut()
+            .enumerate()
+            .take(4usize.saturating_sub(word_shift))
+        {
+            *word = self.words[i + word_shift] >> bit_shift;
+            if bit_shift > 0 && i + word_shift + 1 < 4 {
+                *word |= self.words[i + word_shift + 1] << (64 - bit_shift);
+            }
+        }
+        Self { words }
+    }
+}
+
+impl Ord for Target {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.words.iter().rev().cmp(other.words.iter().rev())
+    }
+}
+
+impl PartialOrd for Target {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Shl<u32> for Target {
+    type Output = Self;
+
+    fn shl(self, shift: u32) -> Self {
+        Target::shl(self, shift)
+    }
+}
+
+impl Shr<u32> for Target {
+    type Output = Self;
+
+    fn shr(self, shift: u32) -> Self {
+        Target::shr(self, shift)
+    }
+}
+
+// Big-endian hex, the way targets are usually written
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for word in self.words.iter().rev() {
+            write!(f, "{:016x}", word)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_decoding() {
+        let target = Target::from_compact(0x1b0404cb).unwrap();
+        assert_eq!(
+            target.to_string(),
+            "00000000000404cb000000000000000000000000000000000000000000000000"
+        );
+        assert_eq!(target.to_compact(), 0x1b0404cb);
+
+        assert_eq!(Target::from_compact(0x01003456), Some(Target::ZERO));
+        // The sign bit is ignored once the shift leaves nothing of the mantissa
+        assert_eq!(Target::from_compact(0x01803456), Some(Target::ZERO));
+        assert_eq!(Target::from_compact(0x01fedcba), None);
+        assert_eq!(
+            Target::from_compact(0x01123456).unwrap().to_compact(),
+            0x01120000
+        );
+        assert_eq!(Target::from_compact(0x04923456), None); // negative
+        assert_eq!(Target::from_compact(0xff123456), None); // overflow
+    }
+
+    #[test]
+    fn test_compact_normalizes_sign_bit() {
+        // 0x80 would read as a negative mantissa, so it moves up a byte
+        let target = Target::from_u64(0x80);
+        assert_eq!(target.to_compact(), 0x02008000);
+        assert_eq!(Target::from_compact(0x02008000), Some(target));
+    }
+
+    #[test]
+    fn test_difficulty_conversion() {
+        let one = Target::from_compact(DIFFICULTY_ONE_BITS).unwrap();
+        assert_eq!(one.difficulty(), 1.0);
+
+        let harder = Target::from_difficulty(1.5);
+        assert_eq!(harder.to_compact(), 0x1d00aaaa);
+        assert!((harder.difficulty() - 1.5).abs() < 1e-9);
+
+        let real = Target::from_compact(0x1b0404cb).unwrap();
+        assert!((real.difficulty() - 16307.420938523983).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_hash_comparison_is_little_endian() {
+        let target = Target::from_leading_zero_bits(8);
+
+        let mut hash = [0xffu8; 32];
+        hash[31] = 0x00;
+        assert!(target.is_met_by(&hash));
+
+        hash[31] = 0x01;
+        assert!(!target.is_met_by(&hash));
+    }
+
+    #[test]
+    fn test_work() {
+        // Difficulty 1 takes 2^32 hashes, give or take the compact rounding
+        let one = Target::from_compact(DIFFICULTY_ONE_BITS).unwrap();
+        assert_eq!(one.work(), 0x0100010001);
+        assert_eq!(Target::from_leading_zero_bits(8).work(), 256);
+        assert_eq!(Target::MAX.work(), 1);
+        assert_eq!(Target::ZERO.work(), u128::MAX);
+
+        // Mainnet block 100000
+        let real = Target::from_compact(0x1b04864c).unwrap();
+        assert_eq!(real.work(), 62_209_952_899_966);
+    }
+
+    #[test]
+    fn test_scaling() {
+        let target = Target::from_leading_zero_bits(8);
+        assert_eq!(target.checked_mul_u64(4).unwrap().div_u64(4), target);
+        assert_eq!(target.div_u64(4), Target::from_leading_zero_bits(10));
+        assert_eq!(target.checked_mul_u64(256), Some(Target::MAX.shl(8)));
+        assert_eq!(target.checked_mul_u64(257), None);
+
+        assert_eq!(
+            target.scale(3, 4),
+            target.checked_mul_u64(3).unwrap().div_u64(4)
+        );
+        assert_eq!(Target::MAX.scale(1, 2), Target::MAX.shr(1));
+        assert_eq!(Target::MAX.scale(2, 1), Target::MAX);
+        assert_eq!(Target::MAX.saturating_add(target), Target::MAX);
+        assert_eq!(target.saturating_add(target), target.shl(1));
+        assert_eq!(
+            Target::from_u64(3).checked_mul_u64(u64::MAX).unwrap().words[1],
+            2
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{Block, MEDIAN_TIME_SPAN, median_time_past};
+use std::collections::{HashMap, HashSet};
+
+// What the chain knows about a block without needing the block itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockIndexEntry {
+    pub height: u32,
+    pub previous_hash: [u8; 32],
+    pub timestamp: u32,
+    pub bits: u32,
+    // Total work of the chain ending in this block
+    pub chainwork: u128,
+}
+
+// Every known block, on the active chain or a competing branch. Blocks off the active
+// chain are kept here in full so a reorg can connect them.
+#[derive(Debug, Default)]
+pub struct BlockTree {
+    entries: HashMap<[u8; 32], BlockIndexEntry>,
+    side_blocks: HashMap<[u8; 32], Block>,
+    invalid: HashSet<[u8; 32]>,
+}
+
+impl BlockTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, hash: &[u8; 32]) -> Option<&BlockIndexEntry> {
+        self.entries.get(hash)
+    }
+
+    pub fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    // Index `block` on top of its parent, which must already be indexed unless the
+    // block is a genesis block
+    pub fn insert(&mut self, block: &Block) -> &BlockIndexEntry {
+        let parent_work = self
+            .entries
+            .get(&block.header.previous_hash)
+            .map_or(0, |parent| parent.chainwork);
+        let work = block.header.target().map_or(0, |target| target.work());
+
+        self.entries.entry(block.hash()).or_insert(BlockIndexEntry {
+            height: block.height,
+            previous_hash: block.header.previous_hash,
+            timestamp: block.header.timestamp,
+            bits: block.header.bits,
+            chainwork: parent_work.saturating_add(work),
+        })
+    }
+
+    // Median timestamp of `hash` and its ancestors, which a child block must exceed
+    pub fn median_time_past(&self, hash: &[u8; 32]) -> u32 {
+        let mut timestamps = Vec::new();
+        let mut current = self.entries.get(hash);
+        while let Some(entry) = current
+            && timestamps.len() < MEDIAN_TIME_SPAN
+        {
+            timestamps.push(entry.timestamp);
+            current = self.entries.get(&entry.previous_hash);
+        }
+        median_time_past(&timestamps)
+    }
+
+    // Median time past of the block at `height` on the branch ending in `hash`
+    pub fn ancestor_median_time_past(&self, hash: &[u8; 32], height: u32) -> u32 {
+        let mut hash = *hash;
+        while let Some(entry) = self.entries.get(&hash)
+            && entry.height > height
+        {
+            hash = entry.previous_hash;
+        }
+        self.median_time_past(&hash)
+    }
+
+    // The block at `height` on the branch ending in `hash`
+    pub fn ancestor(&self, hash: &[u8; 32], height: u32) -> Option<&BlockIndexEntry> {
+        let mut entry = self.entries.get(hash)?;
+        while entry.height > height {
+            entry = self.entries.get(&entry.previous_hash)?;
+        }
+        (entry.height == height).then_some(entry)
+    }
+
+    pub fn add_side_block(&mut self, block: Block) {
+        self.side_blocks.insert(block.hash(), block);
+    }
+
+    pub fn take_side_block(&mut self, hash: &[u8; 32]) -> Option<Block> {
+        self.side_blocks.remove(hash)
+    }
+
+    // A block whose transactions failed to connect. It and its descendants are never
+    // connected again.
+    pub fn mark_invalid(&mut self, hash: &[u8; 32]) {
+        self.side_blocks.remove(hash);
+        self.invalid.insert(*hash);
+    }
+
+    pub fn is_invalid(&self, hash: &[u8; 32]) -> bool {
+        self.invalid.contains(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{COIN, Transaction};
+
+    #[test]
+    fn test_chainwork_and_median_time_accumulate() {
+        let mut tree = BlockTree::new();
+        let mut previous_hash = [0; 32];
+        let mut third = [0; 32];
+
+        for height in 0..15u32 {
+            let coinbase = Transaction::coinbase(height, 0, "miner", COIN);
+            let mut block = Block::new(1, previous_hash, vec![coinbase], 0x207fffff, height);
+            block.header.timestamp = 1000 + height;
+            previous_hash = block.hash();
+            if height == 3 {
+                third = previous_hash;
+            }
+
+            let entry = tree.insert(&block);
+            // 0x207fffff is half the hash space, so each block is worth two hashes
+            assert_eq!(entry.chainwork, 2 * (height as u128 + 1));
+        }
+
+        // The last eleven timestamps are 1004..=1014
+        assert_eq!(tree.median_time_past(&previous_hash), 1009);
+        assert_eq!(tree.median_time_past(&[0; 32]), 0);
+
+        assert_eq!(tree.ancestor(&previous_hash, 3), tree.get(&third));
+        assert_eq!(tree.ancestor(&previous_hash, 14), tree.get(&previous_hash));
+        assert_eq!(tree.ancestor(&third, 4), None);
+    }
+}
@@ -0,0 +1,447 @@
+use crate::script::{ScriptError, TransactionChecker, verify_script};
+use crate::wallet;
+use crate::{Block, MAX_MONEY, OutPoint, Transaction, TxOut, hash_to_hex};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// An unspent output and where it was created
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtxoEntry {
+    pub output: TxOut,
+    pub height: u32,
+    pub is_coinbase: bool,
+}
+
+impl UtxoEntry {
+    // Coinbase outputs can only be spent `maturity` blocks after the one creating them
+    pub fn is_mature(&self, spend_height: u32, maturity: u32) -> bool {
+        !self.is_coinbase || spend_height.saturating_sub(self.height) >= maturity
+    }
+}
+
+// Set of all unspent transaction outputs, updated as blocks connect
+#[derive(Debug, Clone, Default)]
+pub struct UtxoSet {
+    entries: HashMap<OutPoint, UtxoEntry>,
+}
+
+// What connecting a block changed, so it can be disconnected again in a reorg
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockUndo {
+    pub fees: u64,
+    // Entries the block spent, which disconnecting restores
+    pub spent: Vec<(OutPoint, UtxoEntry)>,
+}
+
+// Outputs a batch of transactions spends and creates, checked but not yet applied
+struct UtxoChanges {
+    spent: HashSet<OutPoint>,
+    created: HashMap<OutPoint, UtxoEntry>,
+    fees: u64,
+}
+
+impl UtxoSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, outpoint: &OutPoint) -> Option<&UtxoEntry> {
+        self.entries.get(outpoint)
+    }
+
+    pub fn contains(&self, outpoint: &OutPoint) -> bool {
+        self.entries.contains_key(outpoint)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&OutPoint, &UtxoEntry)> {
+        self.entries.iter()
+    }
+
+    // Unspent outputs locked to `address`
+    pub fn outputs_for<'a>(
+        &'a self,
+        address: &'a str,
+    ) -> impl Iterator<Item = (&'a OutPoint, &'a UtxoEntry)> + 'a {
+        let script_pubkey = wallet::script_pubkey(address);
+        self.entries
+            .iter()
+            .filter(move |(_, entry)| entry.output.script_pubkey == script_pubkey)
+    }
+
+    pub fn balance(&self, address: &str) -> u64 {
+        self.outputs_for(address)
+            .map(|(_, entry)| entry.output.value)
+            .sum()
+    }
+
+    // Total fees paid by `transactions` if they were applied in order on top of this set
+    // in a block at `height`
+    pub fn fees(
+        &self,
+        transactions: &[Transaction],
+        height: u32,
+        maturity: u32,
+    ) -> Result<u64, UtxoError> {
+        self.check(transactions, height, maturity)
+            .map(|changes| changes.fees)
+    }
+
+    // Spend the inputs and add the outputs of every transaction in the block, where
+    // coinbase outputs take `maturity` blocks to become spendable. Nothing changes if
+    // any transaction is invalid.
+    pub fn connect_block(&mut self, block: &Block, maturity: u32) -> Result<BlockUndo, UtxoError> {
+        let changes = self.check(&block.transactions, block.height, maturity)?;
+
+        let spent = changes
+            .spent
+            .iter()
+            .map(|outpoint| (*outpoint, self.entries.remove(outpoint).unwrap()))
+            .collect();
+        self.entries.extend(changes.created);
+
+        Ok(BlockUndo {
+            fees: changes.fees,
+            spent,
+        })
+    }
+
+    // Reverse connect_block: drop the block's outputs and restore what it spent.
+    // `block` must be the last block connected.
+    pub fn disconnect_block(&mut self, block: &Block, undo: &BlockUndo) {
+        for tx in &block.transactions {
+            let txid = tx.txid();
+            for vout in 0..tx.outputs.len() {
+                self.entries.remove(&OutPoint::new(txid, vout as u32));
+            }
+        }
+        self.entries.extend(undo.spent.iter().cloned());
+    }
+
+    fn check(
+        &self,
+        transactions: &[Transaction],
+        height: u32,
+        maturity: u32,
+    ) -> Result<UtxoChanges, UtxoError> {
+        let mut changes = UtxoChanges {
+            spent: HashSet::new(),
+            created: HashMap::new(),
+            fees: 0,
+        };
+
+        for tx in transactions {
+            let txid = tx.txid();
+            if tx.inputs.is_empty() {
+                return Err(UtxoError::NoInputs { txid });
+            }
+            if tx.outputs.is_empty() {
+                return Err(UtxoError::NoOutputs { txid });
+            }
+
+            if !tx.is_coinbase() {
+                let mut input_value = 0u64;
+                for (index, input) in tx.inputs.iter().enumerate() {
+                    let outpoint = input.previous_output;
+                    let entry = changes
+                        .created
+                        .get(&outpoint)
+                        .or_else(|| self.entries.get(&outpoint))
+                        .ok_or(UtxoError::MissingInput { txid, outpoint })?;
+                    if !entry.is_mature(height, maturity) {
+                        return Err(UtxoError::ImmatureCoinbase { txid, outpoint });
+                    }
+                    if !changes.spent.insert(outpoint) {
+                        return Err(UtxoError::DoubleSpend { txid, outpoint });
+                    }
+                    let checker = TransactionChecker::new(tx, index);
+                    verify_script(&input.script_sig, &entry.output.script_pubkey, &checker)
+                        .map_err(|error| UtxoError::ScriptFailed {
+                            txid,
+                            input: index,
+                            error,
+                        })?;
+                    input_value = input_value
+                        .checked_add(entry.output.value)
+                        .filter(|value| *value <= MAX_MONEY)
+                        .ok_or(UtxoError::ValueOutOfRange { txid })?;
+                }
+
+                let output_value = tx
+                    .total_output()
+                    .ok_or(UtxoError::ValueOutOfRange { txid })?;
+                if output_value > input_value {
+                    return Err(UtxoError::OutputsExceedInputs {
+                        txid,
+                        input_value,
+                        output_value,
+                    });
+                }
+                changes.fees = changes
+                    .fees
+                    .checked_add(input_value - output_value)
+                    .filter(|fees| *fees <= MAX_MONEY)
+                    .ok_or(UtxoError::ValueOutOfRange { txid })?;
+            }
+
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                let outpoint = OutPoint::new(txid, vout as u32);
+                if self.entries.contains_key(&outpoint) || changes.created.contains_key(&outpoint) {
+                    return Err(UtxoError::DuplicateTransaction { txid });
+                }
+                changes.created.insert(
+                    outpoint,
+                    UtxoEntry {
+                        output: output.clone(),
+                        height,
+                        is_coinbase: tx.is_coinbase(),
+                    },
+                );
+            }
+        }
+
+        // Outputs created and spent within the same batch never reach the set
+        changes
+            .spent
+            .retain(|outpoint| changes.created.remove(outpoint).is_none());
+
+        Ok(changes)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum UtxoError {
+    NoInputs {
+        txid: [u8; 32],
+    },
+    NoOutputs {
+        txid: [u8; 32],
+    },
+    MissingInput {
+        txid: [u8; 32],
+        outpoint: OutPoint,
+    },
+    DoubleSpend {
+        txid: [u8; 32],
+        outpoint: OutPoint,
+    },
+    OutputsExceedInputs {
+        txid: [u8; 32],
+        input_value: u64,
+        output_value: u64,
+    },
+    DuplicateTransaction {
+        txid: [u8; 32],
+    },
+    // An output, the outputs' total, the inputs' total or the fees so far are above
+    // MAX_MONEY
+    ValueOutOfRange {
+        txid: [u8; 32],
+    },
+    ImmatureCoinbase {
+        txid: [u8; 32],
+        outpoint: OutPoint,
+    },
+    ScriptFailed {
+        txid: [u8; 32],
+        input: usize,
+        error: ScriptError,
+    },
+}
+
+impl fmt::Display for UtxoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UtxoError::NoInputs { txid } => {
+                write!(f, "Transaction {} has no inputs", hash_to_hex(txid))
+            }
+            UtxoError::NoOutputs { txid } => {
+                write!(f, "Transaction {} has no outputs", hash_to_hex(txid))
+            }
+            UtxoError::MissingInput { txid, outpoint } => write!(
+                f,
+                "Transaction {} spends missing or spent output {}",
+                hash_to_hex(txid),
+                outpoint
+            ),
+            UtxoError::DoubleSpend { txid, outpoint } => write!(
+                f,
+                "Transaction {} spends output {} twice",
+                hash_to_hex(txid),
+                outpoint
+            ),
+            UtxoError::OutputsExceedInputs {
+                txid,
+                input_value,
+                output_value,
+            } => write!(
+                f,
+                "Transaction {} spends {} satoshis but only has {}",
+                hash_to_hex(txid),
+                output_value,
+                input_value
+            ),
+            UtxoError::DuplicateTransaction { txid } => {
+                write!(f, "Transaction {} already exists", hash_to_hex(txid))
+            }
+            UtxoError::ValueOutOfRange { txid } => write!(
+                f,
+                "Transaction {} has values beyond the 21 million coin limit",
+                hash_to_hex(txid)
+            ),
+            UtxoError::ImmatureCoinbase { txid, outpoint } => write!(
+                f,
+                "Transaction {} spends coinbase output {} before it matures",
+                hash_to_hex(txid),
+                outpoint
+            ),
+            UtxoError::ScriptFailed { txid, input, error } => write!(
+                f,
+                "Transaction {} input {} fails its script: {}",
+                hash_to_hex(txid),
+                input,
+                error
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UtxoError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Transaction, TxIn};
+
+    fn funded_set() -> (UtxoSet, OutPoint) {
+        let coinbase = Transaction::coinbase(0, 0, "alice", 50);
+        let outpoint = OutPoint::new(coinbase.txid(), 0);
+        let mut utxos = UtxoSet::new();
+        utxos
+            .connect_block(&Block::new(1, [0; 32], vec![coinbase], 0x207fffff, 0), 0)
+            .unwrap();
+        (utxos, outpoint)
+    }
+
+    // A transaction spending alice's `outpoint`, signed with her wallet key
+    fn alice_spends(outpoint: OutPoint, outputs: Vec<TxOut>) -> Transaction {
+        let mut tx = Transaction::new(vec![TxIn::new(outpoint)], outputs);
+        wallet::sign_inputs(&mut tx, "alice");
+        tx
+    }
+
+    #[test]
+    fn test_spend_with_change() {
+        let (mut utxos, outpoint) = funded_set();
+        let payment = alice_spends(
+            outpoint,
+            vec![TxOut::to_address(30, "bob"), TxOut::to_address(15, "alice")],
+        );
+        let coinbase = Transaction::coinbase(1, 0, "miner", 5);
+        let block = Block::new(1, [0; 32], vec![coinbase, payment], 0x207fffff, 1);
+
+        let before = utxos.clone();
+        let undo = utxos.connect_block(&block, 0).unwrap();
+        assert_eq!(undo.fees, 5);
+        assert!(!utxos.contains(&outpoint));
+        assert_eq!(utxos.balance("alice"), 15);
+        assert_eq!(utxos.balance("bob"), 30);
+        assert_eq!(utxos.balance("miner"), 5);
+
+        utxos.disconnect_block(&block, &undo);
+        assert_eq!(utxos.entries, before.entries);
+    }
+
+    #[test]
+    fn test_double_spend_is_rejected() {
+        let (mut utxos, outpoint) = funded_set();
+        let first = alice_spends(outpoint, vec![TxOut::to_address(50, "bob")]);
+        let second = alice_spends(outpoint, vec![TxOut::to_address(50, "carol")]);
+        let block = Block::new(
+            1,
+            [0; 32],
+            vec![Transaction::coinbase(1, 0, "miner", 0), first, second],
+            0x207fffff,
+            1,
+        );
+
+        assert!(matches!(
+            utxos.connect_block(&block, 0),
+            Err(UtxoError::DoubleSpend { .. })
+        ));
+        // A rejected block leaves the set untouched
+        assert!(utxos.contains(&outpoint));
+        assert_eq!(utxos.len(), 1);
+    }
+
+    #[test]
+    fn test_overspend_is_rejected() {
+        let (utxos, outpoint) = funded_set();
+        let tx = alice_spends(outpoint, vec![TxOut::to_address(51, "bob")]);
+
+        assert!(matches!(
+            utxos.fees(&[tx], 1, 0),
+            Err(UtxoError::OutputsExceedInputs {
+                input_value: 50,
+                output_value: 51,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_overflowing_outputs_are_rejected() {
+        let (utxos, outpoint) = funded_set();
+        // Wrapping the sum of these would total 1 satoshi
+        let tx = alice_spends(
+            outpoint,
+            vec![
+                TxOut::to_address(u64::MAX, "bob"),
+                TxOut::to_address(2, "bob"),
+            ],
+        );
+        assert_eq!(tx.total_output(), None);
+        let txid = tx.txid();
+        assert_eq!(
+            utxos.fees(&[tx], 1, 0),
+            Err(UtxoError::ValueOutOfRange { txid })
+        );
+
+        // Each output may be in range while the total isn't
+        let tx = alice_spends(
+            outpoint,
+            vec![
+                TxOut::to_address(MAX_MONEY, "bob"),
+                TxOut::to_address(1, "bob"),
+            ],
+        );
+        let txid = tx.txid();
+        assert_eq!(
+            utxos.fees(&[tx], 1, 0),
+            Err(UtxoError::ValueOutOfRange { txid })
+        );
+    }
+
+    #[test]
+    fn test_coinbase_must_mature() {
+        let (utxos, outpoint) = funded_set();
+        let tx = alice_spends(outpoint, vec![TxOut::to_address(50, "bob")]);
+
+        // Created at height 0, so with a maturity of 100 it's spendable from height 100
+        assert_eq!(
+            utxos.fees(std::slice::from_ref(&tx), 99, 100),
+            Err(UtxoError::ImmatureCoinbase {
+                txid: tx.txid(),
+                outpoint
+            })
+        );
+        assert_eq!(utxos.fees(&[tx], 100, 100), Ok(0));
+    }
+}
@@ -0,0 +1,50 @@
+use crate::Transaction;
+use crate::script::{self, Builder, SIGHASH_ALL};
+use k256::ecdsa::SigningKey;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+// The simulator pays and spends on behalf of every address it knows, so each
+// address's key is derived from the address itself. Outputs are locked to the hash of
+// that key's public key (pay-to-public-key-hash) and only its signature spends them.
+pub fn key_for(address: &str) -> SigningKey {
+    let seed = Sha256::digest(address.as_bytes());
+    // Only a seed of zero or at least the curve order is rejected, which no address
+    // hashes to in practice
+    SigningKey::from_slice(&seed).unwrap()
+}
+
+// Compressed SEC1 public key of `address`
+pub fn public_key(address: &str) -> Vec<u8> {
+    key_for(address).verifying_key().to_sec1_bytes().to_vec()
+}
+
+// Locking script of outputs paying `address`. Deriving the key takes an elliptic
+// curve multiplication, and balances and coinbases need the script again and again,
+// so each thread remembers the ones it has built.
+pub fn script_pubkey(address: &str) -> Vec<u8> {
+    thread_local! {
+        static SCRIPTS: RefCell<HashMap<String, Vec<u8>>> = RefCell::default();
+    }
+    SCRIPTS.with_borrow_mut(|scripts| {
+        scripts
+            .entry(address.to_string())
+            .or_insert_with(|| script::p2pkh(&script::hash160(&public_key(address))))
+            .clone()
+    })
+}
+
+// Sign every input of `transaction` as spending an output paid to `address`
+pub fn sign_inputs(transaction: &mut Transaction, address: &str) {
+    let key = key_for(address);
+    let pubkey = public_key(address);
+    let script_code = script_pubkey(address);
+    for input in 0..transaction.inputs.len() {
+        let signature = script::sign(transaction, input, &script_code, &key, SIGHASH_ALL);
+        transaction.inputs[input].script_sig = Builder::new()
+            .push_slice(&signature)
+            .push_slice(&pubkey)
+            .into_bytes();
+    }
+}